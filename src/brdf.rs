@@ -0,0 +1,152 @@
+use std::f32::consts::PI;
+use glam::Vec3;
+use crate::color::Color;
+use crate::material::Material;
+
+// Metallic-roughness BRDF: a Lambert diffuse lobe under a GGX specular lobe with height-correlated Smith masking.
+// Mirrors brdf.wgsl line for line so the CPU and GPU renderers converge to the same image.
+
+/// Keeps the GGX distribution finite for perfectly smooth surfaces.
+const MIN_ALPHA: f32 = 1.0e-3;
+
+pub struct BrdfSample {
+    pub direction: Vec3,
+    /// BRDF times cosine over the pdf, what the path throughput gets multiplied by.
+    pub weight: Color,
+    pub pdf: f32,
+}
+
+/// BRDF value and the pdf `sample` picks `light` with. `normal` has to face the viewer.
+pub fn evaluate(material: &Material, normal: Vec3, view: Vec3, light: Vec3) -> (Color, f32) {
+    let n_dot_l = normal.dot(light);
+    let n_dot_v = normal.dot(view);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+        return (Color::black(), 0.0);
+    }
+
+    let alpha = alpha(material.roughness);
+    let half = (view + light).normalize();
+    let n_dot_h = normal.dot(half).max(0.0);
+    let v_dot_h = view.dot(half).max(0.0);
+
+    let f0 = specular_color(material);
+    let fresnel = fresnel_schlick(f0, v_dot_h);
+    let d = ggx_d(n_dot_h, alpha);
+    let g = smith_g2(n_dot_v, n_dot_l, alpha);
+
+    let specular = fresnel * (d * g / (4.0 * n_dot_v * n_dot_l));
+    let diffuse = (Color::white() - fresnel) * diffuse_color(material) * (1.0 / PI);
+
+    let p_specular = specular_probability(material, n_dot_v);
+    let pdf = p_specular * vndf_pdf(n_dot_v, n_dot_h, alpha) + (1.0 - p_specular) * n_dot_l / PI;
+    (specular + diffuse, pdf)
+}
+
+/// Picks the specular or the diffuse lobe, then a direction from it. `u` are three uniform numbers in [0, 1): the
+/// first selects the lobe, the other two the direction. `None` when the direction ends up below the surface.
+pub fn sample(material: &Material, normal: Vec3, view: Vec3, u: Vec3) -> Option<BrdfSample> {
+    let n_dot_v = normal.dot(view);
+    if n_dot_v <= 0.0 {
+        return None;
+    }
+
+    let (tangent, bitangent) = tangent_frame(normal);
+    let to_local = |v: Vec3| Vec3::new(v.dot(tangent), v.dot(bitangent), v.dot(normal));
+    let to_world = |v: Vec3| tangent * v.x + bitangent * v.y + normal * v.z;
+
+    let direction = if u.x < specular_probability(material, n_dot_v) {
+        let half = to_world(sample_vndf(to_local(view), alpha(material.roughness), u.y, u.z));
+        2.0 * view.dot(half) * half - view
+    } else {
+        to_world(cosine_hemisphere(u.y, u.z))
+    };
+
+    let (value, pdf) = evaluate(material, normal, view, direction);
+    if pdf <= 0.0 {
+        return None;
+    }
+    Some(BrdfSample { direction, weight: value * (normal.dot(direction) / pdf), pdf })
+}
+
+fn alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(MIN_ALPHA)
+}
+
+fn specular_color(material: &Material) -> Color {
+    crate::color::lerp(&Color::new(0.04, 0.04, 0.04), &material.albedo, material.metallic)
+}
+
+fn diffuse_color(material: &Material) -> Color {
+    material.albedo * (1.0 - material.metallic)
+}
+
+fn fresnel_schlick(f0: Color, cos_theta: f32) -> Color {
+    f0 + (Color::white() - f0) * (1.0 - cos_theta).powi(5)
+}
+
+/// Chance of sampling the specular lobe, from how much each lobe reflects head on from this angle.
+fn specular_probability(material: &Material, n_dot_v: f32) -> f32 {
+    let fresnel = fresnel_schlick(specular_color(material), n_dot_v);
+    let specular = fresnel.luminance();
+    let diffuse = ((Color::white() - fresnel) * diffuse_color(material)).luminance();
+    if specular + diffuse <= 0.0 { 1.0 } else { specular / (specular + diffuse) }
+}
+
+fn ggx_d(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(cos_theta, alpha))
+}
+
+fn smith_g2(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(n_dot_v, alpha) + smith_lambda(n_dot_l, alpha))
+}
+
+/// Density of the reflected direction when the half vector comes from the visible normals,
+/// G1(v) D(h) / (4 n.v) once the reflection Jacobian is folded in.
+fn vndf_pdf(n_dot_v: f32, n_dot_h: f32, alpha: f32) -> f32 {
+    smith_g1(n_dot_v, alpha) * ggx_d(n_dot_h, alpha) / (4.0 * n_dot_v)
+}
+
+/// Heitz 2018, "Sampling the GGX Distribution of Visible Normals". `view` and the result are in the local frame with
+/// the normal along +Z.
+fn sample_vndf(view: Vec3, alpha: f32, u1: f32, u2: f32) -> Vec3 {
+    let vh = Vec3::new(alpha * view.x, alpha * view.y, view.z).normalize();
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt() } else { Vec3::X };
+    let t2 = vh.cross(t1);
+
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize()
+}
+
+fn cosine_hemisphere(u1: f32, u2: f32) -> Vec3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+}
+
+/// Duff et al. 2017, "Building an Orthonormal Basis, Revisited".
+pub fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let sign = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    (Vec3::new(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x),
+     Vec3::new(b, sign + normal.y * normal.y * a, -normal.y))
+}
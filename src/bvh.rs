@@ -2,9 +2,9 @@ use crate::gpu_types::GpuBVHNode;
 use crate::model::Mesh;
 use crate::objects::{Hittable, Triangle};
 use glam::Vec3;
-use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy)]
 pub struct AABB {
     pub(crate) min: Vec3,
     pub(crate) max: Vec3,
@@ -33,16 +33,23 @@ impl AABB {
             (v000, v100), (v001, v101), (v010, v110), (v011, v111),
         ]
     }
-    pub(crate) fn get_biggest_axis(&self) -> Axis {
-        let x_length = self.max.x - self.min.x;
-        let y_length = self.max.y - self.min.y;
-        let z_length = self.max.z - self.min.z;
-        if x_length > y_length && x_length > z_length { Axis::X } else if y_length > z_length { Axis::Y } else { Axis::Z }
+    /// Inverted box that any `grow` or `union` replaces.
+    pub(crate) fn empty() -> Self {
+        AABB { min: Vec3::splat(f32::MAX), max: Vec3::splat(f32::MIN) }
+    }
+    pub(crate) fn grow(&self, point: Vec3) -> Self {
+        AABB { min: self.min.min(point), max: self.max.max(point) }
+    }
+    pub(crate) fn union(&self, other: &AABB) -> Self {
+        AABB { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+    pub(crate) fn contains(&self, other: &AABB) -> bool {
+        self.min.cmple(other.min).all() && self.max.cmpge(other.max).all()
+    }
+    pub(crate) fn surface_area(&self) -> f32 {
+        let size = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
     }
-}
-
-pub enum Axis {
-    X, Y, Z
 }
 
 #[derive(Debug, Clone)]
@@ -52,102 +59,341 @@ pub enum BVHNode {
         left: Box<BVHNode>,
         right: Box<BVHNode>,
     },
+    /// Covers `BVH::indices[first..first + count]`.
     LeafNode {
         aabb: AABB,
-        objects: Arc<Mesh>,
+        first: u32,
+        count: u32,
     },
 }
 
-pub fn construct_bvh(mesh: &Mesh) -> BVHNode {
-    let prims = mesh.get_triangles();
-    let aabb = mesh.to_aabb();
+/// Bounding volume hierarchy over a triangle list. Leaves refer to triangles through `indices`, so the triangles
+/// themselves stay in the order `Mesh::local_triangles` returns them.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone)]
+pub struct BVH {
+    pub root: BVHNode,
+    pub indices: Vec<u32>,
+    pub max_leaf_size: u32,
+}
 
-    if prims.len() <= 4 {
-        return BVHNode::LeafNode {
-            aabb,
-            objects: Arc::new(mesh.clone()),
-        };
-    }
+pub const DEFAULT_LEAF_SIZE: u32 = 4;
+/// `traverse_blas` in hit.wgsl stops after this many primitives in one leaf.
+pub const MAX_LEAF_SIZE: u32 = 100;
 
-    let (left, right) = split_prims(prims, aabb);
-    BVHNode::BVHNode {
-        aabb: mesh.to_aabb(),
-        left: Box::new(construct_bvh(&to_mesh(left))),
-        right: Box::new(construct_bvh(&to_mesh(right)))
+const SAH_BINS: usize = 16;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+impl BVH {
+    /// Expected cost of tracing a random ray through the tree, relative to testing a single triangle.
+    pub fn sah_cost(&self) -> f32 {
+        let root_area = self.root.aabb().surface_area();
+        if root_area <= 0.0 {
+            return 0.0;
+        }
+        node_cost(&self.root) / root_area
     }
-}
 
-fn split_prims(prims: Vec<Triangle>, aabb: AABB) -> (Vec<Triangle>, Vec<Triangle>) {
-    let mut left = Vec::new();
-    let mut right = Vec::new();
-    let mut center = Vec3::ZERO;
+    /// Checks that the leaves cover every triangle exactly once and that every box contains what is below it.
+    pub fn validate(&self, triangles: &[Triangle]) -> Result<(), String> {
+        let mut seen = vec![false; triangles.len()];
+        for &i in &self.indices {
+            match seen.get_mut(i as usize) {
+                None => return Err(format!("index {} is out of range for {} triangles", i, triangles.len())),
+                Some(true) => return Err(format!("triangle {} is referenced twice", i)),
+                Some(flag) => *flag = true,
+            }
+        }
+        if let Some(missing) = seen.iter().position(|&flag| !flag) {
+            return Err(format!("triangle {} is not referenced by any leaf", missing));
+        }
 
-    for prim in &prims {
-        center += prim.center()
+        let covered = validate_node(&self.root, &self.indices, triangles, 0)?;
+        if covered as usize != self.indices.len() {
+            return Err(format!("leaves cover {} of {} indices", covered, self.indices.len()));
+        }
+        Ok(())
     }
-    center /= prims.len() as f32;
 
-    let axis = aabb.get_biggest_axis();
-    for prim in prims {
-        match axis {
-            Axis::X => { if prim.center().x < center.x { left.push(prim); } else { right.push(prim); }}
-            Axis::Y => { if prim.center().y < center.y { left.push(prim); } else { right.push(prim); }}
-            Axis::Z => { if prim.center().z < center.z { left.push(prim); } else { right.push(prim); }}
+    pub fn node_count(&self) -> usize {
+        fn count(node: &BVHNode) -> usize {
+            match node {
+                BVHNode::LeafNode { .. } => 1,
+                BVHNode::BVHNode { left, right, .. } => 1 + count(left) + count(right),
+            }
         }
+        count(&self.root)
     }
-
-    (left, right)
 }
 
-fn to_mesh(prims: Vec<Triangle>) -> Mesh {
-    let mut mesh = Mesh::new();
-    for tri in prims {
-        mesh.append_tri(tri);
+impl BVHNode {
+    pub fn aabb(&self) -> &AABB {
+        match self {
+            BVHNode::BVHNode { aabb, .. } | BVHNode::LeafNode { aabb, .. } => aabb,
+        }
     }
-    mesh
 }
 
-pub fn traverse_leaf_nodes<F>(node: &BVHNode, f: &mut F) where F: FnMut(&AABB, &Arc<Mesh>) {
+/// Returns the end of the index range covered by `node`, which has to start at `next`.
+fn validate_node(node: &BVHNode, indices: &[u32], triangles: &[Triangle], next: u32) -> Result<u32, String> {
     match node {
-        BVHNode::LeafNode { aabb, objects } => {
-            f(aabb, objects);
+        BVHNode::LeafNode { aabb, first, count } => {
+            if *first != next {
+                return Err(format!("leaf starts at index {} but {} was expected", first, next));
+            }
+            let end = first + count;
+            let leaf_indices = indices.get(*first as usize..end as usize)
+                .ok_or_else(|| format!("leaf range {}..{} is out of bounds", first, end))?;
+            for &i in leaf_indices {
+                if !aabb.contains(&triangles[i as usize].to_aabb()) {
+                    return Err(format!("leaf bounds do not contain triangle {}", i));
+                }
+            }
+            Ok(end)
         }
-        BVHNode::BVHNode { aabb: _aabb, left, right } => {
-            traverse_leaf_nodes(left, f);
-            traverse_leaf_nodes(right, f);
+        BVHNode::BVHNode { aabb, left, right } => {
+            if !aabb.contains(left.aabb()) || !aabb.contains(right.aabb()) {
+                return Err("node bounds do not contain a child".to_string());
+            }
+            let middle = validate_node(left, indices, triangles, next)?;
+            validate_node(right, indices, triangles, middle)
+        }
+    }
+}
+
+fn node_cost(node: &BVHNode) -> f32 {
+    match node {
+        BVHNode::LeafNode { aabb, count, .. } => aabb.surface_area() * INTERSECTION_COST * *count as f32,
+        BVHNode::BVHNode { aabb, left, right } => aabb.surface_area() * TRAVERSAL_COST + node_cost(left) + node_cost(right),
+    }
+}
+
+/// Builds the mesh's BVH in object space, over `Mesh::local_triangles`.
+pub fn construct_bvh(mesh: &Mesh, max_leaf_size: u32) -> BVH {
+    let triangles = mesh.local_triangles();
+    let bvh = build_bvh(&triangles, max_leaf_size);
+
+    if cfg!(debug_assertions) && let Err(err) = bvh.validate(&triangles) {
+        panic!("invalid BVH: {}", err);
+    }
+    bvh
+}
+
+/// Binned SAH build: every split tries `SAH_BINS` planes per axis and keeps the cheapest, nodes become leaves once
+/// they are at most `max_leaf_size` triangles and splitting would not pay off.
+pub fn build_bvh(triangles: &[Triangle], max_leaf_size: u32) -> BVH {
+    let bounds: Vec<AABB> = triangles.iter().map(|tri| tri.to_aabb()).collect();
+    let centroids: Vec<Vec3> = triangles.iter().map(Triangle::center).collect();
+    build_bvh_over_bounds(&bounds, &centroids, max_leaf_size)
+}
+
+/// Same builder for anything with a box, like the instances of the top level.
+pub fn build_bvh_over_bounds(bounds: &[AABB], centroids: &[Vec3], max_leaf_size: u32) -> BVH {
+    let mut indices: Vec<u32> = (0..bounds.len() as u32).collect();
+
+    let max_leaf_size = max_leaf_size.max(1);
+    let root = build_node(bounds, centroids, &mut indices, 0, max_leaf_size as usize);
+    BVH { root, indices, max_leaf_size }
+}
+
+fn build_node(bounds: &[AABB], centroids: &[Vec3], indices: &mut [u32], first: usize, max_leaf_size: usize) -> BVHNode {
+    let aabb = indices.iter().fold(AABB::empty(), |aabb, &i| aabb.union(&bounds[i as usize]));
+    let count = indices.len();
+    let leaf = |aabb: AABB| BVHNode::LeafNode { aabb, first: first as u32, count: count as u32 };
+
+    if count <= 1 {
+        return leaf(aabb);
+    }
+
+    let split = find_sah_split(bounds, centroids, indices);
+    let leaf_cost = INTERSECTION_COST * count as f32;
+    let mid = match split {
+        Some((axis, position, cost)) => {
+            let split_cost = TRAVERSAL_COST + cost / aabb.surface_area().max(f32::MIN_POSITIVE);
+            if count <= max_leaf_size && leaf_cost <= split_cost {
+                return leaf(aabb);
+            }
+            partition(indices, |i| centroids[i as usize][axis] < position)
         }
+        None if count <= max_leaf_size => return leaf(aabb),
+        // all centroids coincide, any split is as good as another
+        None => count / 2,
+    };
+    let mid = if mid == 0 || mid == count { count / 2 } else { mid };
+
+    let (left, right) = indices.split_at_mut(mid);
+    BVHNode::BVHNode {
+        aabb,
+        left: Box::new(build_node(bounds, centroids, left, first, max_leaf_size)),
+        right: Box::new(build_node(bounds, centroids, right, first + mid, max_leaf_size)),
     }
 }
 
-fn vec3_to_hashable(v: Vec3) -> (u32, u32, u32) {
-    (v.x.to_bits(), v.y.to_bits(), v.z.to_bits())
+/// Returns the axis, plane position and unnormalised cost (area times count of both sides) of the best bin boundary.
+fn find_sah_split(bounds: &[AABB], centroids: &[Vec3], indices: &[u32]) -> Option<(usize, f32, f32)> {
+    let centroid_bounds = indices.iter().fold(AABB::empty(), |aabb, &i| aabb.grow(centroids[i as usize]));
+    let mut best: Option<(usize, f32, f32)> = None;
+
+    for axis in [0, 1, 2] {
+        let (min, max) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+        if max - min <= 0.0 {
+            continue;
+        }
+
+        let scale = SAH_BINS as f32 / (max - min);
+        let bin_of = |i: u32| (((centroids[i as usize][axis] - min) * scale) as usize).min(SAH_BINS - 1);
+
+        let mut bins = [(AABB::empty(), 0usize); SAH_BINS];
+        for &i in indices {
+            let bin = &mut bins[bin_of(i)];
+            bin.0 = bin.0.union(&bounds[i as usize]);
+            bin.1 += 1;
+        }
+
+        // sweep from the right first so the left sweep can evaluate every plane in one pass
+        let mut right_costs = [0.0; SAH_BINS];
+        let (mut right_aabb, mut right_count) = (AABB::empty(), 0);
+        for plane in (1..SAH_BINS).rev() {
+            right_aabb = right_aabb.union(&bins[plane].0);
+            right_count += bins[plane].1;
+            right_costs[plane] = right_aabb.surface_area() * right_count as f32;
+        }
+
+        let (mut left_aabb, mut left_count) = (AABB::empty(), 0);
+        for plane in 1..SAH_BINS {
+            left_aabb = left_aabb.union(&bins[plane - 1].0);
+            left_count += bins[plane - 1].1;
+            if left_count == 0 || left_count == indices.len() {
+                continue;
+            }
+
+            let cost = left_aabb.surface_area() * left_count as f32 + right_costs[plane];
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, min + plane as f32 / scale, cost));
+            }
+        }
+    }
+
+    best
 }
 
-pub fn flatten_bvh_for_gpu(bvh: &BVHNode, triangles: &[Triangle]) -> (Vec<GpuBVHNode>, Vec<u32>) {
-    let mut nodes = Vec::new();
-    let mut triangle_indices = Vec::new();
+/// Moves every index matching `is_left` to the front and returns how many there are.
+fn partition(indices: &mut [u32], is_left: impl Fn(u32) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..indices.len() {
+        if is_left(indices[i]) {
+            indices.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
 
-    let mut tri_to_idx = std::collections::HashMap::new();
-    for (idx, tri) in triangles.iter().enumerate() {
-        let key = (
-            vec3_to_hashable(tri.v0()),
-            vec3_to_hashable(tri.v1()),
-            vec3_to_hashable(tri.v2())
-        );
-        tri_to_idx.insert(key, idx);
+pub fn traverse_leaf_nodes<F>(bvh: &BVH, f: &mut F) where F: FnMut(&AABB, &[u32]) {
+    fn visit<F>(node: &BVHNode, indices: &[u32], f: &mut F) where F: FnMut(&AABB, &[u32]) {
+        match node {
+            BVHNode::LeafNode { aabb, first, count } => {
+                f(aabb, &indices[*first as usize..(*first + *count) as usize]);
+            }
+            BVHNode::BVHNode { aabb: _aabb, left, right } => {
+                visit(left, indices, f);
+                visit(right, indices, f);
+            }
+        }
     }
+    visit(&bvh.root, &bvh.indices, f);
+}
+
+/// Leaf index entries carry the primitive type in their top two bits, the rest is the index into that type's buffer.
+pub const PRIMITIVE_TRIANGLE: u32 = 0;
+pub const PRIMITIVE_SPHERE: u32 = 1;
+pub const PRIMITIVE_PLANE: u32 = 2;
+const PRIMITIVE_SHIFT: u32 = 30;
+const PRIMITIVE_INDEX_MASK: u32 = (1 << PRIMITIVE_SHIFT) - 1;
 
-    flatten_node(bvh, &mut nodes, &mut triangle_indices, &tri_to_idx);
+pub fn primitive_ref(kind: u32, index: u32) -> u32 {
+    debug_assert!(index <= PRIMITIVE_INDEX_MASK, "primitive index {} does not fit next to its type", index);
+    (kind << PRIMITIVE_SHIFT) | index
+}
+
+/// Splits a leaf index entry into its primitive type and index.
+pub fn unpack_primitive_ref(entry: u32) -> (u32, u32) {
+    (entry >> PRIMITIVE_SHIFT, entry & PRIMITIVE_INDEX_MASK)
+}
 
+/// Where a flattened BVH lands in the scene-wide buffers, so several of them can share one node and index buffer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GpuBVHOffsets {
+    /// First triangle of the BVH in the triangle buffer.
+    pub triangle: u32,
+    /// Position of the BVH's root in the node buffer.
+    pub node: u32,
+    /// Position of the BVH's first leaf index in the index buffer.
+    pub index: u32,
+}
+
+/// Lays the tree out in depth-first order as the GPU expects it, with node and index references rebased by `offsets`.
+/// The root ends up at `offsets.node`.
+pub fn flatten_bvh_for_gpu(bvh: &BVH, offsets: GpuBVHOffsets) -> (Vec<GpuBVHNode>, Vec<u32>) {
+    let mut nodes = Vec::with_capacity(bvh.node_count());
+    flatten_node(&bvh.root, &mut nodes, offsets);
+
+    let triangle_indices = bvh.indices.iter().map(|&i| i + offsets.triangle).collect();
     (nodes, triangle_indices)
 }
-fn flatten_node(node: &BVHNode, nodes: &mut Vec<GpuBVHNode>, triangle_indices: &mut Vec<u32>,
-                tri_to_idx: &std::collections::HashMap<((u32, u32, u32), (u32, u32, u32), (u32, u32, u32)), usize>) -> u32 {
-    let node_index = nodes.len() as u32;
+
+/// Checks what the GPU will traverse: starting from `roots`, every primitive below `primitive_count` is reached
+/// exactly once and every node's box contains its children's boxes. `slot` maps a leaf index entry to its primitive.
+pub fn validate_flattened(nodes: &[GpuBVHNode], indices: &[u32], roots: &[u32], primitive_count: usize, slot: impl Fn(u32) -> usize) -> Result<(), String> {
+    let contains = |outer: &GpuBVHNode, inner: &GpuBVHNode| {
+        (0..3).all(|axis| outer.min[axis] <= inner.min[axis] && outer.max[axis] >= inner.max[axis])
+    };
+
+    let mut reached = vec![0u32; primitive_count];
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = roots.to_vec();
+
+    while let Some(node_index) = stack.pop() {
+        let node = nodes.get(node_index as usize).ok_or_else(|| format!("node {} is out of bounds", node_index))?;
+        if std::mem::replace(&mut visited[node_index as usize], true) {
+            return Err(format!("node {} is reachable twice", node_index));
+        }
+
+        if node.is_leaf == 1 {
+            let range = node.left_first as usize..(node.left_first + node.right_count) as usize;
+            let leaf = indices.get(range).ok_or_else(|| format!("leaf {} points past the index buffer", node_index))?;
+            for &entry in leaf {
+                let count = reached.get_mut(slot(entry))
+                    .ok_or_else(|| format!("leaf {} references missing primitive {:#x}", node_index, entry))?;
+                *count += 1;
+            }
+            continue;
+        }
+
+        for child in [node.left_first, node.right_count] {
+            let child_node = nodes.get(child as usize).ok_or_else(|| format!("node {} is out of bounds", child))?;
+            if !contains(node, child_node) {
+                return Err(format!("node {} does not contain its child {}", node_index, child));
+            }
+            stack.push(child);
+        }
+    }
+
+    match reached.iter().position(|&count| count != 1) {
+        Some(primitive) => Err(format!("primitive {} is reached {} times", primitive, reached[primitive])),
+        None => Ok(()),
+    }
+}
+
+fn flatten_node(node: &BVHNode, nodes: &mut Vec<GpuBVHNode>, offsets: GpuBVHOffsets) -> u32 {
+    let node_index = offsets.node + nodes.len() as u32;
+    let slot = nodes.len();
+    let aabb = node.aabb();
     nodes.push(GpuBVHNode {
-        min: [0.0; 3],
+        min: [aabb.min.x, aabb.min.y, aabb.min.z],
         _pad0: 0.0,
-        max: [0.0; 3],
+        max: [aabb.max.x, aabb.max.y, aabb.max.z],
         _pad1: 0.0,
         left_first: 0,
         right_count: 0,
@@ -156,48 +402,19 @@ fn flatten_node(node: &BVHNode, nodes: &mut Vec<GpuBVHNode>, triangle_indices: &
     });
 
     match node {
-        BVHNode::LeafNode { aabb, objects } => {
-            let first_tri = triangle_indices.len() as u32;
-            let triangles = objects.get_triangles();
-
-            for tri in &triangles {
-                let key = (
-                    vec3_to_hashable(tri.v0()),
-                    vec3_to_hashable(tri.v1()),
-                    vec3_to_hashable(tri.v2())
-                );
-                if let Some(&idx) = tri_to_idx.get(&key) {
-                    triangle_indices.push(idx as u32);
-                }
-            }
-
-            let tri_count = (triangle_indices.len() as u32) - first_tri;
-
-            nodes[node_index as usize] = GpuBVHNode {
-                min: [aabb.min.x, aabb.min.y, aabb.min.z],
-                _pad0: 0.0,
-                max: [aabb.max.x, aabb.max.y, aabb.max.z],
-                _pad1: 0.0,
-                left_first: first_tri,
-                right_count: tri_count,
-                is_leaf: 1,
-                _pad2: 0,
-            };
+        BVHNode::LeafNode { first, count, .. } => {
+            let gpu_node = &mut nodes[slot];
+            gpu_node.left_first = offsets.index + *first;
+            gpu_node.right_count = *count;
+            gpu_node.is_leaf = 1;
         }
-        BVHNode::BVHNode { aabb, left, right } => {
-            let left_index = flatten_node(left, nodes, triangle_indices, tri_to_idx);
-            let right_index = flatten_node(right, nodes, triangle_indices, tri_to_idx);
-
-            nodes[node_index as usize] = GpuBVHNode {
-                min: [aabb.min.x, aabb.min.y, aabb.min.z],
-                _pad0: 0.0,
-                max: [aabb.max.x, aabb.max.y, aabb.max.z],
-                _pad1: 0.0,
-                left_first: left_index,
-                right_count: right_index,
-                is_leaf: 0,
-                _pad2: 0,
-            };
+        BVHNode::BVHNode { left, right, .. } => {
+            let left_index = flatten_node(left, nodes, offsets);
+            let right_index = flatten_node(right, nodes, offsets);
+
+            let gpu_node = &mut nodes[slot];
+            gpu_node.left_first = left_index;
+            gpu_node.right_count = right_index;
         }
     }
 
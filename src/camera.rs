@@ -1,15 +1,331 @@
-use glam::Vec3;
+use std::f32::consts::{FRAC_PI_2, PI};
+use glam::{Mat3, Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::gpu_types::GpuCamera;
 use crate::ray::Ray;
 
+/// How the sub-pixel samples of a pixel are weighted against each other.
+/// Both renderers jitter every sample over the filter's footprint and accumulate the weight next to the colour.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PixelFilter {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    BlackmanHarris,
+}
+
+impl PixelFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "box" => Some(PixelFilter::Box),
+            "tent" => Some(PixelFilter::Tent),
+            "gaussian" => Some(PixelFilter::Gaussian),
+            "blackman-harris" => Some(PixelFilter::BlackmanHarris),
+            _ => None,
+        }
+    }
+
+    /// Half the width of the footprint in pixels.
+    pub fn radius(self) -> f32 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+            PixelFilter::BlackmanHarris => 1.5,
+        }
+    }
+
+    /// How much a sample `offset` pixels from the pixel centre counts, the same as `filter_weight` in raytracer.wgsl.
+    /// Offsets are spread uniformly over the footprint, so the filter shape only comes from this weight.
+    pub fn weight(self, offset: Vec2) -> f32 {
+        let r = self.radius();
+        let w = match self {
+            PixelFilter::Box => return 1.0,
+            PixelFilter::Tent => (1.0 - offset.abs() / r).max(Vec2::ZERO),
+            PixelFilter::Gaussian => {
+                // sigma 0.5, shifted down so it reaches zero at the edge of the footprint
+                let edge = (-2.0 * r * r).exp();
+                ((-2.0 * offset * offset).exp() - edge).max(Vec2::ZERO)
+            }
+            PixelFilter::BlackmanHarris => {
+                let t = 2.0 * PI * (offset / r * 0.5 + 0.5);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        };
+        w.x * w.y
+    }
+
+    /// Matches the FILTER_ constants in raytracer.wgsl.
+    fn gpu_id(self) -> u32 {
+        match self {
+            PixelFilter::Box => 0,
+            PixelFilter::Tent => 1,
+            PixelFilter::Gaussian => 2,
+            PixelFilter::BlackmanHarris => 3,
+        }
+    }
+}
+
+/// How directions map to the image. Fisheye is equidistant with `fov` spanning the image height, equirectangular covers
+/// the full sphere with longitude across and latitude down the image.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn as_u32(self) -> u32 {
+        match self {
+            Projection::Perspective => 0,
+            Projection::Orthographic => 1,
+            Projection::Fisheye => 2,
+            Projection::Equirectangular => 3,
+        }
+    }
+}
+
+/// A thin-lens camera, a pinhole while `aperture` is zero. Looks down its local -Z with +Y up, `roll` turns the image
+/// around the view direction on top of that.
+#[derive(Debug, Clone)]
 pub struct Camera {
     width: u32,
     height: u32,
-    ray: Ray
+    position: Vec3,
+    orientation: Quat,
+    /// World up, what yaw turns around and what `look_at` keeps the horizon level against.
+    up: Vec3,
+    projection: Projection,
+    /// Vertical field of view in degrees, for the perspective and fisheye projections.
+    fov: f32,
+    /// World space height of the view for the orthographic projection.
+    ortho_height: f32,
+    /// Radians around the view direction, positive tilts the camera counter-clockwise.
+    roll: f32,
+    /// Lens radius in world units.
+    aperture: f32,
+    /// Distance along the view direction that stays sharp.
+    focus_distance: f32,
+    /// Polygonal aperture for shaped bokeh, fewer than three blades is a round one.
+    blades: u32,
+    blade_rotation: f32,
+    /// Part of the motion between the start and end poses the shutter is open for, both in [0, 1].
+    shutter_open: f32,
+    shutter_close: f32,
+    /// Position and orientation at the end of the shutter, when the camera moves.
+    end: Option<(Vec3, Quat)>,
+    filter: PixelFilter,
 }
 
 impl Camera {
-    pub fn new(width: u32, height: u32, ray: Ray) -> Self {
-        Self { width, height, ray }
+    /// A camera at `eye` looking at `target`, with `fov` the vertical field of view in degrees.
+    /// The size starts at one pixel, the canvas sets the real one through `resize`.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3, fov: f32) -> Self {
+        let mut camera = Self { width: 1, height: 1, position: eye, orientation: Quat::IDENTITY, up: Vec3::Y,
+            projection: Projection::Perspective, fov, ortho_height: 2.0, roll: 0.0, aperture: 0.0,
+            focus_distance: (target - eye).length().max(f32::EPSILON), blades: 0, blade_rotation: 0.0, shutter_open: 0.0,
+            shutter_close: 0.0, end: None, filter: PixelFilter::default() };
+        camera.set_look_at(target, up);
+        camera
+    }
+
+    /// Points the camera at `target`.
+    pub fn set_look_at(&mut self, target: Vec3, up: Vec3) {
+        self.up = up.try_normalize().unwrap_or(Vec3::Y);
+        self.orientation = look_at_orientation(target - self.position, self.up);
+    }
+
+    /// Moves the camera to `end_eye`, looking at `end_target`, over the motion the shutter samples.
+    pub fn with_end_look_at(mut self, end_eye: Vec3, end_target: Vec3) -> Self {
+        self.end = Some((end_eye, look_at_orientation(end_target - end_eye, self.up)));
+        self
+    }
+
+    /// Rays get times spread over `open..close`, a shutter that never opens leaves everything still.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    /// Maps `u` in [0, 1) into the shutter interval.
+    pub fn shutter_time(&self, u: f32) -> f32 {
+        self.shutter_open + (self.shutter_close - self.shutter_open) * u
+    }
+
+    /// Position, right, up and forward at `time`. The end pose is blended in the same way as `camera_frame` in
+    /// raytracer.wgsl does it.
+    pub fn frame_at(&self, time: f32) -> (Vec3, Vec3, Vec3, Vec3) {
+        let (right, up, forward) = self.basis();
+        let Some((end_position, end_orientation)) = self.end else {
+            return (self.position, right, up, forward);
+        };
+
+        let (_, end_up, end_forward) = rolled_basis(end_orientation, self.roll);
+        let forward = forward.lerp(end_forward, time).normalize();
+        let right = forward.cross(up.lerp(end_up, time)).normalize();
+        (self.position.lerp(end_position, time), right, right.cross(forward), forward)
+    }
+
+    pub fn with_filter(mut self, filter: PixelFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn filter(&self) -> PixelFilter {
+        self.filter
+    }
+
+    pub fn with_roll(mut self, roll: f32) -> Self {
+        self.roll = roll;
+        self
+    }
+
+    /// `ortho_height` only matters for the orthographic projection.
+    pub fn with_projection(mut self, projection: Projection, ortho_height: f32) -> Self {
+        self.projection = projection;
+        self.ortho_height = ortho_height;
+        self
+    }
+
+    pub fn with_lens(mut self, aperture: f32, focus_distance: f32) -> Self {
+        self.aperture = aperture;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// `rotation` in radians.
+    pub fn with_aperture_blades(mut self, blades: u32, rotation: f32) -> Self {
+        self.blades = blades;
+        self.blade_rotation = rotation;
+        self
+    }
+
+    /// Moves the focal plane through `point`.
+    pub fn focus_on(&mut self, point: Vec3) {
+        let distance = (point - self.position).dot(self.direction());
+        if distance > 0.0 {
+            self.focus_distance = distance;
+        }
+    }
+
+    /// The ray at `time` through a point in pixel coordinates from the centre of the lens, `None` outside the fisheye
+    /// circle.
+    pub fn pinhole_ray(&self, px: f32, py: f32, time: f32) -> Option<Ray> {
+        let (position, right, up, forward) = self.frame_at(time);
+        let ndc = Vec2::new(2.0 * px / self.width as f32 - 1.0, 1.0 - 2.0 * py / self.height as f32);
+
+        match self.projection {
+            Projection::Perspective => {
+                let screen = ndc * Vec2::new(self.aspect(), 1.0) * self.tan_half_fov();
+                Some(Ray::new(position, (forward + right * screen.x + up * screen.y).normalize()))
+            }
+            Projection::Orthographic => {
+                let offset = ndc * Vec2::new(self.aspect(), 1.0) * self.ortho_height * 0.5;
+                Some(Ray::new(position + right * offset.x + up * offset.y, forward))
+            }
+            Projection::Fisheye => {
+                let p = ndc * Vec2::new(self.aspect(), 1.0);
+                if p.length() > 1.0 {
+                    return None;
+                }
+                let theta = p.length() * self.fov.to_radians() * 0.5;
+                let phi = p.y.atan2(p.x);
+                let side = right * phi.cos() + up * phi.sin();
+                Some(Ray::new(position, forward * theta.cos() + side * theta.sin()))
+            }
+            Projection::Equirectangular => {
+                let longitude = ndc.x * PI;
+                let latitude = ndc.y * FRAC_PI_2;
+                let direction = (forward * longitude.cos() + right * longitude.sin()) * latitude.cos() + up * latitude.sin();
+                Some(Ray::new(position, direction))
+            }
+        }
+    }
+
+    /// The ray at `time` through a point in pixel coordinates from the spot on the lens picked by `lens_sample` in
+    /// [0, 1)². Matches `lens_ray` in raytracer.wgsl.
+    pub fn lens_ray(&self, px: f32, py: f32, lens_sample: Vec2, time: f32) -> Option<Ray> {
+        let pinhole = self.pinhole_ray(px, py, time)?.with_time(time);
+        if self.aperture <= 0.0 {
+            return Some(pinhole);
+        }
+
+        // the planar projections focus on a plane, the wide angle ones on a sphere around the camera
+        let (_, right, up, forward) = self.frame_at(time);
+        let focus_t = match self.projection {
+            Projection::Perspective | Projection::Orthographic => self.focus_distance / pinhole.direction().dot(forward),
+            Projection::Fisheye | Projection::Equirectangular => self.focus_distance,
+        };
+        let focus_point = pinhole.at(focus_t);
+        let lens = sample_aperture(lens_sample, self.blades, self.blade_rotation) * self.aperture;
+        let origin = pinhole.origin() + right * lens.x + up * lens.y;
+        Some(Ray::new(origin, (focus_point - origin).normalize()).with_time(time))
+    }
+
+    /// Right, up and forward in world space, roll included. Projection and ray generation both go through this.
+    pub fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        rolled_basis(self.orientation, self.roll)
+    }
+
+    /// Turns around world up by `yaw` and around the camera's right by `pitch`, both in radians.
+    /// Pitch stops just short of looking straight up or down so the horizon can't flip.
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        self.orientation = (Quat::from_axis_angle(self.up, yaw) * self.orientation).normalize();
+
+        let pitched = (self.orientation * Quat::from_rotation_x(pitch)).normalize();
+        if (pitched * Vec3::NEG_Z).dot(self.up).abs() < 0.999 {
+            self.orientation = pitched;
+        }
+    }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        self.position += offset;
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn tan_half_fov(&self) -> f32 {
+        (self.fov.to_radians() * 0.5).tan()
+    }
+
+    /// Everything raytracer.wgsl needs to generate the primary rays itself.
+    pub fn to_gpu_camera(&self) -> GpuCamera {
+        let (right, up, forward) = self.basis();
+        let (end_position, end_right, end_up, end_forward) = self.frame_at(1.0);
+        GpuCamera {
+            origin: self.position.to_array(),
+            tan_half_fov: self.tan_half_fov(),
+            right: right.to_array(),
+            aspect: self.aspect(),
+            up: up.to_array(),
+            filter_radius: self.filter.radius(),
+            forward: forward.to_array(),
+            filter_kind: self.filter.gpu_id(),
+            aperture: self.aperture,
+            focus_distance: self.focus_distance,
+            blades: self.blades,
+            blade_rotation: self.blade_rotation,
+            projection: self.projection.as_u32(),
+            half_fov: self.fov.to_radians() * 0.5,
+            ortho_height: self.ortho_height,
+            _pad: 0,
+            end_origin: end_position.to_array(),
+            shutter_open: self.shutter_open,
+            end_right: end_right.to_array(),
+            shutter_close: self.shutter_close,
+            end_up: end_up.to_array(),
+            moving: self.end.is_some() as u32,
+            end_forward: end_forward.to_array(),
+            _pad1: 0,
+        }
     }
 
     pub fn for_each_pixel<F>(&self, mut f: F) where F: FnMut(u32, u32) {
@@ -20,36 +336,53 @@ impl Camera {
         }
     }
 
+    /// Inverse of `pinhole_ray` for every projection, so overlays line up with the render.
     pub fn world_to_screen(&self, p: Vec3) -> Option<(i32, i32)> {
-        let forward = self.ray.direction().normalize();
-
-        let world_up = if forward.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
-        let right = world_up.cross(forward).normalize();
-        let up = forward.cross(right).normalize();
-
-        let rel = p - self.ray.origin();
+        let (right, up, forward) = self.basis();
+        let rel = p - self.position;
 
         let x = rel.dot(right);
         let y = rel.dot(up);
         let z = rel.dot(forward);
 
-        if z <= 0.0 {
-            return None;
-        }
-
-        let fov_y = 90.0_f32.to_radians();
-        let f = 1.0 / (fov_y * 0.5).tan();
-        let aspect = self.width as f32 / self.height as f32;
-
-        let ndc_x = (x * f / aspect) / z;
-        let ndc_y = (y * f) / z;
-
-        // if ndc_x.abs() > 10.0 || ndc_y.abs() > 10.0 {
-        //     return None;
-        // }
+        let ndc = match self.projection {
+            Projection::Perspective => {
+                if z <= 0.0 {
+                    return None;
+                }
+                Vec2::new(x / (z * self.tan_half_fov() * self.aspect()), y / (z * self.tan_half_fov()))
+            }
+            Projection::Orthographic => {
+                if z <= 0.0 {
+                    return None;
+                }
+                Vec2::new(x / self.aspect(), y) / (self.ortho_height * 0.5)
+            }
+            Projection::Fisheye => {
+                let length = rel.length();
+                if length == 0.0 {
+                    return None;
+                }
+                let theta = (z / length).clamp(-1.0, 1.0).acos();
+                let r = theta / (self.fov.to_radians() * 0.5);
+                // outside the image circle, which is left black
+                if r > 1.0 {
+                    return None;
+                }
+                let phi = y.atan2(x);
+                Vec2::new(r * phi.cos() / self.aspect(), r * phi.sin())
+            }
+            Projection::Equirectangular => {
+                let length = rel.length();
+                if length == 0.0 {
+                    return None;
+                }
+                Vec2::new(x.atan2(z) / PI, (y / length).clamp(-1.0, 1.0).asin() / FRAC_PI_2)
+            }
+        };
 
-        let sx = ((ndc_x + 1.0) * 0.5 * self.width as f32) as i32;
-        let sy = ((1.0 - ndc_y) * 0.5 * self.height as f32) as i32;
+        let sx = ((ndc.x + 1.0) * 0.5 * self.width as f32) as i32;
+        let sy = ((1.0 - ndc.y) * 0.5 * self.height as f32) as i32;
 
         Some((sx, sy))
     }
@@ -58,9 +391,55 @@ impl Camera {
         self.width = width;
         self.height = height;
     }
-    
+
     pub fn width(&self) -> u32 { self.width }
     pub fn height(&self) -> u32 { self.height }
-    pub fn ray(&self) -> Ray { self.ray.clone() }
-    pub fn set_ray(&mut self, ray: Ray) { self.ray = ray; }
-}
\ No newline at end of file
+    pub fn position(&self) -> Vec3 { self.position }
+    pub fn direction(&self) -> Vec3 { self.basis().2 }
+    pub fn up(&self) -> Vec3 { self.up }
+    pub fn projection(&self) -> Projection { self.projection }
+    pub fn fov(&self) -> f32 { self.fov }
+    pub fn ortho_height(&self) -> f32 { self.ortho_height }
+    pub fn roll(&self) -> f32 { self.roll }
+    pub fn aperture(&self) -> f32 { self.aperture }
+    pub fn focus_distance(&self) -> f32 { self.focus_distance }
+    pub fn blades(&self) -> u32 { self.blades }
+    pub fn blade_rotation(&self) -> f32 { self.blade_rotation }
+    pub fn shutter(&self) -> (f32, f32) { (self.shutter_open, self.shutter_close) }
+    pub fn end_position(&self) -> Option<Vec3> { self.end.map(|(position, _)| position) }
+    pub fn end_direction(&self) -> Option<Vec3> { self.end.map(|(_, orientation)| orientation * Vec3::NEG_Z) }
+}
+
+/// Looking along `direction` with the horizon level against `up`. Looking straight along `up` picks an arbitrary right
+/// vector instead of failing.
+fn look_at_orientation(direction: Vec3, up: Vec3) -> Quat {
+    let forward = direction.try_normalize().unwrap_or(Vec3::NEG_Z);
+    let right = forward.cross(up).try_normalize().unwrap_or_else(|| forward.any_orthonormal_vector());
+    let true_up = right.cross(forward);
+    Quat::from_mat3(&Mat3::from_cols(right, true_up, -forward)).normalize()
+}
+
+fn rolled_basis(orientation: Quat, roll: f32) -> (Vec3, Vec3, Vec3) {
+    let rotation = orientation * Quat::from_rotation_z(roll);
+    (rotation * Vec3::X, rotation * Vec3::Y, rotation * Vec3::NEG_Z)
+}
+
+/// A uniform point on the unit disk, or on the regular polygon inscribed in it when there are at least three blades.
+/// Same as `sample_aperture` in raytracer.wgsl.
+fn sample_aperture(u: Vec2, blades: u32, rotation: f32) -> Vec2 {
+    if blades < 3 {
+        let r = u.x.sqrt();
+        let phi = 2.0 * PI * u.y;
+        return Vec2::new(r * phi.cos(), r * phi.sin());
+    }
+
+    // pick one of the triangles between the centre and a blade edge, then a uniform point in it
+    let scaled = u.x * blades as f32;
+    let sector = scaled.floor().min(blades as f32 - 1.0);
+    let v = scaled - sector;
+    let step = 2.0 * PI / blades as f32;
+    let a = rotation + sector * step;
+    let corner_a = Vec2::new(a.cos(), a.sin());
+    let corner_b = Vec2::new((a + step).cos(), (a + step).sin());
+    v.sqrt() * (corner_a * (1.0 - u.y) + corner_b * u.y)
+}
@@ -0,0 +1,60 @@
+use clap::Parser;
+use crate::camera::PixelFilter;
+use crate::output::ImageFormat;
+
+#[derive(Parser, Debug)]
+#[command(about = "GPU path tracer", long_about = None)]
+pub struct Args {
+    /// Scene description file (TOML)
+    #[arg(short, long, default_value = "scenes/cornell.toml")]
+    pub scene: String,
+
+    /// Image width in pixels
+    #[arg(long, default_value_t = 800, value_parser = clap::value_parser!(u32).range(1..=16384))]
+    pub width: u32,
+
+    /// Image height in pixels
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u32).range(1..=16384))]
+    pub height: u32,
+
+    /// Samples per pixel to accumulate; the window keeps accumulating forever when omitted
+    #[arg(short = 'n', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Maximum number of bounces per path
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub max_bounces: u32,
+
+    /// Where to write the image in headless mode (.png, .ppm, .hdr or .exr)
+    #[arg(short, long, default_value = "render.png", value_parser = parse_output_path)]
+    pub output: String,
+
+    /// Render offscreen without opening a window and write the result to --output
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Pixel reconstruction filter: box, tent, gaussian or blackman-harris
+    #[arg(long, default_value = "box", value_parser = parse_filter)]
+    pub filter: PixelFilter,
+
+    /// Draw the BVH leaf bounds on top of the interactive render
+    #[arg(long)]
+    pub debug_bvh: bool,
+}
+
+impl Args {
+    pub fn headless_samples(&self) -> u32 {
+        self.samples.unwrap_or(256)
+    }
+}
+
+fn parse_output_path(path: &str) -> Result<String, String> {
+    match ImageFormat::from_path(path) {
+        Some(_) => Ok(path.to_string()),
+        None => Err("expected a file ending in .png, .ppm, .hdr or .exr".to_string()),
+    }
+}
+
+fn parse_filter(name: &str) -> Result<PixelFilter, String> {
+    PixelFilter::from_name(name).ok_or_else(|| "expected box, tent, gaussian or blackman-harris".to_string())
+}
@@ -22,8 +22,59 @@ impl Color {
         }
     }
 
-    pub fn to_u32(&self) -> u32 {
-        ((self.r * 255.0) as u32) << 16 | ((self.g * 255.0) as u32) << 8 | (self.b * 255.0) as u32
+    /// ACES filmic curve (Narkowicz fit), maps any radiance into [0, 1) without the hard clip of `to_u32`.
+    pub fn tonemap(self) -> Self {
+        let aces = |x: f32| {
+            let x = x.max(0.0);
+            (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+        };
+        Self::new(aces(self.r), aces(self.g), aces(self.b))
+    }
+
+    /// Packs into 0x00RRGGBB, saturating each channel so out of range values can't bleed into their neighbours.
+    pub fn to_u32(self) -> u32 {
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u32;
+        channel(self.r) << 16 | channel(self.g) << 8 | channel(self.b)
+    }
+
+    /// Rec. 709 luminance of linear sRGB.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    /// Drops NaN and infinite samples, a single one would otherwise poison a pixel's accumulated sum for good.
+    pub fn finite_or_black(self) -> Self {
+        if self.is_finite() { self } else { Self::black() }
+    }
+
+    /// Linear sRGB colour of a black body at `kelvin`, scaled to unit luminance.
+    /// Integrates Planck's law against the Wyman, Sloan and Shirley fit of the CIE 1931 observer.
+    pub fn from_temperature(kelvin: f32) -> Self {
+        let lobe = |x: f64, mean: f64, left: f64, right: f64| {
+            let t = (x - mean) / if x < mean { left } else { right };
+            (-0.5 * t * t).exp()
+        };
+
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for nm in (380..=780).step_by(5) {
+            let nm = nm as f64;
+            let meters = nm * 1e-9;
+            let radiance = 1.0 / (meters.powi(5) * ((1.4388e-2 / (meters * kelvin as f64)).exp() - 1.0));
+
+            x += radiance * (1.056 * lobe(nm, 599.8, 37.9, 31.0) + 0.362 * lobe(nm, 442.0, 16.0, 26.7) - 0.065 * lobe(nm, 501.1, 20.4, 26.2));
+            y += radiance * (0.821 * lobe(nm, 568.8, 46.9, 40.5) + 0.286 * lobe(nm, 530.9, 16.3, 31.1));
+            z += radiance * (1.217 * lobe(nm, 437.0, 11.8, 36.0) + 0.681 * lobe(nm, 459.0, 26.0, 13.8));
+        }
+
+        let r = (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0);
+        let g = (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0);
+        let b = (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0);
+        let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        Self::new((r / luminance) as f32, (g / luminance) as f32, (b / luminance) as f32)
     }
 
     #[allow(dead_code)]
@@ -1,8 +1,13 @@
-use crate::gpu_types::{GpuColor, GpuPlane, GpuRay, GpuSphere, GpuTriangle, GpuBVHNode};
-use crate::scene::Scene;
+use crate::gpu_types::{GpuCamera, GpuPlane, GpuSphere, GpuTriangle, GpuBVHNode, GpuTextureIds, GpuInstance, GpuMaterial, GpuPose, GpuEmitter, GpuLight};
+use crate::emitter::EmitterList;
+use crate::scene::{material_to_gpu_material, plane_to_gpu_plane, sphere_to_gpu_sphere, triangle_to_gpu_triangle, GpuSlot, Scene, SceneChanges, SceneInstance};
 use crate::window::Canvas;
-use crate::bvh::{construct_bvh, flatten_bvh_for_gpu};
-use crate::model::Mesh;
+use crate::bvh::{build_bvh_over_bounds, construct_bvh, flatten_bvh_for_gpu, primitive_ref, unpack_primitive_ref, validate_flattened,
+    GpuBVHOffsets, AABB, DEFAULT_LEAF_SIZE, PRIMITIVE_PLANE, PRIMITIVE_SPHERE, PRIMITIVE_TRIANGLE};
+use crate::objects::{Hittable, Plane, Sphere, Triangle};
+use crate::profiler::{profiler_start, profiler_stop};
+use glam::{Mat4, Vec3};
+use crate::instance::motion_aabb;
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt;
 
@@ -17,14 +22,40 @@ struct Counts {
     frame_number: u32,
     bvh_node_count: u32,
     bvh_index_count: u32,
+    max_bounces: u32,
+    texture_count: u32,
+    instance_count: u32,
+    tlas_root: u32,
+    emitter_count: u32,
+    emitter_power: f32,
+    light_count: u32,
+    _pad0: u32,
 }
 
-pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene) {
+/// What incremental updates need to know about the scene data already on the GPU.
+pub struct GpuScene {
+    /// Root node and object-space box of every BLAS, the loose primitives' first when there are any.
+    blas: Vec<(u32, AABB)>,
+    /// Where the top-level BVH starts in the node and index buffers, it always sits after every BLAS.
+    offsets: GpuBVHOffsets,
+    instance_count: usize,
+    /// The loose primitives' BVH as uploaded to the start of the node and index buffers, kept to refit it in place.
+    loose_nodes: Vec<GpuBVHNode>,
+    loose_indices: Vec<u32>,
+    max_bounces: u32,
+    emitter_count: usize,
+}
+
+pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene, max_bounces: u32) {
     let shader_source = format!(
-        "{}\n{}\n{}\n{}",
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
         include_str!("shaders/types.wgsl"),
         include_str!("shaders/hit.wgsl"),
+        include_str!("shaders/texture.wgsl"),
         include_str!("shaders/random.wgsl"),
+        include_str!("shaders/brdf.wgsl"),
+        include_str!("shaders/light.wgsl"),
+        include_str!("shaders/environment.wgsl"),
         include_str!("shaders/raytracer.wgsl"),
     );
 
@@ -33,88 +64,6 @@ pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene) {
         source: wgpu::ShaderSource::Wgsl(shader_source.into()),
     });
 
-    let (gpu_spheres, gpu_triangles, gpu_planes) = extract_scene_data(scene);
-    let (bvh_nodes, bvh_indices) = build_scene_bvh(scene, &gpu_triangles);
-
-    let counts = Counts {
-        sphere_count: gpu_spheres.len() as u32,
-        triangle_count: gpu_triangles.len() as u32,
-        plane_count: gpu_planes.len() as u32,
-        width: canvas.width(),
-        height: canvas.height(),
-        frame_number: canvas.sample_count,
-        bvh_node_count: bvh_nodes.len() as u32,
-        bvh_index_count: bvh_indices.len() as u32,
-    };
-
-    println!("Creating counts buffer:");
-    println!("  spheres: {}", counts.sphere_count);
-    println!("  triangles: {}", counts.triangle_count);
-    println!("  planes: {}", counts.plane_count);
-    println!("  width: {}", counts.width);
-    println!("  height: {}", counts.height);
-    println!("  BVH nodes: {}", counts.bvh_node_count);
-    println!("  BVH indices: {}", counts.bvh_index_count);
-
-    let sphere_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Sphere Buffer"),
-        contents: bytemuck::cast_slice(&gpu_spheres),
-        usage: wgpu::BufferUsages::STORAGE,
-    });
-
-    let triangle_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Triangle Buffer"),
-        contents: bytemuck::cast_slice(&gpu_triangles),
-        usage: wgpu::BufferUsages::STORAGE,
-    });
-
-    let plane_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Plane Buffer"),
-        contents: bytemuck::cast_slice(&gpu_planes),
-        usage: wgpu::BufferUsages::STORAGE,
-    });
-
-    let bvh_node_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("BVH Node Buffer"),
-        contents: bytemuck::cast_slice(&bvh_nodes),
-        usage: wgpu::BufferUsages::STORAGE,
-    });
-
-    let bvh_index_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("BVH Index Buffer"),
-        contents: bytemuck::cast_slice(&bvh_indices),
-        usage: wgpu::BufferUsages::STORAGE,
-    });
-
-    let pixel_count = canvas.pixel_count() as usize;
-
-    let ray_buffer = canvas.device().create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Ray Buffer"),
-        size: (pixel_count * std::mem::size_of::<GpuRay>()) as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    let color_buffer = canvas.device().create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Color Output Buffer"),
-        size: (pixel_count * std::mem::size_of::<GpuColor>()) as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
-
-    let staging_buffer = canvas.device().create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Staging Buffer"),
-        size: (pixel_count * std::mem::size_of::<GpuColor>()) as u64,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    let counts_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Counts Buffer"),
-        contents: bytemuck::cast_slice(&[counts]),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
-
     let bind_group_layout = canvas.device().create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("Compute Bind Group Layout"),
         entries: &[
@@ -122,7 +71,7 @@ pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene) {
                 binding: 0,
                 visibility: wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
@@ -198,21 +147,66 @@ pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene) {
                 },
                 count: None,
             },
-        ],
-    });
-
-    let bind_group = canvas.device().create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Compute Bind Group"),
-        layout: &bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry { binding: 0, resource: ray_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 1, resource: color_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 2, resource: sphere_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 3, resource: triangle_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 4, resource: plane_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 5, resource: counts_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 6, resource: bvh_node_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 7, resource: bvh_index_buffer.as_entire_binding() },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 11,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 12,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 13,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     });
 
@@ -231,23 +225,385 @@ pub fn setup_compute_pipeline(canvas: &mut Canvas, scene: &Scene) {
         cache: None,
     });
 
+    let camera_buffer = canvas.device().create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Camera Buffer"),
+        size: std::mem::size_of::<GpuCamera>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
     canvas.compute_pipeline = Some(pipeline);
+    canvas.compute_bind_group_layout = Some(bind_group_layout);
+    canvas.camera_buffer = Some(camera_buffer);
+
+    let counts = upload_scene(canvas, scene, max_bounces);
+
+    println!("Creating counts buffer:");
+    println!("  spheres: {}", counts.sphere_count);
+    println!("  triangles: {}", counts.triangle_count);
+    println!("  planes: {}", counts.plane_count);
+    println!("  width: {}", counts.width);
+    println!("  height: {}", counts.height);
+    println!("  instances: {}", counts.instance_count);
+    println!("  BVH nodes: {}", counts.bvh_node_count);
+    println!("  BVH indices: {}", counts.bvh_index_count);
+    println!("  max bounces: {}", counts.max_bounces);
+    println!("  textures: {}", counts.texture_count);
+    println!("  emitters: {}", counts.emitter_count);
+    println!("  lights: {}", counts.light_count);
+}
+
+/// What `upload_scene` copies into the scene buffers, laid out exactly as the shader reads it.
+struct SceneBuffers {
+    spheres: Vec<GpuSphere>,
+    planes: Vec<GpuPlane>,
+    triangles: Vec<GpuTriangle>,
+    bvh_nodes: Vec<GpuBVHNode>,
+    bvh_indices: Vec<u32>,
+    instances: Vec<GpuInstance>,
+    emitters: Vec<GpuEmitter>,
+    lights: Vec<GpuLight>,
+    textures: Vec<u32>,
+    /// The size and frame number are left at zero for the caller to fill in.
+    counts: Counts,
+    gpu_scene: GpuScene,
+}
+
+/// Lays the scene out for the GPU: the BLAS of the loose primitives and every mesh, then the TLAS over the
+/// instances. Empty buffers get one dummy element, wgpu does not allow zero-sized bindings.
+fn build_scene_buffers(scene: &Scene, max_bounces: u32) -> SceneBuffers {
+    let (spheres, planes) = extract_scene_data(scene);
+    let BottomLevel { triangles, nodes: mut bvh_nodes, indices: mut bvh_indices, blas, loose_node_count, loose_index_count } = build_blas(scene);
+    let loose_nodes = bvh_nodes[..loose_node_count].to_vec();
+    let loose_indices = bvh_indices[..loose_index_count].to_vec();
+
+    let scene_instances = scene.export_instances();
+    let offsets = GpuBVHOffsets { triangle: 0, node: bvh_nodes.len() as u32, index: bvh_indices.len() as u32 };
+    let emitter_list = EmitterList::from_scene(scene);
+    let gpu_scene = GpuScene { blas, offsets, instance_count: scene_instances.len(), loose_nodes, loose_indices, max_bounces,
+        emitter_count: emitter_list.len() };
+    let (mut instances, tlas_nodes, tlas_indices) = build_tlas(&scene_instances, &gpu_scene);
+
+    bvh_nodes.extend(tlas_nodes);
+    bvh_indices.extend(tlas_indices);
+
+    if cfg!(debug_assertions) && !scene_instances.is_empty() {
+        validate_scene_bvh(scene, &bvh_nodes, &bvh_indices, &instances, &gpu_scene, triangles.len());
+    }
+    let triangles = with_dummy_triangle(triangles);
+    if bvh_nodes.is_empty() {
+        bvh_nodes.push(GpuBVHNode {
+            min: [0.0; 3],
+            _pad0: 0.0,
+            max: [0.0; 3],
+            _pad1: 0.0,
+            left_first: 0,
+            right_count: 0,
+            is_leaf: 1,
+            _pad2: 0,
+        });
+        bvh_indices.push(0);
+    }
+    if instances.is_empty() {
+        instances.push(GpuInstance::zeroed());
+    }
+    let mut emitters = emitter_list.to_gpu();
+    if emitters.is_empty() {
+        emitters.push(GpuEmitter::zeroed());
+    }
+    let scene_lights = scene.export_lights();
+    let mut lights: Vec<GpuLight> = scene_lights.iter().map(|light| light.gpu_light()).collect();
+    if lights.is_empty() {
+        lights.push(GpuLight::zeroed());
+    }
+    let mut textures = scene.export_gpu_textures();
+    if textures.is_empty() {
+        textures.push(0);
+    }
+
+    let counts = Counts {
+        sphere_count: spheres.len() as u32,
+        triangle_count: triangles.len() as u32,
+        plane_count: planes.len() as u32,
+        width: 0,
+        height: 0,
+        frame_number: 0,
+        bvh_node_count: bvh_nodes.len() as u32,
+        bvh_index_count: bvh_indices.len() as u32,
+        max_bounces,
+        texture_count: scene.textures().len() as u32,
+        instance_count: scene_instances.len() as u32,
+        tlas_root: gpu_scene.offsets.node,
+        emitter_count: emitter_list.len() as u32,
+        emitter_power: emitter_list.total_power(),
+        light_count: scene_lights.len() as u32,
+        _pad0: 0,
+    };
+
+    SceneBuffers { spheres, planes, triangles, bvh_nodes, bvh_indices, instances, emitters, lights, textures, counts, gpu_scene }
+}
+
+/// Creates every scene buffer and the bind group from scratch, the shader and pipeline stay as they are. Returns
+/// the counts it uploaded.
+fn upload_scene(canvas: &mut Canvas, scene: &Scene, max_bounces: u32) -> Counts {
+    let SceneBuffers { spheres: gpu_spheres, planes: gpu_planes, triangles: gpu_triangles, bvh_nodes, bvh_indices,
+        instances: gpu_instances, emitters: gpu_emitters, lights: gpu_lights, textures: gpu_textures, mut counts, gpu_scene }
+        = build_scene_buffers(scene, max_bounces);
+    counts.width = canvas.width();
+    counts.height = canvas.height();
+    counts.frame_number = canvas.sample_count;
+
+
+    let sphere_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Sphere Buffer"),
+        contents: bytemuck::cast_slice(&gpu_spheres),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let triangle_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Triangle Buffer"),
+        contents: bytemuck::cast_slice(&gpu_triangles),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let plane_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Plane Buffer"),
+        contents: bytemuck::cast_slice(&gpu_planes),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bvh_node_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("BVH Node Buffer"),
+        contents: bytemuck::cast_slice(&bvh_nodes),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bvh_index_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("BVH Index Buffer"),
+        contents: bytemuck::cast_slice(&bvh_indices),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let instance_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Instance Buffer"),
+        contents: bytemuck::cast_slice(&gpu_instances),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let emitter_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Emitter Buffer"),
+        contents: bytemuck::cast_slice(&gpu_emitters),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let light_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: bytemuck::cast_slice(&gpu_lights),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let environment_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Buffer"),
+        contents: bytemuck::cast_slice(&[scene.environment().gpu_environment()]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let environment_data_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Environment Data Buffer"),
+        contents: bytemuck::cast_slice(&scene.environment().gpu_data()),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let texture_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Texture Buffer"),
+        contents: bytemuck::cast_slice(&gpu_textures),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let counts_buffer = canvas.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Counts Buffer"),
+        contents: bytemuck::cast_slice(&[counts]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bind_group = canvas.device().create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Compute Bind Group"),
+        layout: canvas.compute_bind_group_layout.as_ref().unwrap(),
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: canvas.camera_buffer.as_ref().unwrap().as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: canvas.accumulation_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: sphere_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: triangle_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 4, resource: plane_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 5, resource: counts_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 6, resource: bvh_node_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 7, resource: bvh_index_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 8, resource: texture_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 9, resource: instance_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 10, resource: emitter_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 11, resource: light_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 12, resource: environment_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 13, resource: environment_data_buffer.as_entire_binding() },
+        ],
+    });
+
     canvas.compute_bind_group = Some(bind_group);
     canvas.sphere_buffer = Some(sphere_buffer);
     canvas.triangle_buffer = Some(triangle_buffer);
     canvas.plane_buffer = Some(plane_buffer);
-    canvas.ray_buffer = Some(ray_buffer);
-    canvas.color_buffer = Some(color_buffer);
-    canvas.staging_buffer = Some(staging_buffer);
     canvas.counts_buffer = Some(counts_buffer);
+    canvas.bvh_node_buffer = Some(bvh_node_buffer);
+    canvas.bvh_index_buffer = Some(bvh_index_buffer);
+    canvas.instance_buffer = Some(instance_buffer);
+    canvas.emitter_buffer = Some(emitter_buffer);
+    canvas.light_buffer = Some(light_buffer);
+    canvas.gpu_scene = Some(gpu_scene);
+    counts
+}
+
+/// Brings the GPU up to date with the scene's edits since the last call, writing only the ranges that changed:
+/// - a changed material or loose primitive rewrites just its element,
+/// - moved loose primitives refit their BVH in place and rebuild the top level,
+/// - moved instances rebuild only the top level,
+/// - added or removed objects and lights, or a new environment, upload the scene buffers again but keep the compiled pipeline,
+/// - any change rewrites the emitter list, or uploads everything again when the number of emitters changed.
+///
+/// Accumulation restarts only when something changed, which is also what it returns.
+pub fn apply_scene_changes(canvas: &mut Canvas, scene: &mut Scene) -> bool {
+    let changes = scene.take_changes();
+    if changes.is_empty() {
+        return false;
+    }
+    canvas.reset_accumulation();
+
+    // nothing is on the GPU yet, the first render uploads everything
+    if canvas.compute_pipeline.is_none() {
+        return true;
+    }
+    let Some(mut gpu_scene) = canvas.gpu_scene.take() else {
+        return true;
+    };
+
+    match scene_writes(&mut gpu_scene, scene, &changes) {
+        Some(writes) => {
+            for write in writes {
+                let buffer = match write.buffer {
+                    SceneBuffer::Sphere => &canvas.sphere_buffer,
+                    SceneBuffer::Plane => &canvas.plane_buffer,
+                    SceneBuffer::Triangle => &canvas.triangle_buffer,
+                    SceneBuffer::BvhNode => &canvas.bvh_node_buffer,
+                    SceneBuffer::BvhIndex => &canvas.bvh_index_buffer,
+                    SceneBuffer::Instance => &canvas.instance_buffer,
+                    SceneBuffer::Emitter => &canvas.emitter_buffer,
+                    SceneBuffer::Counts => &canvas.counts_buffer,
+                };
+                canvas.queue().write_buffer(buffer.as_ref().unwrap(), write.offset, &write.data);
+            }
+            canvas.gpu_scene = Some(gpu_scene);
+        }
+        None => {
+            profiler_start("upload scene");
+            upload_scene(canvas, scene, gpu_scene.max_bounces);
+            profiler_stop("upload scene");
+        }
+    }
+    true
+}
+
+/// The scene buffers incremental updates write into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SceneBuffer {
+    Sphere,
+    Plane,
+    Triangle,
+    BvhNode,
+    BvhIndex,
+    Instance,
+    Emitter,
+    Counts,
+}
+
+/// Bytes for one of the scene buffers, starting `offset` bytes in.
+struct BufferWrite {
+    buffer: SceneBuffer,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+impl BufferWrite {
+    fn elements<T: Pod>(buffer: SceneBuffer, first: usize, elements: &[T]) -> Self {
+        Self { buffer, offset: (first * std::mem::size_of::<T>()) as u64, data: bytemuck::cast_slice(elements).to_vec() }
+    }
+}
+
+/// The writes that bring buffers uploaded for `gpu_scene` in line with the scene after `changes`, or `None` when
+/// they have to be uploaded again.
+fn scene_writes(gpu_scene: &mut GpuScene, scene: &Scene, changes: &SceneChanges) -> Option<Vec<BufferWrite>> {
+    let emitters = EmitterList::from_scene(scene);
+    let instances = scene.export_instances();
+    if changes.structure || gpu_scene.instance_count != instances.len() || gpu_scene.emitter_count != emitters.len() {
+        return None;
+    }
+
+    let mut writes = Vec::new();
+    if !emitters.is_empty() {
+        writes.push(BufferWrite::elements(SceneBuffer::Emitter, 0, &emitters.to_gpu()));
+    }
+    // emitter_power follows emitter_count
+    writes.push(BufferWrite {
+        buffer: SceneBuffer::Counts,
+        offset: std::mem::offset_of!(Counts, emitter_count) as u64,
+        data: bytemuck::cast_slice(&[emitters.len() as u32, emitters.total_power().to_bits()]).to_vec(),
+    });
+
+    let mut rebuild_tlas = changes.transforms;
+    for &object in changes.materials.iter().chain(&changes.geometry) {
+        let (write, instance) = object_write(scene, gpu_scene, &instances, object)?;
+        writes.push(write);
+        rebuild_tlas |= instance;
+    }
+
+    if !changes.geometry.is_empty() {
+        refit_loose_blas(gpu_scene, scene);
+        writes.push(BufferWrite::elements(SceneBuffer::BvhNode, 0, &gpu_scene.loose_nodes));
+        rebuild_tlas = true;
+    }
+
+    if rebuild_tlas && !instances.is_empty() {
+        // the same instance count always gives the same number of nodes, so the new top level fits over the old one
+        let (gpu_instances, tlas_nodes, tlas_indices) = build_tlas(&instances, gpu_scene);
+        writes.push(BufferWrite::elements(SceneBuffer::Instance, 0, &gpu_instances));
+        writes.push(BufferWrite::elements(SceneBuffer::BvhNode, gpu_scene.offsets.node as usize, &tlas_nodes));
+        writes.push(BufferWrite::elements(SceneBuffer::BvhIndex, gpu_scene.offsets.index as usize, &tlas_indices));
+    }
+    Some(writes)
 }
 
-fn extract_scene_data(scene: &Scene) -> (Vec<GpuSphere>, Vec<GpuTriangle>, Vec<GpuPlane>) {
-    let primitives = scene.export_gpu_data();
+/// The write for one object's GPU element, and whether it went to the instance buffer, those are left to the
+/// top-level rebuild when one is happening anyway.
+fn object_write(scene: &Scene, gpu_scene: &GpuScene, instances: &[SceneInstance], object: usize) -> Option<(BufferWrite, bool)> {
+    let any = scene.get_objects()[object].as_any();
+    let write = match scene.gpu_slot(object)? {
+        GpuSlot::Sphere(index) => {
+            BufferWrite::elements(SceneBuffer::Sphere, index, &[sphere_to_gpu_sphere(any.downcast_ref::<Sphere>()?)])
+        }
+        GpuSlot::Plane(index) => {
+            BufferWrite::elements(SceneBuffer::Plane, index, &[plane_to_gpu_plane(any.downcast_ref::<Plane>()?)])
+        }
+        GpuSlot::Triangle(index) => {
+            BufferWrite::elements(SceneBuffer::Triangle, index, &[triangle_to_gpu_triangle(any.downcast_ref::<Triangle>()?)])
+        }
+        GpuSlot::Instance(index) => {
+            return Some((BufferWrite::elements(SceneBuffer::Instance, index, &[gpu_instance(&instances[index], gpu_scene)]), true));
+        }
+    };
+    Some((write, false))
+}
+
+const NO_TEXTURES: GpuTextureIds = GpuTextureIds { albedo: -1, roughness: -1, metallic: -1, _pad: 0 };
 
-    let mut spheres = primitives.0;
-    let mut triangles = primitives.1;
-    let mut planes = primitives.2;
+fn extract_scene_data(scene: &Scene) -> (Vec<GpuSphere>, Vec<GpuPlane>) {
+    let (mut spheres, mut planes) = scene.export_gpu_data();
 
     if spheres.is_empty() {
         spheres.push(GpuSphere {
@@ -257,23 +613,13 @@ fn extract_scene_data(scene: &Scene) -> (Vec<GpuSphere>, Vec<GpuTriangle>, Vec<G
             emission: 0.0,
             metallic: 0.0,
             roughness: 0.0,
-            _padding: [0.0, 0.0],
-        });
-    }
-
-    if triangles.is_empty() {
-        triangles.push(GpuTriangle {
-            v0: [0.0, 0.0, 0.0],
+            transmission: 0.0,
+            ior: 1.0,
+            textures: NO_TEXTURES,
+            emission_color: [0.0; 3],
             _pad0: 0.0,
-            v1: [0.0, 0.0, 0.0],
+            end_center: [0.0; 3],
             _pad1: 0.0,
-            v2: [0.0, 0.0, 0.0],
-            _pad2: 0.0,
-            albedo: [0.0, 0.0, 0.0],
-            emission: 0.0,
-            metallic: 0.0,
-            roughness: 0.0,
-            _padding: [0.0, 0.0],
         });
     }
 
@@ -283,63 +629,508 @@ fn extract_scene_data(scene: &Scene) -> (Vec<GpuSphere>, Vec<GpuTriangle>, Vec<G
             normal: [0.0, 1.0, 0.0, 0.0],
             width: 0.0,
             length: 0.0,
-            _pad2: [0.0, 0.0],
+            transmission: 0.0,
+            ior: 1.0,
             albedo: [0.0, 0.0, 0.0, 0.0],
             emission: 0.0,
             metallic: 0.0,
             roughness: 0.0,
             _pad3: 0.0,
+            textures: NO_TEXTURES,
+            emission_color: [0.0; 3],
+            _pad4: 0.0,
         });
     }
 
-    (spheres, triangles, planes)
+    (spheres, planes)
 }
 
-fn build_scene_bvh(scene: &Scene, triangles: &[GpuTriangle]) -> (Vec<GpuBVHNode>, Vec<u32>) {
-    use crate::objects::Triangle;
-    use crate::material::Material;
-    use crate::color::Color;
-    use glam::Vec3;
-
-    let cpu_triangles: Vec<Triangle> = triangles.iter().map(|t| {
-        Triangle::new(
-            Vec3::new(t.v0[0], t.v0[1], t.v0[2]),
-            Vec3::new(t.v1[0], t.v1[1], t.v1[2]),
-            Vec3::new(t.v2[0], t.v2[1], t.v2[2]),
-            Material::new(
-                Color::new(t.albedo[0], t.albedo[1], t.albedo[2]),
-                t.emission,
-                t.metallic,
-                t.roughness,
-            )
-        )
-    }).collect();
+fn with_dummy_triangle(mut triangles: Vec<GpuTriangle>) -> Vec<GpuTriangle> {
+    if triangles.is_empty() {
+        triangles.push(GpuTriangle {
+            v0: [0.0, 0.0, 0.0],
+            _pad0: 0.0,
+            v1: [0.0, 0.0, 0.0],
+            _pad1: 0.0,
+            v2: [0.0, 0.0, 0.0],
+            _pad2: 0.0,
+            albedo: [0.0, 0.0, 0.0],
+            emission: 0.0,
+            metallic: 0.0,
+            roughness: 0.0,
+            transmission: 0.0,
+            ior: 1.0,
+            uv0: [0.0; 2],
+            uv1: [0.0; 2],
+            uv2: [0.0; 2],
+            _pad3: [0.0; 2],
+            textures: NO_TEXTURES,
+            n0: [0.0, 1.0, 0.0],
+            _pad4: 0.0,
+            n1: [0.0, 1.0, 0.0],
+            _pad5: 0.0,
+            n2: [0.0, 1.0, 0.0],
+            _pad6: 0.0,
+            emission_color: [0.0; 3],
+            _pad7: 0.0,
+        });
+    }
+    triangles
+}
+
+/// Every distinct mesh's object-space triangles with its BVH, flattened into shared buffers.
+struct BottomLevel {
+    triangles: Vec<GpuTriangle>,
+    nodes: Vec<GpuBVHNode>,
+    indices: Vec<u32>,
+    /// Root node and bounds per BVH.
+    blas: Vec<(u32, AABB)>,
+    /// How much of the start of `nodes` and `indices` belongs to the loose primitives.
+    loose_node_count: usize,
+    loose_index_count: usize,
+}
 
+fn build_blas(scene: &Scene) -> BottomLevel {
+    let mut triangles = Vec::new();
     let mut all_nodes = Vec::new();
     let mut all_indices = Vec::new();
+    let mut blas = Vec::new();
+
+    if scene.has_loose_primitives() {
+        let (nodes, indices, aabb) = build_loose_blas(scene, triangles.len() as u32);
+        blas.push((0, aabb));
+        triangles.extend(scene.loose_triangles().iter().map(triangle_to_gpu_triangle));
+        all_nodes.extend(nodes);
+        all_indices.extend(indices);
+    }
+    let (loose_node_count, loose_index_count) = (all_nodes.len(), all_indices.len());
+
+    for mesh in scene.export_meshes() {
+        let built;
+        let bvh = match &mesh.bvh {
+            Some(bvh) => bvh,
+            None => {
+                built = construct_bvh(mesh, DEFAULT_LEAF_SIZE);
+                &built
+            }
+        };
+
+        let offsets = GpuBVHOffsets { triangle: triangles.len() as u32, node: all_nodes.len() as u32, index: all_indices.len() as u32 };
+        let (nodes, indices) = flatten_bvh_for_gpu(bvh, offsets);
+
+        blas.push((offsets.node, *bvh.root.aabb()));
+        triangles.extend(mesh.local_triangles().iter().map(triangle_to_gpu_triangle));
+        all_nodes.extend(nodes);
+        all_indices.extend(indices);
+    }
+
+    BottomLevel { triangles, nodes: all_nodes, indices: all_indices, blas, loose_node_count, loose_index_count }
+}
+
+/// One BVH over the triangles, spheres and planes that are not part of a mesh, placed at the start of the buffers.
+/// Its leaf entries are tagged with the primitive type, loose triangles start at `triangle_offset`.
+fn build_loose_blas(scene: &Scene, triangle_offset: u32) -> (Vec<GpuBVHNode>, Vec<u32>, AABB) {
+    let (bounds, centroids) = loose_primitive_bounds(scene);
+    let bvh = build_bvh_over_bounds(&bounds, &centroids, DEFAULT_LEAF_SIZE);
+    let (nodes, indices) = flatten_bvh_for_gpu(&bvh, GpuBVHOffsets::default());
+
+    let (triangle_count, sphere_count) = (scene.loose_triangles().len() as u32, scene.spheres().count() as u32);
+    let indices = indices.into_iter().map(|i| match i {
+        i if i < triangle_count => primitive_ref(PRIMITIVE_TRIANGLE, triangle_offset + i),
+        i if i < triangle_count + sphere_count => primitive_ref(PRIMITIVE_SPHERE, i - triangle_count),
+        i => primitive_ref(PRIMITIVE_PLANE, i - triangle_count - sphere_count),
+    }).collect();
+    (nodes, indices, *bvh.root.aabb())
+}
+
+/// Boxes and centroids of the loose triangles, then the spheres, then the planes.
+fn loose_primitive_bounds(scene: &Scene) -> (Vec<AABB>, Vec<Vec3>) {
+    let loose_triangles = scene.loose_triangles();
+    let mut bounds: Vec<AABB> = loose_triangles.iter().map(|triangle| triangle.to_aabb()).collect();
+    let mut centroids: Vec<Vec3> = loose_triangles.iter().map(|triangle| triangle.center()).collect();
+    bounds.extend(scene.spheres().map(|sphere| sphere.to_aabb()));
+    centroids.extend(scene.spheres().map(|sphere| sphere.center()));
+    // axis-aligned planes have flat boxes, the padding keeps rays lying in them from producing NaNs
+    bounds.extend(scene.planes().map(|plane| {
+        let aabb = plane.to_aabb();
+        AABB::new(aabb.min - Vec3::splat(1e-4), aabb.max + Vec3::splat(1e-4))
+    }));
+    centroids.extend(scene.planes().map(|plane| plane.center()));
+    (bounds, centroids)
+}
+
+/// Recomputes the boxes of the loose primitives' BVH bottom up, keeping its shape. Children always come after
+/// their parent in the flattened order so walking it backwards sees them first.
+fn refit_loose_blas(gpu_scene: &mut GpuScene, scene: &Scene) {
+    let (bounds, _) = loose_primitive_bounds(scene);
+    let (triangle_count, sphere_count) = (scene.loose_triangles().len(), scene.spheres().count());
+    let bound_of = |entry: u32| match unpack_primitive_ref(entry) {
+        (PRIMITIVE_TRIANGLE, index) => bounds[index as usize],
+        (PRIMITIVE_SPHERE, index) => bounds[triangle_count + index as usize],
+        (_, index) => bounds[triangle_count + sphere_count + index as usize],
+    };
+
+    let nodes = &mut gpu_scene.loose_nodes;
+    for i in (0..nodes.len()).rev() {
+        let node = nodes[i];
+        let aabb = if node.is_leaf == 1 {
+            let leaf = &gpu_scene.loose_indices[node.left_first as usize..(node.left_first + node.right_count) as usize];
+            leaf.iter().fold(AABB::empty(), |aabb, &entry| aabb.union(&bound_of(entry)))
+        } else {
+            node_aabb(&nodes[node.left_first as usize]).union(&node_aabb(&nodes[node.right_count as usize]))
+        };
+        nodes[i].min = aabb.min.to_array();
+        nodes[i].max = aabb.max.to_array();
+    }
+    gpu_scene.blas[0].1 = node_aabb(&nodes[0]);
+}
 
-    for object in scene.get_objects() {
-        if let Some(mesh) = object.as_any().downcast_ref::<Mesh>() {
-            let bvh = mesh.bvh.as_ref().unwrap();
-            let (nodes, indices) = flatten_bvh_for_gpu(&bvh, &cpu_triangles);
-            all_nodes.extend(nodes);
-            all_indices.extend(indices);
+fn node_aabb(node: &GpuBVHNode) -> AABB {
+    AABB::new(Vec3::from_array(node.min), Vec3::from_array(node.max))
+}
+
+/// Builds the instance buffer and the top-level BVH over the instances' world-space boxes, one instance per leaf.
+/// The nodes and indices are rebased to `gpu_scene.offsets`, the leaves index into the instance buffer.
+fn build_tlas(instances: &[SceneInstance], gpu_scene: &GpuScene) -> (Vec<GpuInstance>, Vec<GpuBVHNode>, Vec<u32>) {
+    let gpu_instances: Vec<GpuInstance> = instances.iter().map(|instance| gpu_instance(instance, gpu_scene)).collect();
+
+    if instances.is_empty() {
+        return (gpu_instances, Vec::new(), Vec::new());
+    }
+
+    let bounds: Vec<AABB> = instances.iter()
+        .map(|instance| motion_aabb(&gpu_scene.blas[instance.mesh].1, &instance.transform, instance.end_transform.as_ref()))
+        .collect();
+    let centroids: Vec<_> = bounds.iter().map(|aabb| (aabb.min + aabb.max) * 0.5).collect();
+    let tlas = build_bvh_over_bounds(&bounds, &centroids, 1);
+    let (nodes, indices) = flatten_bvh_for_gpu(&tlas, gpu_scene.offsets);
+    (gpu_instances, nodes, indices)
+}
+
+fn gpu_instance(instance: &SceneInstance, gpu_scene: &GpuScene) -> GpuInstance {
+    GpuInstance {
+        world_to_object: instance.transform.inverse().to_cols_array_2d(),
+        material: instance.material.as_ref().map_or(GpuMaterial::zeroed(), material_to_gpu_material),
+        blas_root: gpu_scene.blas[instance.mesh].0,
+        override_material: instance.material.is_some() as u32,
+        moving: instance.end_transform.is_some() as u32,
+        _pad0: 0,
+        start: gpu_pose(&instance.transform),
+        end: gpu_pose(&instance.end_transform.unwrap_or(instance.transform)),
+    }
+}
+
+fn gpu_pose(transform: &Mat4) -> GpuPose {
+    let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+    GpuPose { translation: translation.to_array(), _pad0: 0.0, rotation: rotation.to_array(), scale: scale.to_array(), _pad1: 0.0 }
+}
+
+/// The bottom levels together have to reach each triangle, sphere and plane once, and the top level each instance once.
+fn validate_scene_bvh(scene: &Scene, nodes: &[GpuBVHNode], indices: &[u32], instances: &[GpuInstance], gpu_scene: &GpuScene, triangle_count: usize) {
+    let roots: Vec<u32> = gpu_scene.blas.iter().map(|&(root, _)| root).collect();
+    let blas_indices = &indices[..gpu_scene.offsets.index as usize];
+    let counts = [triangle_count, scene.spheres().count(), scene.planes().count()];
+    // triangles, then spheres, then planes, anything out of range maps past the end
+    let slot = |entry: u32| {
+        let (kind, index) = unpack_primitive_ref(entry);
+        match counts.get(kind as usize) {
+            Some(&count) if (index as usize) < count => counts[..kind as usize].iter().sum::<usize>() + index as usize,
+            _ => usize::MAX,
         }
+    };
+    if let Err(err) = validate_flattened(nodes, blas_indices, &roots, counts.iter().sum(), slot) {
+        panic!("invalid bottom-level BVH: {}", err);
     }
+    if let Err(err) = validate_flattened(nodes, indices, &[gpu_scene.offsets.node], instances.len(), |entry| entry as usize) {
+        panic!("invalid top-level BVH: {}", err);
+    }
+}
 
-    if all_nodes.is_empty() {
-        all_nodes.push(GpuBVHNode {
-            min: [0.0; 3],
-            _pad0: 0.0,
-            max: [0.0; 3],
-            _pad1: 0.0,
-            left_first: 0,
-            right_count: 0,
-            is_leaf: 1,
-            _pad2: 0,
-        });
-        all_indices.push(0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Material;
+    use crate::scene_file::parse_scene;
+
+    const BOTH_MODELS: &str = r#"
+[[objects]]
+type = "mesh"
+path = "src/models/teapot.obj"
+position = [-100.0, 0.0, 0.0]
+
+[[objects]]
+type = "mesh"
+path = "src/models/standford_dragon.obj"
+position = [100.0, 0.0, 0.0]
+"#;
+
+    #[test]
+    fn each_model_is_hit_through_its_own_root() {
+        let scene = parse_scene(BOTH_MODELS, "both models").unwrap();
+        let buffers = build_scene_buffers(&scene, 4);
+        let meshes = scene.export_meshes();
+        let instances = scene.export_instances();
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(instances.len(), 2);
+
+        let mut first_triangle = 0;
+        for (mesh_index, mesh) in meshes.iter().enumerate() {
+            let triangles = mesh.local_triangles();
+            let range = first_triangle..first_triangle + triangles.len() as u32;
+            first_triangle = range.end;
+            let (instance_index, instance) = instances.iter().enumerate()
+                .find(|(_, instance)| instance.mesh == mesh_index)
+                .unwrap();
+
+            // aim at the triangle facing the ray the most, so the ray cannot slip past the mesh's silhouette
+            let facing = |triangle: &Triangle| {
+                let [v0, v1, v2] = triangle.get_vertices();
+                (v1 - v0).cross(v2 - v0).normalize_or_zero().z.abs()
+            };
+            let target = triangles.iter().max_by(|a, b| facing(a).total_cmp(&facing(b))).unwrap();
+            let [v0, v1, v2] = target.get_vertices();
+            let target = instance.transform.transform_point3((v0 + v1 + v2) / 3.0);
+
+            let direction = Vec3::new(0.001, 0.002, -1.0).normalize();
+            let origin = target - direction * 1000.0;
+            let (t, hit_instance, triangle) = trace(&buffers, origin, direction)
+                .unwrap_or_else(|| panic!("mesh {} was not hit", mesh_index));
+
+            assert_eq!(hit_instance, instance_index as u32, "mesh {}", mesh_index);
+            assert!(range.contains(&triangle), "mesh {} hit triangle {} outside {:?}", mesh_index, triangle, range);
+            assert!(t <= 1000.0 + 1.0e-2, "mesh {} hit behind its target", mesh_index);
+        }
+    }
+
+    const EDITABLE: &str = r#"
+[[objects]]
+type = "sphere"
+center = [0.0, 1.0, 0.0]
+radius = 1.0
+
+[[objects]]
+type = "sphere"
+center = [3.0, 4.0, 0.0]
+radius = 0.5
+material = { emission = 5.0 }
+
+[[objects]]
+type = "plane"
+center = [0.0, 0.0, 0.0]
+normal = [0.0, 1.0, 0.0]
+width = 10.0
+length = 10.0
+
+[[objects]]
+type = "triangle"
+vertices = [[-1.0, 0.0, -2.0], [1.0, 0.0, -2.0], [0.0, 2.0, -2.0]]
+
+[[objects]]
+type = "mesh"
+path = "src/models/teapot.obj"
+position = [-4.0, 0.0, 0.0]
+
+[[objects]]
+type = "mesh"
+path = "src/models/teapot.obj"
+position = [4.0, 0.0, 0.0]
+material = { albedo = [0.2, 0.4, 0.8] }
+"#;
+
+    #[test]
+    fn material_edits_match_a_fresh_upload() {
+        let (_, incremental, fresh) = edited(|scene| {
+            assert!(scene.set_material(0, Material::new(Color::new(0.9, 0.1, 0.1), 0.3, 0.0, 0.0)));
+            assert!(scene.set_material(1, Material::new(Color::white(), 1.0, 0.0, 8.0)));
+            assert!(scene.set_material(5, Material::new(Color::new(0.8, 0.8, 0.2), 0.2, 1.0, 0.0)));
+        }).expect("material edits are written in place");
+        assert_same(&incremental, &fresh);
+    }
+
+    #[test]
+    fn instance_moves_match_a_fresh_upload() {
+        let (_, incremental, fresh) = edited(|scene| {
+            assert!(scene.set_instance_transform(4, Mat4::from_translation(Vec3::new(-6.0, 2.0, 1.0))));
+            assert!(!scene.set_instance_transform(0, Mat4::IDENTITY));
+        }).expect("instance moves rebuild only the top level");
+        assert_same(&incremental, &fresh);
+    }
+
+    #[test]
+    fn moved_primitives_refit_their_bvh() {
+        let (scene, incremental, fresh) = edited(|scene| {
+            let moved = Sphere::new(Vec3::new(-2.0, 6.0, 1.0), 0.5, Material::new(Color::white(), 1.0, 0.0, 5.0));
+            assert!(scene.replace_object(1, Box::new(moved)).is_some());
+            let triangle = Triangle::new(Vec3::new(-1.0, 0.0, 3.0), Vec3::new(1.0, 0.0, 3.0), Vec3::new(0.0, 3.0, 3.0),
+                                         Material::default());
+            assert!(scene.replace_object(3, Box::new(triangle)).is_some());
+        }).expect("moved loose primitives refit in place");
+
+        // a refit keeps the BVH's shape where a fresh build may pick another one, so the BVH is checked on its own
+        assert!(bytes(&incremental.spheres) == bytes(&fresh.spheres), "spheres differ");
+        assert!(bytes(&incremental.triangles) == bytes(&fresh.triangles), "triangles differ");
+        assert!(bytes(&incremental.emitters) == bytes(&fresh.emitters), "emitters differ");
+        assert_eq!(incremental.gpu_scene.blas[0].1.min, fresh.gpu_scene.blas[0].1.min);
+        assert_eq!(incremental.gpu_scene.blas[0].1.max, fresh.gpu_scene.blas[0].1.max);
+
+        let (bounds, _) = loose_primitive_bounds(&scene);
+        let (triangle_count, sphere_count) = (scene.loose_triangles().len(), scene.spheres().count());
+        for node in &incremental.gpu_scene.loose_nodes {
+            if node.is_leaf == 0 {
+                continue;
+            }
+            for &entry in &incremental.gpu_scene.loose_indices[node.left_first as usize..(node.left_first + node.right_count) as usize] {
+                let bound = match unpack_primitive_ref(entry) {
+                    (PRIMITIVE_TRIANGLE, index) => bounds[index as usize],
+                    (PRIMITIVE_SPHERE, index) => bounds[triangle_count + index as usize],
+                    (_, index) => bounds[triangle_count + sphere_count + index as usize],
+                };
+                let aabb = node_aabb(node);
+                assert!(aabb.min.cmple(bound.min).all() && aabb.max.cmpge(bound.max).all(), "a leaf does not contain its primitive");
+            }
+        }
+        validate_scene_bvh(&scene, &incremental.bvh_nodes, &incremental.bvh_indices, &incremental.instances,
+                           &incremental.gpu_scene, incremental.triangles.len());
+    }
+
+    #[test]
+    fn structural_edits_upload_again() {
+        assert!(edited(|scene| assert!(scene.remove_object(2).is_some())).is_none());
+        assert!(edited(|scene| {
+            let plane = Plane::new(Vec3::ZERO, Vec3::Z, 1.0, 1.0, Material::default());
+            assert!(scene.replace_object(0, Box::new(plane)).is_some());
+        }).is_none());
+        // turning the only emitter off changes the number of emitters
+        assert!(edited(|scene| assert!(scene.set_material(1, Material::default()))).is_none());
     }
 
-    (all_nodes, all_indices)
-}
\ No newline at end of file
+    #[test]
+    fn reloading_changes_only_what_differs() {
+        let mut scene = parse_scene(EDITABLE, "editable").unwrap();
+        scene.take_changes();
+        scene.update_from(parse_scene(EDITABLE, "editable").unwrap());
+        assert!(scene.take_changes().is_empty());
+
+        let reloaded = EDITABLE
+            .replace("position = [4.0, 0.0, 0.0]", "position = [5.0, 1.0, 0.0]")
+            .replace("radius = 1.0", "radius = 1.5");
+        scene.update_from(parse_scene(&reloaded, "reloaded").unwrap());
+        let changes = scene.take_changes();
+        assert!(!changes.structure && changes.transforms);
+        assert_eq!(changes.geometry, [0]);
+        assert!(changes.materials.is_empty());
+
+        let (_, incremental, fresh) = edited(|scene| scene.update_from(parse_scene(&reloaded, "reloaded").unwrap()))
+            .expect("a moved instance and a resized sphere are written in place");
+        assert!(bytes(&incremental.spheres) == bytes(&fresh.spheres), "spheres differ");
+        assert!(bytes(&incremental.instances) == bytes(&fresh.instances), "instances differ");
+    }
+
+    /// Builds the buffers for `EDITABLE`, edits the scene and applies the incremental writes to them. Returns the
+    /// edited scene, those buffers and ones built from scratch after the edit, or `None` when the edit uploads
+    /// everything again.
+    fn edited(edit: impl FnOnce(&mut Scene)) -> Option<(Scene, SceneBuffers, SceneBuffers)> {
+        let mut scene = parse_scene(EDITABLE, "editable").unwrap();
+        let mut buffers = build_scene_buffers(&scene, 4);
+        scene.take_changes();
+
+        edit(&mut scene);
+        let changes = scene.take_changes();
+        assert!(!changes.is_empty());
+        for write in scene_writes(&mut buffers.gpu_scene, &scene, &changes)? {
+            let target: &mut [u8] = match write.buffer {
+                SceneBuffer::Sphere => bytemuck::cast_slice_mut(&mut buffers.spheres),
+                SceneBuffer::Plane => bytemuck::cast_slice_mut(&mut buffers.planes),
+                SceneBuffer::Triangle => bytemuck::cast_slice_mut(&mut buffers.triangles),
+                SceneBuffer::BvhNode => bytemuck::cast_slice_mut(&mut buffers.bvh_nodes),
+                SceneBuffer::BvhIndex => bytemuck::cast_slice_mut(&mut buffers.bvh_indices),
+                SceneBuffer::Instance => bytemuck::cast_slice_mut(&mut buffers.instances),
+                SceneBuffer::Emitter => bytemuck::cast_slice_mut(&mut buffers.emitters),
+                SceneBuffer::Counts => bytemuck::bytes_of_mut(&mut buffers.counts),
+            };
+            let offset = write.offset as usize;
+            target[offset..offset + write.data.len()].copy_from_slice(&write.data);
+        }
+
+        let fresh = build_scene_buffers(&scene, 4);
+        Some((scene, buffers, fresh))
+    }
+
+    fn assert_same(incremental: &SceneBuffers, fresh: &SceneBuffers) {
+        assert!(bytes(&incremental.spheres) == bytes(&fresh.spheres), "spheres differ");
+        assert!(bytes(&incremental.planes) == bytes(&fresh.planes), "planes differ");
+        assert!(bytes(&incremental.triangles) == bytes(&fresh.triangles), "triangles differ");
+        assert!(bytes(&incremental.bvh_nodes) == bytes(&fresh.bvh_nodes), "BVH nodes differ");
+        assert!(bytes(&incremental.bvh_indices) == bytes(&fresh.bvh_indices), "BVH indices differ");
+        assert!(bytes(&incremental.instances) == bytes(&fresh.instances), "instances differ");
+        assert!(bytes(&incremental.emitters) == bytes(&fresh.emitters), "emitters differ");
+        assert!(bytemuck::bytes_of(&incremental.counts) == bytemuck::bytes_of(&fresh.counts), "counts differ");
+    }
+
+    fn bytes<T: Pod>(elements: &[T]) -> &[u8] {
+        bytemuck::cast_slice(elements)
+    }
+
+    /// Walks the flattened TLAS and every BLAS it leads to, like `traverse_tlas` in hit.wgsl. Returns the
+    /// distance, instance and triangle of the closest hit.
+    fn trace(buffers: &SceneBuffers, origin: Vec3, direction: Vec3) -> Option<(f32, u32, u32)> {
+        let mut closest: Option<(f32, u32, u32)> = None;
+        for instance_index in leaf_entries(buffers, buffers.counts.tlas_root, origin, direction) {
+            let instance = &buffers.instances[instance_index as usize];
+            let world_to_object = Mat4::from_cols_array_2d(&instance.world_to_object);
+            let local_origin = world_to_object.transform_point3(origin);
+            let local_direction = world_to_object.transform_vector3(direction);
+
+            for entry in leaf_entries(buffers, instance.blas_root, local_origin, local_direction) {
+                let (kind, triangle) = unpack_primitive_ref(entry);
+                assert_eq!(kind, PRIMITIVE_TRIANGLE);
+                let Some(t) = hit_triangle(&buffers.triangles[triangle as usize], local_origin, local_direction) else {
+                    continue;
+                };
+                if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                    closest = Some((t, instance_index, triangle));
+                }
+            }
+        }
+        closest
+    }
+
+    fn leaf_entries(buffers: &SceneBuffers, root: u32, origin: Vec3, direction: Vec3) -> Vec<u32> {
+        let mut entries = Vec::new();
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            let node = buffers.bvh_nodes[index as usize];
+            let near = (Vec3::from_array(node.min) - origin) / direction;
+            let far = (Vec3::from_array(node.max) - origin) / direction;
+            let (t_enter, t_exit) = (near.min(far).max_element(), near.max(far).min_element());
+            if t_enter > t_exit || t_exit < 0.0 {
+                continue;
+            }
+
+            if node.is_leaf == 1 {
+                entries.extend_from_slice(&buffers.bvh_indices[node.left_first as usize..(node.left_first + node.right_count) as usize]);
+            } else {
+                stack.extend([node.left_first, node.right_count]);
+            }
+        }
+        entries
+    }
+
+    fn hit_triangle(triangle: &GpuTriangle, origin: Vec3, direction: Vec3) -> Option<f32> {
+        let v0 = Vec3::from_array(triangle.v0);
+        let edge1 = Vec3::from_array(triangle.v1) - v0;
+        let edge2 = Vec3::from_array(triangle.v2) - v0;
+        let p = direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < 1.0e-12 {
+            return None;
+        }
+        let offset = origin - v0;
+        let u = offset.dot(p) / det;
+        let q = offset.cross(edge1);
+        let v = direction.dot(q) / det;
+        let t = edge2.dot(q) / det;
+        (u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > 0.0).then_some(t)
+    }
+}
@@ -0,0 +1,186 @@
+use std::f32::consts::PI;
+use glam::{Vec2, Vec3};
+use crate::color::Color;
+use crate::gpu_types::GpuEmitter;
+use crate::instance::Instance;
+use crate::model::Mesh;
+use crate::objects::{Plane, Sphere, Triangle};
+use crate::scene::Scene;
+
+// matches the EMITTER_ constants in raytracer.wgsl
+const EMITTER_SPHERE: u32 = 0;
+const EMITTER_PLANE: u32 = 1;
+const EMITTER_TRIANGLE: u32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub enum EmitterShape {
+    Sphere { center: Vec3, end_center: Vec3, radius: f32 },
+    /// `u` and `v` run from the centre to the edges, half the width and length long.
+    Plane { center: Vec3, u: Vec3, v: Vec3 },
+    Triangle { v0: Vec3, v1: Vec3, v2: Vec3 },
+}
+
+/// An emissive primitive in world space. Emitters shine from both sides, like hitting them does.
+#[derive(Debug, Clone, Copy)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    pub radiance: Color,
+    pub area: f32,
+}
+
+impl Emitter {
+    fn new(shape: EmitterShape, radiance: Color) -> Self {
+        let area = match shape {
+            EmitterShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            EmitterShape::Plane { u, v, .. } => 4.0 * u.length() * v.length(),
+            EmitterShape::Triangle { v0, v1, v2 } => 0.5 * (v1 - v0).cross(v2 - v0).length(),
+        };
+        Self { shape, radiance, area }
+    }
+
+    /// Light sampling picks emitters in proportion to this.
+    pub fn power(&self) -> f32 {
+        self.radiance.luminance() * self.area
+    }
+
+    /// A point spread uniformly over the surface at `time` and the surface normal there.
+    pub fn sample_point(&self, u: Vec2, time: f32) -> (Vec3, Vec3) {
+        match self.shape {
+            EmitterShape::Sphere { center, end_center, radius } => {
+                let z = 1.0 - 2.0 * u.x;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.y;
+                let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                (center.lerp(end_center, time) + normal * radius, normal)
+            }
+            EmitterShape::Plane { center, u: u_axis, v: v_axis } => {
+                let point = center + u_axis * (2.0 * u.x - 1.0) + v_axis * (2.0 * u.y - 1.0);
+                (point, u_axis.cross(v_axis).normalize())
+            }
+            EmitterShape::Triangle { v0, v1, v2 } => {
+                let su = u.x.sqrt();
+                let b0 = 1.0 - su;
+                let b1 = u.y * su;
+                let point = v0 * b0 + v1 * b1 + v2 * (1.0 - b0 - b1);
+                (point, (v1 - v0).cross(v2 - v0).normalize())
+            }
+        }
+    }
+
+    fn gpu_emitter(&self, cdf: f32) -> GpuEmitter {
+        let (kind, p0, p1, p2, radius) = match self.shape {
+            EmitterShape::Sphere { center, end_center, radius } => (EMITTER_SPHERE, center, end_center, Vec3::ZERO, radius),
+            EmitterShape::Plane { center, u, v } => (EMITTER_PLANE, center, u, v, 0.0),
+            EmitterShape::Triangle { v0, v1, v2 } => (EMITTER_TRIANGLE, v0, v1, v2, 0.0),
+        };
+        GpuEmitter {
+            p0: p0.to_array(),
+            kind,
+            p1: p1.to_array(),
+            radius,
+            p2: p2.to_array(),
+            cdf,
+            radiance: [self.radiance.r, self.radiance.g, self.radiance.b],
+            _pad0: 0.0,
+        }
+    }
+}
+
+/// Every emissive primitive of a scene, for next-event estimation. Triangles of moving instances are left out, hits on
+/// them keep the full weight of the BRDF sample.
+pub struct EmitterList {
+    emitters: Vec<Emitter>,
+    /// Running sum of the powers, normalised so the last entry is 1.
+    cdf: Vec<f32>,
+    total_power: f32,
+}
+
+impl EmitterList {
+    pub fn from_scene(scene: &Scene) -> Self {
+        let mut emitters = Vec::new();
+        let add_triangle = |emitters: &mut Vec<Emitter>, triangle: &Triangle| {
+            let material = triangle.material();
+            if material.emission > 0.0 && triangle.area() > 0.0 {
+                let [v0, v1, v2] = triangle.get_vertices();
+                emitters.push(Emitter::new(EmitterShape::Triangle { v0, v1, v2 }, material.emitted()));
+            }
+        };
+
+        for object in scene.get_objects() {
+            let any = object.as_any();
+            if let Some(sphere) = any.downcast_ref::<Sphere>() {
+                if sphere.material().emission > 0.0 {
+                    let shape = EmitterShape::Sphere { center: sphere.center(), end_center: sphere.end_center(), radius: sphere.radius() };
+                    emitters.push(Emitter::new(shape, sphere.material().emitted()));
+                }
+            } else if let Some(plane) = any.downcast_ref::<Plane>() {
+                if plane.material().emission > 0.0 {
+                    let (u, v) = plane.axes();
+                    let shape = EmitterShape::Plane { center: plane.center(), u: u * plane.width() * 0.5, v: v * plane.length() * 0.5 };
+                    emitters.push(Emitter::new(shape, plane.material().emitted()));
+                }
+            } else if let Some(triangle) = any.downcast_ref::<Triangle>() {
+                add_triangle(&mut emitters, triangle);
+            } else if let Some(instance) = any.downcast_ref::<Instance>() {
+                let emissive = match instance.material_override() {
+                    Some(material) => material.emission > 0.0,
+                    None => instance.mesh().faces.iter().any(|face| face.material.emission > 0.0),
+                };
+                if emissive && instance.end_transform().is_none() {
+                    instance.triangles().iter().for_each(|triangle| add_triangle(&mut emitters, triangle));
+                }
+            } else if let Some(mesh) = any.downcast_ref::<Mesh>()
+                && mesh.motion.is_none() && mesh.faces.iter().any(|face| face.material.emission > 0.0) {
+                mesh.get_triangles().iter().for_each(|triangle| add_triangle(&mut emitters, triangle));
+            }
+        }
+
+        // black emission colours can't be picked, and would leave nothing to normalise the CDF with
+        emitters.retain(|emitter| emitter.power() > 0.0);
+        let total_power: f32 = emitters.iter().map(Emitter::power).sum();
+        let mut sum = 0.0;
+        let cdf = emitters.iter().map(|emitter| {
+            sum += emitter.power();
+            sum / total_power
+        }).collect();
+        Self { emitters, cdf, total_power }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emitters.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.emitters.len()
+    }
+
+    pub fn total_power(&self) -> f32 {
+        self.total_power
+    }
+
+    /// Picks an emitter in proportion to its power, the same search as `pick_emitter` in raytracer.wgsl.
+    pub fn pick(&self, u: f32) -> Option<&Emitter> {
+        let index = self.cdf.partition_point(|&cdf| cdf <= u);
+        self.emitters.get(index.min(self.emitters.len().saturating_sub(1)))
+    }
+
+    /// Solid angle density of light sampling reaching a point with `radiance`, `distance` away and seen at `cos_light`
+    /// to its normal. Picking by power and then by area leaves the emitter's area out of it entirely.
+    pub fn pdf(&self, radiance: Color, distance: f32, cos_light: f32) -> f32 {
+        if self.total_power <= 0.0 || cos_light <= 0.0 {
+            return 0.0;
+        }
+        radiance.luminance() / self.total_power * distance * distance / cos_light
+    }
+
+    pub fn to_gpu(&self) -> Vec<GpuEmitter> {
+        self.emitters.iter().zip(&self.cdf).map(|(emitter, &cdf)| emitter.gpu_emitter(cdf)).collect()
+    }
+}
+
+/// Veach's power heuristic with an exponent of two, the weight of the strategy that had `pdf`.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b <= 0.0 { 0.0 } else { a / (a + b) }
+}
@@ -0,0 +1,445 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use glam::{Vec2, Vec3};
+use crate::color::Color;
+use crate::gpu_types::GpuEnvironment;
+use crate::light::Light;
+use crate::sky::Sky;
+
+// matches the ENVIRONMENT_ constants in environment.wgsl
+const ENVIRONMENT_CONSTANT: u32 = 0;
+const ENVIRONMENT_GRADIENT: u32 = 1;
+const ENVIRONMENT_MAP: u32 = 2;
+const ENVIRONMENT_SKY: u32 = 3;
+
+/// Larger maps than 16k by 8k are refused before anything gets allocated for them.
+const MAX_HDR_TEXELS: u32 = 1 << 27;
+
+/// What rays that leave the scene see.
+#[derive(Debug, Clone)]
+pub enum Background {
+    Constant(Color),
+    /// Blends from `horizon` up to `zenith` and down to `ground` with the height of the direction.
+    Gradient { zenith: Color, horizon: Color, ground: Color },
+    Map(EnvironmentMap),
+    /// Daylight for the sky's sun, which also shines as a directional light.
+    Sky(Sky),
+}
+
+/// Light arriving from infinitely far away in every direction, scaled by `intensity`. `rotation` turns the map around
+/// +Y, in radians.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub background: Background,
+    pub intensity: f32,
+    pub rotation: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new(Background::Constant(Color::black()))
+    }
+}
+
+impl Environment {
+    pub fn new(background: Background) -> Self {
+        Self { background, intensity: 1.0, rotation: 0.0 }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Radiance arriving from `direction`, the same as `environment_radiance` in environment.wgsl.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let direction = direction.normalize();
+        let radiance = match &self.background {
+            Background::Constant(color) => *color,
+            Background::Gradient { zenith, horizon, ground } => {
+                if direction.y >= 0.0 {
+                    crate::color::lerp(horizon, zenith, direction.y)
+                } else {
+                    crate::color::lerp(horizon, ground, -direction.y)
+                }
+            }
+            Background::Map(map) => map.lookup(direction_to_uv(direction, self.rotation)),
+            Background::Sky(sky) => sky.radiance(direction),
+        };
+        radiance * self.intensity
+    }
+
+    /// Whether light sampling picks directions from the environment. Only maps are worth it, smooth skies are found
+    /// just as well by the BRDF.
+    pub fn is_sampled(&self) -> bool {
+        matches!(&self.background, Background::Map(map) if map.sampled) && self.intensity > 0.0
+    }
+
+    /// The sun of a sky as a light, lit as brightly as the sky around it.
+    pub fn sun_light(&self) -> Option<Light> {
+        match &self.background {
+            Background::Sky(sky) => sky.sun_light(self.intensity),
+            _ => None,
+        }
+    }
+
+    /// A direction picked in proportion to the map's brightness, its radiance and solid angle density.
+    pub fn sample(&self, u: Vec2) -> Option<(Vec3, Color, f32)> {
+        let Background::Map(map) = &self.background else {
+            return None;
+        };
+        let (uv, pdf) = map.sample(u)?;
+        let direction = uv_to_direction(uv, self.rotation);
+        Some((direction, self.radiance(direction), pdf))
+    }
+
+    /// Solid angle density `sample` picks `direction` with.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match &self.background {
+            Background::Map(map) if map.sampled => map.pdf(direction_to_uv(direction.normalize(), self.rotation)),
+            _ => 0.0,
+        }
+    }
+
+    pub fn gpu_environment(&self) -> GpuEnvironment {
+        let mut environment = GpuEnvironment {
+            kind: ENVIRONMENT_CONSTANT,
+            width: 0,
+            height: 0,
+            intensity: self.intensity,
+            rotation: self.rotation,
+            sampled: self.is_sampled() as u32,
+            _pad0: [0; 2],
+            color: [0.0; 3],
+            _pad1: 0.0,
+            horizon: [0.0; 3],
+            _pad2: 0.0,
+            ground: [0.0; 3],
+            _pad3: 0.0,
+            sun_direction: [0.0; 3],
+            sky_scale: 0.0,
+            perez: [[0.0; 4]; 5],
+            zenith: [0.0; 3],
+            _pad4: 0.0,
+        };
+        match &self.background {
+            Background::Constant(color) => environment.color = [color.r, color.g, color.b],
+            Background::Gradient { zenith, horizon, ground } => {
+                environment.kind = ENVIRONMENT_GRADIENT;
+                environment.color = [zenith.r, zenith.g, zenith.b];
+                environment.horizon = [horizon.r, horizon.g, horizon.b];
+                environment.ground = [ground.r, ground.g, ground.b];
+            }
+            Background::Map(map) => {
+                environment.kind = ENVIRONMENT_MAP;
+                environment.width = map.width;
+                environment.height = map.height;
+            }
+            Background::Sky(sky) => {
+                let (perez, zenith, sun_direction, sky_scale) = sky.gpu_coefficients();
+                environment.kind = ENVIRONMENT_SKY;
+                environment.sun_direction = sun_direction;
+                environment.sky_scale = sky_scale;
+                environment.perez = perez.map(|[luminance, x, y]| [luminance, x, y, 0.0]);
+                environment.zenith = zenith;
+            }
+        }
+        environment
+    }
+
+    /// The map's RGBE texels, then the row CDF, then every row's column CDF as float bits. A single word for the other
+    /// backgrounds, buffers can't be empty.
+    pub fn gpu_data(&self) -> Vec<u32> {
+        let Background::Map(map) = &self.background else {
+            return vec![0];
+        };
+        map.texels.iter().map(|&texel| u32::from_le_bytes(texel))
+            .chain(map.marginal.iter().chain(&map.conditional).map(|cdf| cdf.to_bits()))
+            .collect()
+    }
+}
+
+/// A lat-long HDR image with +Y at the top row and -Z in the middle, kept in RGBE like the file it came from. Rows and
+/// columns get picked by luminance, weighted by how much of the sphere each row covers.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    pub source: Option<String>,
+    width: u32,
+    height: u32,
+    texels: Vec<[u8; 4]>,
+    /// Running sum over the rows, normalised so the last entry is 1.
+    marginal: Vec<f32>,
+    /// The same over the columns of each row, `width` entries per row.
+    conditional: Vec<f32>,
+    /// False when the whole map is black and there is nothing to pick.
+    sampled: bool,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: u32, height: u32, texels: Vec<[u8; 4]>) -> std::io::Result<Self> {
+        let (w, h) = (width as usize, height as usize);
+        if w.checked_mul(h) != Some(texels.len()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "environment map size does not match its texel count"));
+        }
+        let mut conditional = vec![0.0; w * h];
+        let mut row_sums = vec![0.0; h];
+
+        for row in 0..h {
+            let sin_theta = (PI * (row as f32 + 0.5) / h as f32).sin();
+            let cdf = &mut conditional[row * w..(row + 1) * w];
+            let mut sum = 0.0;
+            for (column, entry) in cdf.iter_mut().enumerate() {
+                sum += decode_rgbe(texels[row * w + column]).luminance() * sin_theta;
+                *entry = sum;
+            }
+            row_sums[row] = sum;
+            normalise_cdf(cdf, sum);
+        }
+
+        let mut sum = 0.0;
+        let mut marginal: Vec<f32> = row_sums.iter().map(|row_sum| {
+            sum += row_sum;
+            sum
+        }).collect();
+        normalise_cdf(&mut marginal, sum);
+
+        Ok(Self { source: None, width, height, texels, marginal, conditional, sampled: sum > 0.0 })
+    }
+
+    /// Reads a Radiance `.hdr` file, flat or with run-length encoded scanlines.
+    pub fn load_hdr(path: &str) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (width, height) = read_hdr_header(&mut reader)?;
+
+        let mut texels = Vec::with_capacity(width as usize * height as usize);
+        for _ in 0..height {
+            texels.extend(read_hdr_scanline(&mut reader, width as usize)?);
+        }
+
+        let mut map = Self::new(width, height, texels)?;
+        map.source = Some(path.to_string());
+        Ok(map)
+    }
+
+    /// Bilinear lookup, wrapping around horizontally and clamped at the poles. Matches `environment_lookup` in
+    /// environment.wgsl.
+    fn lookup(&self, uv: Vec2) -> Color {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn texel(&self, x: i32, y: i32) -> Color {
+        let x = x.rem_euclid(self.width as i32) as u32;
+        let y = y.clamp(0, self.height as i32 - 1) as u32;
+        decode_rgbe(self.texels[(y * self.width + x) as usize])
+    }
+
+    fn sample(&self, u: Vec2) -> Option<(Vec2, f32)> {
+        if !self.sampled {
+            return None;
+        }
+        let w = self.width as usize;
+        let (row, v_offset, row_pdf) = sample_cdf(&self.marginal, u.y);
+        let (column, u_offset, column_pdf) = sample_cdf(&self.conditional[row * w..(row + 1) * w], u.x);
+        let uv = Vec2::new((column as f32 + u_offset) / self.width as f32, (row as f32 + v_offset) / self.height as f32);
+
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        Some((uv, row_pdf * column_pdf / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, uv: Vec2) -> f32 {
+        let sin_theta = (PI * uv.y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let w = self.width as usize;
+        let row = ((uv.y * self.height as f32) as usize).min(self.height as usize - 1);
+        let column = ((uv.x * self.width as f32) as usize).min(w - 1);
+        let row_pdf = cdf_step(&self.marginal, row);
+        let column_pdf = cdf_step(&self.conditional[row * w..(row + 1) * w], column);
+        row_pdf * column_pdf / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// Where `direction` lands on a lat-long map turned by `rotation` around +Y, the inverse of `uv_to_direction`.
+fn direction_to_uv(direction: Vec3, rotation: f32) -> Vec2 {
+    let phi = direction.x.atan2(-direction.z);
+    let u = ((phi - rotation) / (2.0 * PI) + 0.5).rem_euclid(1.0);
+    Vec2::new(u, direction.y.clamp(-1.0, 1.0).acos() / PI)
+}
+
+fn uv_to_direction(uv: Vec2, rotation: f32) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI + rotation;
+    let theta = uv.y * PI;
+    Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+/// Radiance's own reconstruction, from the middle of the mantissa's step.
+fn decode_rgbe([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::black();
+    }
+    let scale = 2f32.powi(e as i32 - 136);
+    Color::new((r as f32 + 0.5) * scale, (g as f32 + 0.5) * scale, (b as f32 + 0.5) * scale)
+}
+
+/// Divides by the total, or spreads the entries evenly when there is nothing to divide by.
+fn normalise_cdf(cdf: &mut [f32], sum: f32) {
+    let count = cdf.len() as f32;
+    for (i, entry) in cdf.iter_mut().enumerate() {
+        *entry = if sum > 0.0 { *entry / sum } else { (i + 1) as f32 / count };
+    }
+}
+
+/// The first entry above `u`, how far into it `u` falls and the chance of picking it, like `environment_sample_cdf` in
+/// environment.wgsl.
+fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32, f32) {
+    let index = cdf.partition_point(|&entry| entry <= u).min(cdf.len() - 1);
+    let start = if index == 0 { 0.0 } else { cdf[index - 1] };
+    let step = cdf[index] - start;
+    let offset = if step > 0.0 { ((u - start) / step).clamp(0.0, 1.0) } else { 0.5 };
+    (index, offset, step * cdf.len() as f32)
+}
+
+/// Density of picking `index`, relative to picking evenly.
+fn cdf_step(cdf: &[f32], index: usize) -> f32 {
+    let start = if index == 0 { 0.0 } else { cdf[index - 1] };
+    (cdf[index] - start) * cdf.len() as f32
+}
+
+fn read_hdr_header(reader: &mut impl BufRead) -> std::io::Result<(u32, u32)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("header ends before the image size"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") && format != "32-bit_rle_rgbe" {
+            return Err(invalid(&format!("unsupported format '{}', only 32-bit_rle_rgbe is", format)));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["-Y", height, "+X", width] => {
+            let parse = |value: &str| value.parse::<u32>().ok().filter(|&value| value > 0);
+            let (width, height) = parse(width).zip(parse(height)).ok_or_else(|| invalid("invalid image size"))?;
+            match width.checked_mul(height) {
+                Some(texels) if texels <= MAX_HDR_TEXELS => Ok((width, height)),
+                _ => Err(invalid(&format!("image size {}x{} is too large", width, height))),
+            }
+        }
+        _ => Err(invalid(&format!("unsupported image orientation '{}', only -Y h +X w is", line.trim()))),
+    }
+}
+
+fn read_hdr_scanline(reader: &mut impl Read, width: usize) -> std::io::Result<Vec<[u8; 4]>> {
+    let mut start = [0u8; 4];
+    reader.read_exact(&mut start)?;
+
+    // anything but the run-length marker is the first of `width` flat texels
+    let encoded = (8..=0x7fff).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+    if !encoded {
+        let mut texels = vec![start; width];
+        for texel in texels.iter_mut().skip(1) {
+            reader.read_exact(texel)?;
+        }
+        return Ok(texels);
+    }
+    if ((start[2] as usize) << 8 | start[3] as usize) != width {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "scanline width does not match the image"));
+    }
+
+    let mut texels = vec![[0u8; 4]; width];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let (run, count) = (count[0] > 128, if count[0] > 128 { count[0] as usize - 128 } else { count[0] as usize });
+            if count == 0 || x + count > width {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "run overflows the scanline"));
+            }
+
+            if run {
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                texels[x..x + count].iter_mut().for_each(|texel| texel[channel] = value[0]);
+            } else {
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                texels[x..x + count].iter_mut().zip(values).for_each(|(texel, value)| texel[channel] = value);
+            }
+            x += count;
+        }
+    }
+    Ok(texels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    fn header(size: &str) -> String {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", size)
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        for size in ["-Y 70000 +X 70000", "-Y 8193 +X 16384", "-Y 4294967295 +X 2"] {
+            let err = read_hdr_header(&mut Cursor::new(header(size))).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", size);
+        }
+        assert_eq!(read_hdr_header(&mut Cursor::new(header("-Y 8192 +X 16384"))).unwrap(), (16384, 8192));
+    }
+
+    #[test]
+    fn texel_counts_must_match_the_size() {
+        let texel = [128, 128, 128, 129];
+        assert!(EnvironmentMap::new(8, 4, vec![texel; 32]).is_ok());
+        assert_eq!(EnvironmentMap::new(8, 4, vec![texel; 31]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(EnvironmentMap::new(u32::MAX, u32::MAX, Vec::new()).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn images_shorter_than_their_header_are_rejected() {
+        let path = std::env::temp_dir().join(format!("truncated-{}.hdr", std::process::id()));
+        let mut contents = header("-Y 4 +X 8").into_bytes();
+        contents.extend([128, 128, 128, 129].repeat(8 * 2));
+        std::fs::write(&path, contents).unwrap();
+
+        let result = EnvironmentMap::load_hdr(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -9,7 +9,13 @@ pub struct GpuSphere {
     pub(crate) emission: f32,
     pub(crate) metallic: f32,
     pub(crate) roughness: f32,
-    pub(crate) _padding: [f32; 2],
+    pub(crate) transmission: f32,
+    pub(crate) ior: f32,
+    pub(crate) textures: GpuTextureIds,
+    pub(crate) emission_color: [f32; 3],
+    pub(crate) _pad0: f32,
+    pub(crate) end_center: [f32; 3],
+    pub(crate) _pad1: f32,
 }
 
 #[repr(C)]
@@ -25,7 +31,21 @@ pub struct GpuTriangle {
     pub(crate) emission: f32,
     pub(crate) metallic: f32,
     pub(crate) roughness: f32,
-    pub(crate) _padding: [f32; 2],
+    pub(crate) transmission: f32,
+    pub(crate) ior: f32,
+    pub(crate) uv0: [f32; 2],
+    pub(crate) uv1: [f32; 2],
+    pub(crate) uv2: [f32; 2],
+    pub(crate) _pad3: [f32; 2],
+    pub(crate) textures: GpuTextureIds,
+    pub(crate) n0: [f32; 3],
+    pub(crate) _pad4: f32,
+    pub(crate) n1: [f32; 3],
+    pub(crate) _pad5: f32,
+    pub(crate) n2: [f32; 3],
+    pub(crate) _pad6: f32,
+    pub(crate) emission_color: [f32; 3],
+    pub(crate) _pad7: f32,
 }
 
 #[repr(C)]
@@ -35,36 +55,189 @@ pub struct GpuPlane {
     pub(crate) normal: [f32; 4],
     pub(crate) width: f32,
     pub(crate) length: f32,
-    pub(crate) _pad2: [f32; 2],
+    pub(crate) transmission: f32,
+    pub(crate) ior: f32,
     pub(crate) albedo: [f32; 4],
     pub(crate) emission: f32,
     pub(crate) metallic: f32,
     pub(crate) roughness: f32,
     pub(crate) _pad3: f32,
+    pub(crate) textures: GpuTextureIds,
+    pub(crate) emission_color: [f32; 3],
+    pub(crate) _pad4: f32,
 }
 
+/// Material an instance puts on every triangle of its mesh.
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
-pub struct GpuRay {
-    pub(crate) origin: [f32; 3],
+pub struct GpuMaterial {
+    pub(crate) albedo: [f32; 3],
+    pub(crate) emission: f32,
+    pub(crate) emission_color: [f32; 3],
+    pub(crate) metallic: f32,
+    pub(crate) roughness: f32,
+    pub(crate) transmission: f32,
+    pub(crate) ior: f32,
+    pub(crate) _pad: f32,
+    pub(crate) textures: GpuTextureIds,
+}
+
+/// One entry of the top level: which bottom-level BVH to trace and how to get the ray into its object space.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuInstance {
+    /// Column-major inverse of the object to world transform.
+    pub(crate) world_to_object: [[f32; 4]; 4],
+    pub(crate) material: GpuMaterial,
+    pub(crate) blas_root: u32,
+    /// 1 when `material` replaces the triangles' own materials.
+    pub(crate) override_material: u32,
+    /// 1 when the instance moves, then the transform comes from the two poses below and `world_to_object` is unused.
+    pub(crate) moving: u32,
+    pub(crate) _pad0: u32,
+    pub(crate) start: GpuPose,
+    pub(crate) end: GpuPose,
+}
+
+/// An object to world transform split into its parts, so it can be interpolated.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuPose {
+    pub(crate) translation: [f32; 3],
     pub(crate) _pad0: f32,
-    pub(crate) direction: [f32; 3],
+    /// Quaternion, xyzw.
+    pub(crate) rotation: [f32; 4],
+    pub(crate) scale: [f32; 3],
     pub(crate) _pad1: f32,
 }
 
+/// Indices into the texture table, -1 when the material has no map.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuTextureIds {
+    pub(crate) albedo: i32,
+    pub(crate) roughness: i32,
+    pub(crate) metallic: i32,
+    pub(crate) _pad: i32,
+}
+
+/// Header of one texture in the packed texture buffer, `offset` counts texels from the start of the texel data.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuTexture {
+    pub(crate) offset: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) wrap: u32,
+}
+
+/// The camera, its lens and the pixel filter, primary rays are generated from it in raytracer.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuCamera {
+    pub(crate) origin: [f32; 3],
+    pub(crate) tan_half_fov: f32,
+    pub(crate) right: [f32; 3],
+    pub(crate) aspect: f32,
+    pub(crate) up: [f32; 3],
+    pub(crate) filter_radius: f32,
+    pub(crate) forward: [f32; 3],
+    pub(crate) filter_kind: u32,
+    pub(crate) aperture: f32,
+    pub(crate) focus_distance: f32,
+    pub(crate) blades: u32,
+    pub(crate) blade_rotation: f32,
+    pub(crate) projection: u32,
+    pub(crate) half_fov: f32,
+    pub(crate) ortho_height: f32,
+    pub(crate) _pad: u32,
+    pub(crate) end_origin: [f32; 3],
+    pub(crate) shutter_open: f32,
+    pub(crate) end_right: [f32; 3],
+    pub(crate) shutter_close: f32,
+    pub(crate) end_up: [f32; 3],
+    pub(crate) moving: u32,
+    pub(crate) end_forward: [f32; 3],
+    pub(crate) _pad1: u32,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 pub struct GpuHitInfo {
     pub has_hit: u32,
     pub t: f32,
-    _pad0: [f32; 2],
+    pub transmission: f32,
+    pub ior: f32,
     pub pos: [f32; 4],
     pub normal: [f32; 4],
     pub albedo: [f32; 4],
+    pub uv: [f32; 2],
+    pub _pad0: [f32; 2],
+    pub textures: GpuTextureIds,
     pub emission: f32,
     pub metallic: f32,
     pub roughness: f32,
-    _pad1: f32,
+    pub moving: u32,
+    pub emission_color: [f32; 3],
+    _pad2: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuEmitter {
+    pub p0: [f32; 3],
+    pub kind: u32,
+    pub p1: [f32; 3],
+    pub radius: f32,
+    pub p2: [f32; 3],
+    /// Chance of picking this emitter or one before it.
+    pub cdf: f32,
+    pub radiance: [f32; 3],
+    pub _pad0: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuLight {
+    pub position: [f32; 3],
+    pub kind: u32,
+    /// Spot axis, sun travel direction, or the facing of rect and disk lights.
+    pub direction: [f32; 3],
+    pub radius: f32,
+    pub radiance: [f32; 3],
+    pub cos_inner: f32,
+    pub u: [f32; 3],
+    /// Spot cone edge, or the sun's angular radius.
+    pub cos_outer: f32,
+    pub v: [f32; 3],
+    pub visible: u32,
+}
+
+/// Uniform header of the environment, the map itself sits in a storage buffer of its own.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+pub struct GpuEnvironment {
+    pub kind: u32,
+    pub width: u32,
+    pub height: u32,
+    pub intensity: f32,
+    pub rotation: f32,
+    pub sampled: u32,
+    pub _pad0: [u32; 2],
+    /// The constant colour, or the gradient's zenith.
+    pub color: [f32; 3],
+    pub _pad1: f32,
+    pub horizon: [f32; 3],
+    pub _pad2: f32,
+    pub ground: [f32; 3],
+    pub _pad3: f32,
+    /// Everything below only matters to the sky.
+    pub sun_direction: [f32; 3],
+    pub sky_scale: f32,
+    /// Perez coefficients A to E for the luminance and the two chromaticities, padded to four.
+    pub perez: [[f32; 4]; 5],
+    pub zenith: [f32; 3],
+    pub _pad4: f32,
 }
 
 #[repr(C)]
@@ -73,7 +246,7 @@ pub struct GpuColor {
     pub r: f32,
     pub g: f32,
     pub b: f32,
-    pub _pad: f32,
+    pub weight: f32,
 }
 
 #[repr(C)]
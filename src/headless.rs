@@ -0,0 +1,22 @@
+use crate::camera::Camera;
+use crate::output;
+use crate::profiler::{profiler_start, profiler_stop};
+use crate::renderer::Renderer;
+use crate::scene::Scene;
+use crate::window::Canvas;
+
+pub async fn render_to_file(width: u32, height: u32, samples: u32, camera: &Camera, scene: &Scene, renderer: &Renderer,
+                            path: &str) -> std::io::Result<()> {
+    let mut canvas = Canvas::new_headless(width, height).await;
+
+    profiler_start("headless render");
+    while canvas.sample_count < samples {
+        renderer.render_gpu(camera, scene, &mut canvas);
+        // nothing is read back per sample any more, wait here so the queued frames don't pile up
+        canvas.device().poll(wgpu::PollType::Wait { submission_index: None, timeout: None }).expect("GPU was NOT polled");
+    }
+    profiler_stop("headless render");
+
+    println!("Rendered {} samples at {}x{}, writing {}", canvas.sample_count, width, height, path);
+    output::write_image(path, &canvas)
+}
@@ -1,13 +1,14 @@
 use std::fs::File;
 use std::io::Read;
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use crate::model::{Face, Mesh, Vertex};
 
-pub fn import_obj(path: &str) -> Mesh {
-    let file = File::open(path);
+pub fn import_obj(path: &str) -> std::io::Result<Mesh> {
     let mut contents = String::new();
-    file.expect("Failed to open file").read_to_string(&mut contents).expect("Failed to read string!");
-    parse_obj_into_mesh(contents)
+    File::open(path)?.read_to_string(&mut contents)?;
+    let mut mesh = parse_obj_into_mesh(contents);
+    mesh.source = Some(path.to_string());
+    Ok(mesh)
 }
 
 fn parse_obj_into_mesh(file_str: String) -> Mesh {
@@ -15,6 +16,7 @@ fn parse_obj_into_mesh(file_str: String) -> Mesh {
     
     let mut positions: Vec<Vec3> = Vec::new();
     let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
 
     file_str.lines().for_each(|line| {
         let line = line.trim();
@@ -29,39 +31,47 @@ fn parse_obj_into_mesh(file_str: String) -> Mesh {
         }
 
         match parts[0] {
-            "v" => {
-                if parts.len() >= 4 {
-                    let x = parts[1].parse::<f32>().unwrap_or(0.0);
-                    let y = parts[2].parse::<f32>().unwrap_or(0.0);
-                    let z = parts[3].parse::<f32>().unwrap_or(0.0);
-                    positions.push(Vec3::new(x, y, z));
-                }
+            "v" if parts.len() >= 4 => {
+                let x = parts[1].parse::<f32>().unwrap_or(0.0);
+                let y = parts[2].parse::<f32>().unwrap_or(0.0);
+                let z = parts[3].parse::<f32>().unwrap_or(0.0);
+                positions.push(Vec3::new(x, y, z));
             },
-            "vn" => {
-                if parts.len() >= 4 {
-                    let x = parts[1].parse::<f32>().unwrap_or(0.0);
-                    let y = parts[2].parse::<f32>().unwrap_or(0.0);
-                    let z = parts[3].parse::<f32>().unwrap_or(0.0);
-                    normals.push(Vec3::new(x, y, z));
-                }
+            "vn" if parts.len() >= 4 => {
+                let x = parts[1].parse::<f32>().unwrap_or(0.0);
+                let y = parts[2].parse::<f32>().unwrap_or(0.0);
+                let z = parts[3].parse::<f32>().unwrap_or(0.0);
+                normals.push(Vec3::new(x, y, z));
+            },
+            "vt" if parts.len() >= 3 => {
+                let u = parts[1].parse::<f32>().unwrap_or(0.0);
+                let v = parts[2].parse::<f32>().unwrap_or(0.0);
+                uvs.push(Vec2::new(u, v));
             },
             "f" => {
                 let mut face = Face::new();
 
-                for i in 1..parts.len() {
-                    let indices: Vec<&str> = parts[i].split('/').collect();
+                for part in &parts[1..] {
+                    let indices: Vec<&str> = part.split('/').collect();
                     
                     let pos_idx = indices[0].parse::<usize>().unwrap_or(1) - 1; // OBJ is 1-indexed
                     let position = positions.get(pos_idx).copied().unwrap_or(Vec3::ZERO);
                     
                     let normal = if indices.len() >= 3 && !indices[2].is_empty() {
                         let norm_idx = indices[2].parse::<usize>().unwrap_or(1) - 1;
-                        normals.get(norm_idx).copied().unwrap_or(Vec3::Y)
+                        normals.get(norm_idx).copied().unwrap_or(Vec3::ZERO)
+                    } else {
+                        Vec3::ZERO
+                    };
+
+                    let uv = if indices.len() >= 2 && !indices[1].is_empty() {
+                        let uv_idx = indices[1].parse::<usize>().unwrap_or(1) - 1;
+                        uvs.get(uv_idx).copied().unwrap_or(Vec2::ZERO)
                     } else {
-                        Vec3::Y
+                        Vec2::ZERO
                     };
 
-                    face.append_vertex(Vertex::new(position, normal));
+                    face.append_vertex(Vertex::new(position, normal).with_uv(uv));
                 }
 
                 if !face.vertices.is_empty() {
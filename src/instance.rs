@@ -0,0 +1,152 @@
+use std::any::Any;
+use std::sync::Arc;
+use glam::{Mat4, Vec3};
+use crate::bvh::AABB;
+use crate::material::Material;
+use crate::model::Mesh;
+use crate::objects::{HitInfo, Hittable, Triangle};
+use crate::ray::Ray;
+
+/// A placement of a shared mesh. The mesh and its BVH stay in object space, only `transform` and the optional
+/// material override belong to the instance. With an `end_transform` it moves from one to the other over the shutter.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    mesh: Arc<Mesh>,
+    transform: Mat4,
+    end_transform: Option<Mat4>,
+    material: Option<Material>,
+}
+
+impl Instance {
+    pub fn new(mesh: Arc<Mesh>, transform: Mat4) -> Self {
+        Self { mesh, transform, end_transform: None, material: None }
+    }
+
+    pub fn with_end_transform(mut self, end_transform: Mat4) -> Self {
+        self.end_transform = Some(end_transform);
+        self
+    }
+
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn mesh(&self) -> &Arc<Mesh> {
+        &self.mesh
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Mat4) {
+        self.transform = transform;
+    }
+
+    pub fn end_transform(&self) -> Option<Mat4> {
+        self.end_transform
+    }
+
+    pub fn transform_at(&self, time: f32) -> Mat4 {
+        match self.end_transform {
+            Some(end) => interpolate_transform(&self.transform, &end, time),
+            None => self.transform,
+        }
+    }
+
+    /// The override when there is one, otherwise the mesh's own material.
+    pub fn material(&self) -> Material {
+        self.material.unwrap_or_else(|| self.mesh.material())
+    }
+
+    pub fn material_override(&self) -> Option<&Material> {
+        self.material.as_ref()
+    }
+
+    /// The mesh's triangles moved to world space, with the override applied.
+    pub fn triangles(&self) -> Vec<Triangle> {
+        self.mesh.local_triangles().iter().map(|triangle| {
+            let triangle = triangle.transformed(&self.transform);
+            match self.material {
+                Some(material) => triangle.with_material(material),
+                None => triangle,
+            }
+        }).collect()
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray) -> HitInfo {
+        // the direction is not normalised so t means the same in both spaces
+        let world_to_object = self.transform_at(ray.time()).inverse();
+        let local_ray = Ray::new(world_to_object.transform_point3(ray.origin()), world_to_object.transform_vector3(ray.direction()))
+            .with_time(ray.time());
+
+        let mut info = self.mesh.hit(&local_ray);
+        info.sent_ray = *ray;
+        if info.has_hit {
+            info.pos = ray.at(info.t as f32);
+            info.normal = world_to_object.transpose().transform_vector3(info.normal).normalize();
+            info.moving = self.end_transform.is_some();
+            if let Some(material) = self.material {
+                info.material = material;
+            }
+        }
+        info
+    }
+
+    fn set_material(&mut self, material: Material) {
+        self.material = Some(material);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn to_aabb(&self) -> AABB {
+        let local = match &self.mesh.bvh {
+            Some(bvh) => *bvh.root.aabb(),
+            None => self.mesh.local_triangles().iter().fold(AABB::empty(), |aabb, tri| aabb.union(&tri.to_aabb())),
+        };
+        motion_aabb(&local, &self.transform, self.end_transform.as_ref())
+    }
+}
+
+/// Scale and translation move linearly, the rotation takes the shortest way round. Matches `instance_at` in hit.wgsl.
+pub fn interpolate_transform(start: &Mat4, end: &Mat4, time: f32) -> Mat4 {
+    let (start_scale, start_rotation, start_translation) = start.to_scale_rotation_translation();
+    let (end_scale, end_rotation, end_translation) = end.to_scale_rotation_translation();
+    Mat4::from_scale_rotation_translation(
+        start_scale.lerp(end_scale, time),
+        start_rotation.slerp(end_rotation, time),
+        start_translation.lerp(end_translation, time),
+    )
+}
+
+/// Box around `aabb` over the whole motion. Sampled, with a little slack for the rotation between samples.
+pub fn motion_aabb(aabb: &AABB, start: &Mat4, end: Option<&Mat4>) -> AABB {
+    let Some(end) = end else {
+        return transform_aabb(aabb, start);
+    };
+
+    const STEPS: u32 = 16;
+    let bounds = (0..=STEPS).fold(AABB::empty(), |bounds, step| {
+        bounds.union(&transform_aabb(aabb, &interpolate_transform(start, end, step as f32 / STEPS as f32)))
+    });
+    let slack = (bounds.max - bounds.min) * 0.01;
+    AABB::new(bounds.min - slack, bounds.max + slack)
+}
+
+/// Box around the eight transformed corners of `aabb`.
+pub fn transform_aabb(aabb: &AABB, transform: &Mat4) -> AABB {
+    (0..8).fold(AABB::empty(), |result, corner| {
+        let point = Vec3::new(
+            if corner & 1 == 0 { aabb.min.x } else { aabb.max.x },
+            if corner & 2 == 0 { aabb.min.y } else { aabb.max.y },
+            if corner & 4 == 0 { aabb.min.z } else { aabb.max.z },
+        );
+        result.grow(transform.transform_point3(point))
+    })
+}
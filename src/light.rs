@@ -0,0 +1,308 @@
+use std::f32::consts::PI;
+use glam::{Vec2, Vec3};
+use crate::brdf::tangent_frame;
+use crate::color::Color;
+use crate::gpu_types::GpuLight;
+use crate::ray::Ray;
+
+// matches the LIGHT_ constants in raytracer.wgsl
+const LIGHT_POINT: u32 = 0;
+const LIGHT_SPOT: u32 = 1;
+const LIGHT_DIRECTIONAL: u32 = 2;
+const LIGHT_RECT: u32 = 3;
+const LIGHT_DISK: u32 = 4;
+
+/// Where a light sits and how it spreads its light. Point and spot lights are spheres of `radius`, or true points when
+/// that is zero. The directional light shines along `direction` from a disk in the sky `cos_radius` wide, infinitely far
+/// away. Rect and disk lights only shine towards the side their `normal` points to.
+#[derive(Debug, Clone, Copy)]
+pub enum LightShape {
+    Point { position: Vec3, radius: f32 },
+    /// Full intensity within `cos_inner` of `direction`, fading out smoothly towards `cos_outer`.
+    Spot { position: Vec3, radius: f32, direction: Vec3, cos_inner: f32, cos_outer: f32 },
+    Directional { direction: Vec3, cos_radius: f32 },
+    /// `u` and `v` run from the centre to the edges, half the width and height long.
+    Rect { position: Vec3, u: Vec3, v: Vec3 },
+    Disk { position: Vec3, normal: Vec3, radius: f32 },
+}
+
+/// A light that isn't part of the geometry. `intensity` is radiant intensity for point and spot lights, irradiance
+/// for directional lights and radiance for rect and disk lights, scaled by `color`. Lights don't cast shadows on
+/// each other.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub shape: LightShape,
+    pub color: Color,
+    pub intensity: f32,
+    /// Whether camera rays see the light itself, it lights the scene either way.
+    pub visible: bool,
+}
+
+/// Light arriving from one sampled point on a light. Delta lights come with a pdf of one and the inverse square fall
+/// off already in `radiance`.
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    pub radiance: Color,
+    pub pdf: f32,
+    pub delta: bool,
+}
+
+impl Light {
+    pub fn new(shape: LightShape, color: Color, intensity: f32) -> Self {
+        Self { shape, color, intensity, visible: true }
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    /// Radiance leaving the light's surface, or the intensity or irradiance of a delta light.
+    pub fn radiance(&self) -> Color {
+        let scale = match self.shape {
+            LightShape::Point { radius, .. } | LightShape::Spot { radius, .. } if radius > 0.0 => 1.0 / (PI * radius * radius),
+            LightShape::Directional { cos_radius, .. } if cos_radius < 1.0 => 1.0 / cone_solid_angle(cos_radius),
+            _ => 1.0,
+        };
+        self.color * (self.intensity * scale)
+    }
+
+    /// A direction towards the light from `origin`, spread by `u` in [0, 1)². `None` when `origin` can't see it.
+    /// The same as `sample_light` in raytracer.wgsl.
+    pub fn sample(&self, origin: Vec3, u: Vec2) -> Option<LightSample> {
+        let radiance = self.radiance();
+        match self.shape {
+            LightShape::Point { position, radius } | LightShape::Spot { position, radius, .. } => {
+                let to_center = position - origin;
+                let distance = to_center.length();
+                if radius <= 0.0 {
+                    let direction = to_center / distance;
+                    let radiance = radiance * (self.spot_falloff(-direction) / (distance * distance));
+                    return Some(LightSample { direction, distance, radiance, pdf: 1.0, delta: true });
+                }
+                if distance <= radius {
+                    return None;
+                }
+
+                let sin2_max = radius * radius / (distance * distance);
+                let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+                let direction = sample_cone(to_center / distance, cos_max, u);
+                let distance = sphere_distance(origin, direction, position, radius).unwrap_or(distance);
+                let radiance = radiance * self.spot_falloff(-direction);
+                Some(LightSample { direction, distance, radiance, pdf: 1.0 / cone_solid_angle(cos_max), delta: false })
+            }
+            LightShape::Directional { direction, cos_radius } => {
+                if cos_radius >= 1.0 {
+                    return Some(LightSample { direction: -direction, distance: f32::INFINITY, radiance, pdf: 1.0, delta: true });
+                }
+                let direction = sample_cone(-direction, cos_radius, u);
+                Some(LightSample { direction, distance: f32::INFINITY, radiance, pdf: 1.0 / cone_solid_angle(cos_radius), delta: false })
+            }
+            LightShape::Rect { position, u: u_axis, v: v_axis } => {
+                let point = position + u_axis * (2.0 * u.x - 1.0) + v_axis * (2.0 * u.y - 1.0);
+                self.area_sample(origin, point, u_axis.cross(v_axis).normalize(), 4.0 * u_axis.length() * v_axis.length())
+            }
+            LightShape::Disk { position, normal, radius } => {
+                let (tangent, bitangent) = tangent_frame(normal);
+                let r = radius * u.x.sqrt();
+                let phi = 2.0 * PI * u.y;
+                let point = position + (tangent * phi.cos() + bitangent * phi.sin()) * r;
+                self.area_sample(origin, point, normal, PI * radius * radius)
+            }
+        }
+    }
+
+    /// Solid angle density `sample` picks `direction` from `origin` with, for a ray that hit the light `distance` away.
+    pub fn pdf(&self, origin: Vec3, direction: Vec3, distance: f32) -> f32 {
+        match self.shape {
+            LightShape::Point { position, radius } | LightShape::Spot { position, radius, .. } => {
+                let center_distance = (position - origin).length();
+                if radius <= 0.0 || center_distance <= radius {
+                    return 0.0;
+                }
+                let sin2_max = radius * radius / (center_distance * center_distance);
+                1.0 / cone_solid_angle((1.0 - sin2_max).max(0.0).sqrt())
+            }
+            LightShape::Directional { cos_radius, .. } => {
+                if cos_radius >= 1.0 { 0.0 } else { 1.0 / cone_solid_angle(cos_radius) }
+            }
+            LightShape::Rect { u, v, .. } => area_pdf(u.cross(v).normalize(), 4.0 * u.length() * v.length(), direction, distance),
+            LightShape::Disk { normal, radius, .. } => area_pdf(normal, PI * radius * radius, direction, distance),
+        }
+    }
+
+    /// Distance along `ray` to the light and the radiance it sends back along the ray, closer than `max_t`.
+    /// Directional lights are only seen by rays that leave the scene, at an infinite distance.
+    pub fn hit(&self, ray: &Ray, max_t: f32) -> Option<(f32, Color)> {
+        let direction = ray.direction().normalize();
+        let scale = ray.direction().length();
+        let t = match self.shape {
+            LightShape::Point { position, radius } | LightShape::Spot { position, radius, .. } => {
+                if radius <= 0.0 {
+                    return None;
+                }
+                sphere_distance(ray.origin(), direction, position, radius)?
+            }
+            LightShape::Directional { direction: light_direction, cos_radius } => {
+                if max_t < f32::INFINITY || cos_radius >= 1.0 || direction.dot(-light_direction) < cos_radius {
+                    return None;
+                }
+                return Some((f32::INFINITY, self.radiance()));
+            }
+            LightShape::Rect { position, u, v } => {
+                let normal = u.cross(v).normalize();
+                let t = facing_plane_distance(ray.origin(), direction, position, normal)?;
+                let local = ray.origin() + direction * t - position;
+                if local.dot(u).abs() > u.length_squared() || local.dot(v).abs() > v.length_squared() {
+                    return None;
+                }
+                t
+            }
+            LightShape::Disk { position, normal, radius } => {
+                let t = facing_plane_distance(ray.origin(), direction, position, normal)?;
+                if (ray.origin() + direction * t - position).length_squared() > radius * radius {
+                    return None;
+                }
+                t
+            }
+        };
+
+        // t along the normalised direction, max_t along the ray's own
+        if t / scale >= max_t {
+            return None;
+        }
+        Some((t / scale, self.radiance() * self.spot_falloff(-direction)))
+    }
+
+    /// How much of the spot's light leaves along `direction`, one for every other light.
+    fn spot_falloff(&self, direction: Vec3) -> f32 {
+        match self.shape {
+            LightShape::Spot { direction: axis, cos_inner, cos_outer, .. } => {
+                smoothstep(cos_outer, cos_inner, direction.dot(axis))
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn area_sample(&self, origin: Vec3, point: Vec3, normal: Vec3, area: f32) -> Option<LightSample> {
+        let to_light = point - origin;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let pdf = area_pdf(normal, area, direction, distance);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample { direction, distance, radiance: self.radiance(), pdf, delta: false })
+    }
+
+    pub fn gpu_light(&self) -> GpuLight {
+        let radiance = self.radiance();
+        let mut light = GpuLight {
+            position: [0.0; 3],
+            kind: 0,
+            direction: [0.0; 3],
+            radius: 0.0,
+            radiance: [radiance.r, radiance.g, radiance.b],
+            cos_inner: 1.0,
+            u: [0.0; 3],
+            cos_outer: 1.0,
+            v: [0.0; 3],
+            visible: self.visible as u32,
+        };
+        match self.shape {
+            LightShape::Point { position, radius } => {
+                light.kind = LIGHT_POINT;
+                light.position = position.to_array();
+                light.radius = radius;
+            }
+            LightShape::Spot { position, radius, direction, cos_inner, cos_outer } => {
+                light.kind = LIGHT_SPOT;
+                light.position = position.to_array();
+                light.radius = radius;
+                light.direction = direction.to_array();
+                light.cos_inner = cos_inner;
+                light.cos_outer = cos_outer;
+            }
+            LightShape::Directional { direction, cos_radius } => {
+                light.kind = LIGHT_DIRECTIONAL;
+                light.direction = direction.to_array();
+                light.cos_outer = cos_radius;
+            }
+            LightShape::Rect { position, u, v } => {
+                light.kind = LIGHT_RECT;
+                light.position = position.to_array();
+                light.direction = u.cross(v).normalize().to_array();
+                light.u = u.to_array();
+                light.v = v.to_array();
+            }
+            LightShape::Disk { position, normal, radius } => {
+                light.kind = LIGHT_DISK;
+                light.position = position.to_array();
+                light.direction = normal.to_array();
+                light.radius = radius;
+            }
+        }
+        light
+    }
+}
+
+/// Rect light facing `normal`, with its width and height along the tangent frame of the normal.
+pub fn rect_shape(position: Vec3, normal: Vec3, width: f32, height: f32) -> LightShape {
+    let (tangent, bitangent) = tangent_frame(normal);
+    LightShape::Rect { position, u: tangent * (width * 0.5), v: bitangent * (height * 0.5) }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Solid angle of a cone, written so it keeps its precision for tiny ones.
+fn cone_solid_angle(cos_max: f32) -> f32 {
+    let sin2 = (1.0 - cos_max * cos_max).max(0.0);
+    2.0 * PI * sin2 / (1.0 + cos_max)
+}
+
+/// A direction spread uniformly over the cone of directions within `cos_max` of `axis`.
+fn sample_cone(axis: Vec3, cos_max: f32, u: Vec2) -> Vec3 {
+    let cos_theta = 1.0 - u.x * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    let (tangent, bitangent) = tangent_frame(axis);
+    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta).normalize()
+}
+
+fn area_pdf(normal: Vec3, area: f32, direction: Vec3, distance: f32) -> f32 {
+    let cos_light = -normal.dot(direction);
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    distance * distance / (cos_light * area)
+}
+
+/// Nearest distance along the unit `direction` to the sphere, from outside of it.
+fn sphere_distance(origin: Vec3, direction: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let oc = origin - center;
+    let b = oc.dot(direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = -b - discriminant.sqrt();
+    (t > 0.0).then_some(t)
+}
+
+/// Distance along the unit `direction` to the plane, only when the ray arrives at its front.
+fn facing_plane_distance(origin: Vec3, direction: Vec3, position: Vec3, normal: Vec3) -> Option<f32> {
+    let denom = direction.dot(normal);
+    if denom >= 0.0 {
+        return None;
+    }
+    let t = (position - origin).dot(normal) / denom;
+    (t > 0.0).then_some(t)
+}
@@ -1,7 +1,5 @@
-use crate::camera::Camera;
-use crate::ray::Ray;
 use crate::window::Canvas;
-use glam::Vec3;
+use clap::Parser;
 
 mod camera;
 mod window;
@@ -12,26 +10,59 @@ mod ray;
 mod movement;
 mod renderer;
 mod material;
+mod brdf;
+mod emitter;
+mod environment;
+mod light;
 mod model;
 mod importer;
 mod gpu_types;
 mod compute;
 mod profiler;
 mod bvh;
-
-const DEBUG_MODE: bool = true;
+mod instance;
+mod output;
+mod headless;
+mod scene_file;
+mod sky;
+mod cli;
+mod texture;
 
 #[tokio::main]
 async fn main() {
+    let args = cli::Args::parse();
+
+    let mut scene = match scene_file::load_scene(&args.scene) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("Failed to load scene: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let renderer = renderer::Renderer::new(args.max_bounces);
+
+    if args.headless {
+        let mut camera = scene.camera().clone().with_filter(args.filter);
+        camera.resize(args.width, args.height);
+        if let Err(err) = headless::render_to_file(args.width, args.height, args.headless_samples(), &camera, &scene,
+                                                   &renderer, &args.output).await {
+            eprintln!("Failed to write {}: {}", args.output, err);
+            std::process::exit(1);
+        }
+        profiler::print_profile();
+        return;
+    }
+
     profiler::profiler_start("init");
     profiler::profiler_start("window");
-    let mut canvas = Canvas::new(80 * 10, 60 * 10, "WINDOW").await;
+    let mut canvas = Canvas::new(args.width, args.height, "WINDOW").await;
     profiler::profiler_stop("window");
-    let mut camera = Camera::new(canvas.width(), canvas.height(), Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)));
+    let mut camera = scene.camera().clone().with_filter(args.filter);
+    camera.resize(canvas.width(), canvas.height());
     let mut movement_state = movement::MovementState::new();
-    let scene = scene::create_scene();
-    let renderer = renderer::Renderer::new();
     let mut delta_time = 0.0;
+    let mut scene_modified = modified_time(&args.scene);
 
     profiler::profiler_stop("init");
 
@@ -41,12 +72,24 @@ async fn main() {
         profiler::profiler_start("render");
         profiler::profiler_start("gpu");
 
-        renderer.render_gpu(&camera, &scene, &mut canvas);
+        // saving the scene file applies the edits to the running scene, uploading only what changed
+        let modified = modified_time(&args.scene);
+        if modified != scene_modified {
+            scene_modified = modified;
+            match scene_file::load_scene(&args.scene) {
+                Ok(reloaded) => scene.update_from(reloaded),
+                Err(err) => eprintln!("Failed to reload scene: {}", err),
+            }
+        }
+        compute::apply_scene_changes(&mut canvas, &mut scene);
+        if args.samples.is_none_or(|target| canvas.sample_count < target) {
+            renderer.render_gpu(&camera, &scene, &mut canvas);
+        }
 
         profiler::profiler_stop("gpu");
         profiler::profiler_start("debug");
 
-        if DEBUG_MODE {
+        if args.debug_bvh {
             renderer.render_debug(&camera, &scene, &mut canvas, false);
         }
         profiler::profiler_stop("debug");
@@ -64,6 +107,9 @@ async fn main() {
         if movement::apply_movements(&mut camera, &canvas, delta_time, &mut movement_state) {
             canvas.reset_accumulation();
         }
+        if movement::apply_click_to_focus(&mut camera, &mut canvas, &scene) {
+            canvas.reset_accumulation();
+        }
 
         profiler::profiler_stop("text and movement");
 
@@ -77,4 +123,8 @@ async fn main() {
         profiler::profiler_stop("main");
         profiler::profiler_reset()
     }
-}
\ No newline at end of file
+}
+
+fn modified_time(path: &str) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -1,20 +1,34 @@
+use glam::Vec2;
 use crate::color::Color;
+use crate::texture::Texture;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Material {
     pub albedo: Color,
     pub roughness: f32,
     pub metallic: f32,
-    pub emission: f32
+    /// Emitted radiance is `emission_color * emission`, independent of the albedo and not limited to 1.
+    pub emission: f32,
+    pub emission_color: Color,
+    /// Probability of a path refracting into the surface instead of scattering off it, 0 is opaque and 1 is clear glass.
+    /// Transmitted light is tinted by the albedo.
+    pub transmission: f32,
+    pub ior: f32,
+    /// Indices into the scene textures. The albedo map multiplies the albedo, the red channel of the roughness and
+    /// metallic maps multiplies the scalar.
+    pub albedo_texture: Option<u32>,
+    pub roughness_texture: Option<u32>,
+    pub metallic_texture: Option<u32>,
 }
 
 impl Material {
     pub fn new(albedo: Color, roughness: f32, metallic: f32, emission: f32) -> Self {
-        Self { albedo, roughness, metallic, emission }
+        Self { albedo, roughness, metallic, emission, emission_color: Color::white(), transmission: 0.0, ior: 1.5,
+            albedo_texture: None, roughness_texture: None, metallic_texture: None }
     }
-    
+
     pub fn default() -> Self {
-        Self { albedo: Color::new(0.5, 0.5, 0.5), roughness: 0.0, metallic: 0.0, emission: 0.0 }
+        Self::new(Color::new(0.5, 0.5, 0.5), 0.0, 0.0, 0.0)
     }
 
     pub fn albedo(&self) -> &Color {
@@ -25,6 +39,14 @@ impl Material {
         self.emission
     }
 
+    pub fn emission_color(&self) -> &Color {
+        &self.emission_color
+    }
+
+    pub fn emitted(&self) -> Color {
+        self.emission_color * self.emission
+    }
+
     pub fn metallic(&self) -> f32 {
         self.metallic
     }
@@ -32,4 +54,31 @@ impl Material {
     pub fn roughness(&self) -> f32 {
         self.roughness
     }
-}
\ No newline at end of file
+
+    pub fn transmission(&self) -> f32 {
+        self.transmission
+    }
+
+    pub fn ior(&self) -> f32 {
+        self.ior
+    }
+
+    /// Resolves the texture maps at `uv`, returning a copy with plain albedo, roughness and metallic values.
+    pub fn at_uv(&self, uv: Vec2, textures: &[Texture]) -> Self {
+        let lookup = |index: Option<u32>, srgb: bool| {
+            index.and_then(|i| textures.get(i as usize)).map(|texture| texture.sample(uv, srgb))
+        };
+
+        let mut material = *self;
+        if let Some(color) = lookup(self.albedo_texture, true) {
+            material.albedo *= color;
+        }
+        if let Some(color) = lookup(self.roughness_texture, false) {
+            material.roughness *= color.r;
+        }
+        if let Some(color) = lookup(self.metallic_texture, false) {
+            material.metallic *= color.r;
+        }
+        material
+    }
+}
@@ -1,5 +1,6 @@
-use glam::{Quat, Vec3};
-use crate::bvh::BVHNode;
+use std::collections::HashMap;
+use glam::{Mat4, Quat, Vec2, Vec3};
+use crate::bvh::BVH;
 use crate::color::Color;
 use crate::material::Material;
 use crate::objects::Triangle;
@@ -8,7 +9,8 @@ use crate::objects::Triangle;
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     position: Vec3,
-    normal: Vec3
+    normal: Vec3,
+    uv: Vec2,
 }
 
 #[derive(Debug, Clone)]
@@ -19,49 +21,84 @@ pub struct Face {
 #[derive(Debug, Clone)]
 pub struct Mesh {
     pub faces: Vec<Face>,
-    pub bvh: Option<BVHNode>,
+    pub bvh: Option<BVH>,
     pub position: Vec3,
     pub rotation: Vec3,
     pub scale: f32,
+    /// Position, rotation and scale at the end of the shutter, when the mesh moves.
+    pub motion: Option<(Vec3, Vec3, f32)>,
+    pub source: Option<String>,
+    /// Set when the vertex normals were generated rather than read from the file.
+    pub crease_angle: Option<f32>,
 }
 
 impl Mesh {
     pub fn new() -> Self {
-        Self { faces: Vec::new(), bvh: None, position: Vec3::ZERO, rotation: Vec3::ZERO, scale: 1.0 }
+        Self { faces: Vec::new(), bvh: None, position: Vec3::ZERO, rotation: Vec3::ZERO, scale: 1.0, motion: None, source: None,
+            crease_angle: None }
     }
-    pub fn add_bvh(&mut self, bvh: BVHNode) {
+    pub fn add_bvh(&mut self, bvh: BVH) {
         self.bvh = Some(bvh);
     }
+    pub fn material(&self) -> Material {
+        self.faces.first().map_or(Material::default(), |face| face.material)
+    }
     pub fn append_face(&mut self, face: Face) {
         self.faces.push(face);
     }
-    pub fn append_tri(&mut self, triangle: Triangle) {
-        let mut face = Face::new();
-        face.append_vertex(Vertex::new(triangle.v0(), Vec3::ZERO));
-        face.append_vertex(Vertex::new(triangle.v1(), Vec3::ZERO));
-        face.append_vertex(Vertex::new(triangle.v2(), Vec3::ZERO));
-        self.append_face(face);
+
+    /// True when every vertex carries a normal, either from the OBJ file or from `generate_normals`.
+    pub fn has_normals(&self) -> bool {
+        self.faces.iter().all(|face| face.vertices.iter().all(|vertex| vertex.normal != Vec3::ZERO))
     }
 
-    pub fn get_triangles(&self) -> Vec<Triangle> {
-        let rotation =
-            Quat::from_rotation_x(self.rotation.x) *
-            Quat::from_rotation_y(self.rotation.y) *
-            Quat::from_rotation_z(self.rotation.z);
+    /// Replaces the vertex normals with angle-weighted averages of the adjacent face normals. Faces meeting at more
+    /// than `crease_angle` radians keep a hard edge.
+    pub fn generate_normals(&mut self, crease_angle: f32) {
+        let key = |position: Vec3| [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()];
+        let face_normals: Vec<Vec3> = self.faces.iter().map(Face::normal).collect();
+
+        // every corner touching a position, as (face normal, weight)
+        let mut corners: HashMap<[u32; 3], Vec<(Vec3, f32)>> = HashMap::new();
+        for (face, &normal) in self.faces.iter().zip(&face_normals) {
+            for i in 0..face.vertices.len() {
+                corners.entry(key(face.vertices[i].position)).or_default().push((normal, face.corner_angle(i)));
+            }
+        }
+
+        self.crease_angle = Some(crease_angle);
+        let cos_crease = crease_angle.cos();
+        for (face, &face_normal) in self.faces.iter_mut().zip(&face_normals) {
+            for vertex in face.vertices.iter_mut() {
+                let sum: Vec3 = corners[&key(vertex.position)].iter()
+                    .filter(|(normal, _)| normal.dot(face_normal) >= cos_crease)
+                    .map(|&(normal, weight)| normal * weight)
+                    .sum();
+                vertex.normal = sum.normalize_or(face_normal);
+            }
+        }
+    }
+
+    /// Object to world transform built from `position`, `rotation` and `scale`.
+    pub fn transform(&self) -> Mat4 {
+        object_to_world(self.position, self.rotation, self.scale)
+    }
+
+    pub fn end_transform(&self) -> Option<Mat4> {
+        self.motion.map(|(position, rotation, scale)| object_to_world(position, rotation, scale))
+    }
 
+    /// Triangles in object space, the order the BVH indices refer to.
+    pub fn local_triangles(&self) -> Vec<Triangle> {
+        self.faces.iter().flat_map(|face| face.to_tris()).collect()
+    }
+
+    pub fn get_triangles(&self) -> Vec<Triangle> {
+        let transform = self.transform();
         self.faces
             .iter()
-            .flat_map(|face| {
-                face.to_tris().into_iter().map(|triangle| {
-                    let mut vertices = triangle.get_vertices();
-                    for vertex in vertices.iter_mut() {
-                        *vertex *= self.scale;
-                        *vertex = rotation * *vertex;
-                        *vertex += self.position;
-                    }
-                    Triangle::new(vertices[0], vertices[1], vertices[2], triangle.material().clone())
-                })
-            }).collect()
+            .flat_map(|face| face.to_tris().into_iter().map(|triangle| triangle.transformed(&transform)))
+            .collect()
     }
 }
 
@@ -78,18 +115,63 @@ impl Face {
     pub fn to_tris(&self) -> Vec<Triangle> {
         if self.vertices.len() < 3 { return vec![] }
         if self.vertices.len() == 3 {
-            return vec![Triangle::new(self.vertices[0].position, self.vertices[1].position, self.vertices[2].position, self.material)]
+            return vec![self.make_tri(0, 1, 2)]
         }
         let mut tris = Vec::new();
         for i in 1..(self.vertices.len() - 1) {
-            tris.push(Triangle::new(self.vertices[0].position, self.vertices[i].position, self.vertices[i + 1].position, self.material, ));
+            tris.push(self.make_tri(0, i, i + 1));
         }
         tris
     }
+    fn make_tri(&self, a: usize, b: usize, c: usize) -> Triangle {
+        let (a, b, c) = (&self.vertices[a], &self.vertices[b], &self.vertices[c]);
+        let normals = [a.normal, b.normal, c.normal];
+        let normals = if normals.contains(&Vec3::ZERO) { None } else { Some(normals) };
+        Triangle::new(a.position, b.position, c.position, self.material)
+            .with_uvs([a.uv, b.uv, c.uv])
+            .with_normals(normals)
+    }
+    /// Newell's method, robust for non-planar polygons.
+    fn normal(&self) -> Vec3 {
+        let mut normal = Vec3::ZERO;
+        for (i, current) in self.vertices.iter().enumerate() {
+            let next = self.vertices[(i + 1) % self.vertices.len()].position;
+            let current = current.position;
+            normal += Vec3::new(
+                (current.y - next.y) * (current.z + next.z),
+                (current.z - next.z) * (current.x + next.x),
+                (current.x - next.x) * (current.y + next.y),
+            );
+        }
+        normal.normalize_or_zero()
+    }
+    fn corner_angle(&self, i: usize) -> f32 {
+        let count = self.vertices.len();
+        let position = self.vertices[i].position;
+        let previous = self.vertices[(i + count - 1) % count].position - position;
+        let next = self.vertices[(i + 1) % count].position - position;
+        if previous.length_squared() == 0.0 || next.length_squared() == 0.0 {
+            return 0.0;
+        }
+        previous.angle_between(next)
+    }
 }
 
 impl Vertex {
     pub fn new(position: Vec3, normal: Vec3) -> Self {
-        Self { position, normal }
+        Self { position, normal, uv: Vec2::ZERO }
+    }
+    pub fn with_uv(mut self, uv: Vec2) -> Self {
+        self.uv = uv;
+        self
     }
-}
\ No newline at end of file
+}
+
+/// Uniform scale, then the rotation in radians (X then Y then Z), then the translation.
+pub fn object_to_world(position: Vec3, rotation: Vec3, scale: f32) -> Mat4 {
+    let rotation =
+        Quat::from_rotation_x(rotation.x) *
+        Quat::from_rotation_y(rotation.y) *
+        Quat::from_rotation_z(rotation.z);
+    Mat4::from_scale_rotation_translation(Vec3::splat(scale), rotation, position)
+}
@@ -1,14 +1,13 @@
 use glam::Vec3;
 use glfw::Key;
 use crate::camera::Camera;
+use crate::scene::Scene;
 use crate::window::Canvas;
 
 pub struct MovementState {
     last_mouse_x: f64,
     last_mouse_y: f64,
     first_mouse: bool,
-    yaw: f64,
-    pitch: f64,
 }
 
 impl MovementState {
@@ -17,15 +16,12 @@ impl MovementState {
             last_mouse_x: 0.0,
             last_mouse_y: 0.0,
             first_mouse: true,
-            yaw: 0.0,
-            pitch: 0.0,
         }
     }
 }
 
 pub fn apply_movements(camera: &mut Camera, canvas: &Canvas, delta_time: f32, state: &mut MovementState) -> bool {
     let mut moved = false;
-    let mut ray = camera.ray();
     let (mouse_x, mouse_y) = canvas.get_mouse_pos();
     {
         if state.first_mouse {
@@ -44,34 +40,16 @@ pub fn apply_movements(camera: &mut Camera, canvas: &Canvas, delta_time: f32, st
         let x_offset = x_offset * sensitivity;
         let y_offset = y_offset * sensitivity;
 
-        state.yaw += x_offset;
-        state.pitch += y_offset;
-
-        if state.pitch > 89.0 {
-            state.pitch = 89.0;
-        }
-        if state.pitch < -89.0 {
-            state.pitch = -89.0;
-        }
-
-        let direction = Vec3::new(
-            (state.yaw.to_radians().cos() * state.pitch.to_radians().cos()) as f32,
-            state.pitch.to_radians().sin() as f32,
-            (state.yaw.to_radians().sin() * state.pitch.to_radians().cos()) as f32,
-        ).normalize();
-
         if x_offset != 0.0 || y_offset != 0.0 {
+            camera.rotate((x_offset as f32).to_radians(), (y_offset as f32).to_radians());
             moved = true;
         }
-
-        ray = crate::ray::Ray::new(ray.origin(), direction);
     }
 
     let move_speed = 5.0;
 
-    let forward = ray.direction().normalize();
-    let right = Vec3::new(0.0, 1.0, 0.0).cross(forward).normalize();
-    let up = Vec3::new(0.0, 1.0, 0.0);
+    let (right, _, forward) = camera.basis();
+    let up = camera.up();
 
     let mut movement = Vec3::ZERO;
 
@@ -95,13 +73,28 @@ pub fn apply_movements(camera: &mut Camera, canvas: &Canvas, delta_time: f32, st
     }
 
     if movement.length_squared() > 0.0 {
+        camera.translate(movement);
         moved = true;
     }
 
-    ray = crate::ray::Ray::new(ray.origin() + movement, ray.direction());
+    moved
+}
 
-    if moved {
-        camera.set_ray(ray);
+/// Click to focus. The cursor is captured for mouse look, so what it points at is the centre of the view.
+pub fn apply_click_to_focus(camera: &mut Camera, canvas: &mut Canvas, scene: &Scene) -> bool {
+    if !canvas.take_click() || camera.aperture() <= 0.0 {
+        return false;
     }
-    moved
-}
\ No newline at end of file
+
+    let time = camera.shutter_time(0.0);
+    let Some(ray) = camera.pinhole_ray(camera.width() as f32 * 0.5, camera.height() as f32 * 0.5, time) else {
+        return false;
+    };
+    match scene.hit(&ray.with_time(time)) {
+        Some(info) => {
+            camera.focus_on(info.pos);
+            true
+        }
+        None => false,
+    }
+}
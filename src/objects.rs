@@ -1,5 +1,5 @@
 use std::any::Any;
-use glam::Vec3;
+use glam::{Mat4, Vec2, Vec3};
 use crate::bvh::AABB;
 use crate::material::Material;
 use crate::model::Mesh;
@@ -9,6 +9,7 @@ pub trait Hittable {
     fn hit(&self, ray: &Ray) -> HitInfo;
     fn set_material(&mut self, material: Material);
     fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
     fn to_aabb(&self) -> AABB;
 }
 #[allow(dead_code)]
@@ -19,11 +20,16 @@ pub struct HitInfo {
     pub pos: Vec3,
     pub sent_ray: Ray,
     pub normal: Vec3,
+    pub uv: Vec2,
     pub material: Material,
+    /// Set on hits of moving instances, whose emissive triangles light sampling leaves out.
+    pub moving: bool,
 }
 
 pub struct Sphere {
     pos: Vec3,
+    /// Where the centre is at the end of the shutter, the same as `pos` for a sphere that doesn't move.
+    end_pos: Vec3,
     radius: f32,
     material: Material,
 }
@@ -31,13 +37,26 @@ pub struct Sphere {
 impl Sphere {
     #[allow(dead_code)]
     pub fn new(pos: Vec3, radius: f32, material: Material) -> Self {
-        Self { pos, radius, material }
+        Self { pos, end_pos: pos, radius, material }
+    }
+
+    pub fn with_end_center(mut self, end_pos: Vec3) -> Self {
+        self.end_pos = end_pos;
+        self
     }
 
     pub fn center(&self) -> Vec3 {
         self.pos
     }
 
+    pub fn end_center(&self) -> Vec3 {
+        self.end_pos
+    }
+
+    pub fn center_at(&self, time: f32) -> Vec3 {
+        self.pos.lerp(self.end_pos, time)
+    }
+
     pub fn radius(&self) -> f32 {
         self.radius
     }
@@ -76,21 +95,77 @@ impl Plane {
         self.length
     }
 
+    /// Unit vectors along the width and the length, the same frame `hit_plane` in hit.wgsl builds.
+    pub fn axes(&self) -> (Vec3, Vec3) {
+        let n = self.normal.normalize();
+        let tangent = if n.x.abs() > 0.9 {
+            Vec3::Y
+        } else {
+            Vec3::X
+        };
+
+        let u = n.cross(tangent).normalize();
+        (u, n.cross(u))
+    }
+
     pub fn material(&self) -> &Material {
         &self.material
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Triangle {
     v0: Vec3,
     v1: Vec3,
     v2: Vec3,
+    uvs: [Vec2; 3],
+    normals: Option<[Vec3; 3]>,
     material: Material
 }
 
 impl Triangle {
     pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: Material) -> Self {
-        Self { v0, v1, v2, material }
+        Self { v0, v1, v2, uvs: [Vec2::ZERO, Vec2::X, Vec2::Y], normals: None, material }
+    }
+
+    /// Per-vertex normals for smooth shading, interpolated across the triangle.
+    pub fn with_normals(mut self, normals: Option<[Vec3; 3]>) -> Self {
+        self.normals = normals;
+        self
+    }
+
+    pub fn normals(&self) -> Option<[Vec3; 3]> {
+        self.normals
+    }
+
+    /// Moves the vertices by `transform`, normals go through its inverse transpose.
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let normal_matrix = transform.inverse().transpose();
+        let [v0, v1, v2] = self.get_vertices().map(|v| transform.transform_point3(v));
+        let normals = self.normals.map(|normals| normals.map(|n| normal_matrix.transform_vector3(n).normalize_or_zero()));
+        Triangle { v0, v1, v2, normals, ..*self }
+    }
+
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+        self
+    }
+
+    pub fn area(&self) -> f32 {
+        0.5 * (self.v1 - self.v0).cross(self.v2 - self.v0).length()
+    }
+
+    pub fn geometric_normal(&self) -> Vec3 {
+        (self.v1 - self.v0).cross(self.v2 - self.v0).normalize()
+    }
+
+    pub fn with_uvs(mut self, uvs: [Vec2; 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn uvs(&self) -> [Vec2; 3] {
+        self.uvs
     }
 
     pub fn v0(&self) -> Vec3 {
@@ -151,15 +226,21 @@ impl Hittable for Triangle {
 
         if t > 0.001 {
             let hit_pos = ray.at(t);
-            let normal = edge1.cross(edge2).normalize();
+            let mut normal = edge1.cross(edge2).normalize();
+            if let Some([n0, n1, n2]) = self.normals {
+                normal = shading_normal(normal, n0 * (1.0 - u - v) + n1 * u + n2 * v);
+            }
+            let uv = self.uvs[0] * (1.0 - u - v) + self.uvs[1] * u + self.uvs[2] * v;
 
             return HitInfo {
                 has_hit: true,
                 t: t as f64,
                 pos: hit_pos,
-                sent_ray: ray.clone(),
+                sent_ray: *ray,
                 normal,
+                uv,
                 material: self.material,
+                moving: false,
             };
         }
 
@@ -172,6 +253,9 @@ impl Hittable for Triangle {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
     fn to_aabb(&self) -> AABB {
         let mut min = Vec3::splat(f32::MAX);
         let mut max = Vec3::splat(f32::MIN);
@@ -206,15 +290,7 @@ impl Hittable for Plane {
 
         let hit_pos = ray.at(t);
         let n = self.normal.normalize();
-
-        let tangent = if n.x.abs() > 0.9 {
-            Vec3::Y
-        } else {
-            Vec3::X
-        };
-
-        let u = n.cross(tangent).normalize();
-        let v = n.cross(u);
+        let (u, v) = self.axes();
 
         let local = hit_pos - self.center;
         let u_dist = local.dot(u);
@@ -228,9 +304,11 @@ impl Hittable for Plane {
             has_hit: true,
             t: t as f64,
             pos: hit_pos,
-            sent_ray: ray.clone(),
+            sent_ray: *ray,
             normal: n,
+            uv: Vec2::new(u_dist / self.width + 0.5, v_dist / self.length + 0.5),
             material: self.material,
+            moving: false,
         }
     }
 
@@ -240,6 +318,9 @@ impl Hittable for Plane {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
     fn to_aabb(&self) -> AABB {
         let (u, v) = plane_basis(self.normal);
 
@@ -277,7 +358,8 @@ fn plane_basis(normal: Vec3) -> (Vec3, Vec3) {
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray) -> HitInfo {
-        let oc = ray.origin() - self.pos;
+        let center = self.center_at(ray.time());
+        let oc = ray.origin() - center;
         let a = ray.direction().dot(ray.direction());
         let b = 2.0 * oc.dot(ray.direction());
         let c = oc.dot(oc) - self.radius * self.radius;
@@ -293,15 +375,17 @@ impl Hittable for Sphere {
 
             if t > 0.001 {
                 let hit_pos = ray.at(t);
-                let normal = (hit_pos - self.pos).normalize();
+                let normal = (hit_pos - center).normalize();
 
                 return HitInfo {
                     has_hit: true,
                     t: t as f64,
                     pos: hit_pos,
-                    sent_ray: ray.clone(),
+                    sent_ray: *ray,
                     normal,
-                    material: self.material
+                    uv: sphere_uv(normal),
+                    material: self.material,
+                    moving: false,
                 };
             }
         }
@@ -315,8 +399,11 @@ impl Hittable for Sphere {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
     fn to_aabb(&self) -> AABB {
-        AABB::new(self.pos - self.radius, self.pos + self.radius)
+        AABB::new(self.pos.min(self.end_pos) - self.radius, self.pos.max(self.end_pos) + self.radius)
     }
 }
 
@@ -339,6 +426,9 @@ impl Hittable for Mesh {
     fn as_any(&self) -> &dyn Any {
         self
     }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
     fn to_aabb(&self) -> AABB {
         let (min, max) = self.get_triangles().iter().flat_map(|tri| {
             tri.get_vertices()
@@ -351,13 +441,28 @@ impl Hittable for Mesh {
     }
 }
 
+/// Keeps the interpolated normal on the same side as the geometric one, so the bounce never starts inside the surface.
+fn shading_normal(geometric: Vec3, interpolated: Vec3) -> Vec3 {
+    let normal = interpolated.normalize_or(geometric);
+    if normal.dot(geometric) < 0.0 { -normal } else { normal }
+}
+
+/// Longitude/latitude mapping, u wraps around Y starting at -X and v goes from the south to the north pole.
+pub fn sphere_uv(normal: Vec3) -> Vec2 {
+    let u = 0.5 + normal.z.atan2(-normal.x) / (2.0 * std::f32::consts::PI);
+    let v = 0.5 + normal.y.clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+    Vec2::new(u, v)
+}
+
 fn no_hit(ray: &Ray, material: Material) -> HitInfo {
     HitInfo {
         has_hit: false,
         t: f64::INFINITY,
         pos: Vec3::ZERO,
-        sent_ray: ray.clone(),
+        sent_ray: *ray,
         normal: Vec3::ZERO,
+        uv: Vec2::ZERO,
         material,
+        moving: false,
     }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::color::Color;
+use crate::window::Canvas;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Hdr,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the averaged accumulation buffer of the canvas, picking the format from the file extension.
+/// PNG and PPM are tonemapped to 8 bits, HDR and EXR keep the raw linear radiance.
+pub fn write_image(path: &str, canvas: &Canvas) -> std::io::Result<()> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("Unknown image format for '{}', expected .png, .ppm, .hdr or .exr", path),
+    ))?;
+
+    let pixels = canvas.averaged_accumulation();
+    let (width, height) = (canvas.width(), canvas.height());
+
+    match format {
+        ImageFormat::Png => write_png(path, width, height, &pixels),
+        ImageFormat::Ppm => write_ppm(path, width, height, &pixels),
+        ImageFormat::Hdr => write_hdr(path, width, height, &pixels),
+        ImageFormat::Exr => write_exr(path, width, height, &pixels),
+    }
+}
+
+pub fn write_png(path: &str, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header().map_err(std::io::Error::other)?;
+    png_writer.write_image_data(&to_display_bytes(pixels)).map_err(std::io::Error::other)?;
+    png_writer.finish().map_err(std::io::Error::other)
+}
+
+pub fn write_ppm(path: &str, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(&to_display_bytes(pixels))?;
+    writer.flush()
+}
+
+/// Radiance RGBE, with run-length encoded scanlines where the width allows it.
+pub fn write_hdr(path: &str, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+
+    for row in pixels.chunks(width as usize) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|&color| to_rgbe(color)).collect();
+
+        if !(8..=0x7fff).contains(&width) {
+            for pixel in &rgbe {
+                writer.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        writer.write_all(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8])?;
+        for channel in 0..4 {
+            let bytes: Vec<u8> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+            write_rle_channel(&mut writer, &bytes)?;
+        }
+    }
+
+    writer.flush()
+}
+
+pub fn write_exr(path: &str, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()> {
+    exr::prelude::write_rgb_file(path, width as usize, height as usize, |x, y| {
+        let color = pixels[y * width as usize + x];
+        (color.r, color.g, color.b)
+    }).map_err(std::io::Error::other)
+}
+
+fn to_display_bytes(pixels: &[Color]) -> Vec<u8> {
+    pixels.iter().flat_map(|&color| {
+        let color = color.tonemap().gamma_correct().clamp(0.0, 1.0);
+        [to_byte(color.r), to_byte(color.g), to_byte(color.b)]
+    }).collect()
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value * 255.0 + 0.5) as u8
+}
+
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let max = color.r.max(color.g).max(color.b);
+    if max < 1e-32 || !max.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+
+    [
+        (color.r.max(0.0) * scale).min(255.0) as u8,
+        (color.g.max(0.0) * scale).min(255.0) as u8,
+        (color.b.max(0.0) * scale).min(255.0) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn write_rle_channel(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    const MIN_RUN: usize = 4;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < bytes.len() {
+            run_length = 1;
+            while run_start + run_length < bytes.len() && run_length < 127
+                && bytes[run_start + run_length] == bytes[run_start] {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        while i < run_start {
+            let count = (run_start - i).min(128);
+            writer.write_all(&[count as u8])?;
+            writer.write_all(&bytes[i..i + count])?;
+            i += count;
+        }
+
+        if run_length >= MIN_RUN {
+            writer.write_all(&[128 + run_length as u8, bytes[run_start]])?;
+            i += run_length;
+        }
+    }
+
+    Ok(())
+}
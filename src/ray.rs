@@ -1,21 +1,28 @@
 use crate::camera::Camera;
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use rand::random;
 
 #[derive(Clone, Debug, Copy)]
 pub struct Ray {
     origin: Vec3,
-    direction: Vec3
+    direction: Vec3,
+    /// When in the shutter interval the ray was sent, from 0 at the start transforms to 1 at the end ones.
+    time: f32,
 }
 #[allow(dead_code)]
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self { origin, direction, time: 0.0 }
+    }
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
     }
     pub fn origin(&self) -> Vec3 { self.origin }
     pub fn direction(&self) -> Vec3 { self.direction }
+    pub fn time(&self) -> f32 { self.time }
     pub fn reflect(&self, normal: Vec3) -> Self {
-        Self::new(self.origin(), self.direction() - 2.0 * normal.dot(self.direction()) * normal)
+        Self::new(self.origin(), self.direction() - 2.0 * normal.dot(self.direction()) * normal).with_time(self.time)
     }
 
     pub fn rotate_x(&mut self, angle: f32) {
@@ -51,23 +58,10 @@ impl Ray {
     pub fn at(&self, t: f32) -> Vec3 { self.origin + self.direction * t }
 }
 
-pub fn get_ray_from_screen(camera: &Camera, x: u32, y: u32) -> Ray {
-    let fov_y: f32 = 90.0f32.to_radians();
-    let aspect = camera.width() as f32 / camera.height() as f32;
-
-    let ndc_x = (x as f32 + 0.5) / camera.width() as f32;
-    let ndc_y = (y as f32 + 0.5) / camera.height() as f32;
-
-    let screen_x = (2.0 * ndc_x - 1.0) * aspect * (fov_y * 0.5).tan();
-    let screen_y = (1.0 - 2.0 * ndc_y) * (fov_y * 0.5).tan();
-
-    let forward = camera.ray().direction().normalize();
-    let right = Vec3::new(0.0, 1.0, 0.0).cross(forward).normalize();
-    let up = forward.cross(right);
-
-    let direction = (forward + right * screen_x + up * screen_y).normalize();
-
-    Ray::new(camera.ray().origin(), direction)
+/// A ray through the pixel, `offset` pixels away from its centre. `None` where the projection doesn't cover that point.
+pub fn get_ray_from_screen(camera: &Camera, x: u32, y: u32, offset: Vec2) -> Option<Ray> {
+    camera.lens_ray(x as f32 + 0.5 + offset.x, y as f32 + 0.5 + offset.y, Vec2::new(random::<f32>(), random::<f32>()),
+                    camera.shutter_time(random::<f32>()))
 }
 
 pub fn random_cosine_hemisphere(normal: Vec3) -> Vec3 {
@@ -95,7 +89,17 @@ pub fn random_cosine_hemisphere(normal: Vec3) -> Vec3 {
         normal * local.z).normalize()
 }
 
-pub fn lerp(ray1: &Ray, ray2: &Ray, t: f32) -> Ray {
-    Ray::new(ray1.origin() + (ray2.origin() - ray1.origin()) * t, ray1.direction() + (ray2.direction() - ray1.direction()) * t)
+pub fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = (-direction).dot(normal).min(1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+        return None;
+    }
+    Some(eta * direction + (eta * cos_i - (1.0 - sin2_t).sqrt()) * normal)
+}
+
+pub fn schlick_reflectance(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
 }
 
@@ -1,39 +1,54 @@
-use crate::bvh::{construct_bvh, traverse_leaf_nodes, AABB};
+use crate::bvh::{traverse_leaf_nodes, AABB};
 use crate::camera::Camera;
 use crate::color::Color;
-use crate::gpu_types::{GpuColor, GpuRay};
+use crate::instance::{transform_aabb, Instance};
 use crate::model::Mesh;
-use crate::objects::HitInfo;
 use crate::profiler::{profiler_start, profiler_stop};
 use crate::ray::Ray;
 use crate::scene::Scene;
 use crate::window::Canvas;
-use crate::{compute, ray};
-use wgpu::PollType;
+use crate::{brdf, compute, ray};
+use crate::emitter::{power_heuristic, EmitterList};
+use crate::light::Light;
+use crate::material::Material;
+use glam::{Vec2, Vec3};
+use rand::random;
 
-pub struct Renderer {
+/// Shadow rays count as unoccluded when they stop this close to the light, relative to its distance.
+const SHADOW_EPSILON: f32 = 1.0e-3;
 
+pub struct Renderer {
+    max_bounces: u32,
 }
 
-const MAX_RECURSION: u8 = 5;
-
 impl Renderer {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(max_bounces: u32) -> Self {
+        Self { max_bounces }
     }
     #[allow(dead_code)]
     pub fn render(&self, camera: &Camera, scene: &Scene, canvas: &mut Canvas) {
+        if canvas.sample_count == 0 || canvas.accum_buffer.len() != canvas.pixel_count() as usize {
+            canvas.accum_buffer = vec![Color::black(); canvas.pixel_count() as usize];
+            canvas.accum_weights = vec![0.0; canvas.pixel_count() as usize];
+        }
+
+        let emitters = EmitterList::from_scene(scene);
+        let lights = scene.export_lights();
+        let filter = camera.filter();
         camera.for_each_pixel(|x, y| {
-            let ray = ray::get_ray_from_screen(camera, x, y);
-            let sample = recursive_bounce(ray, Color::white(), scene, 0);
+            let path = Path { scene, emitters: &emitters, lights: &lights, max_bounces: self.max_bounces };
+            // spread over the filter's footprint like pixel_offset in raytracer.wgsl
+            let offset = (Vec2::new(random::<f32>(), random::<f32>()) * 2.0 - 1.0) * filter.radius();
+            let weight = filter.weight(offset);
+            let sample = ray::get_ray_from_screen(camera, x, y, offset)
+                .map_or(Color::black(), |ray| recursive_bounce(ray, Color::white(), &path, 0, 0.0));
 
             let idx = (y * canvas.width() + x) as usize;
-            canvas.accum_buffer[idx] = canvas.accum_buffer[idx] + sample;
-
-            let avg = canvas.accum_buffer[idx] / (canvas.sample_count as f32 + 1.0);
-            canvas.paint_pixel(x, y, avg.gamma_correct().to_u32());
+            canvas.accum_buffer[idx] += sample.finite_or_black() * weight;
+            canvas.accum_weights[idx] += weight;
         });
 
+        canvas.write_accumulation(&canvas.accum_buffer, &canvas.accum_weights);
         canvas.sample_count += 1;
     }
 
@@ -41,15 +56,14 @@ impl Renderer {
         if should_clear { canvas.clear(camera); }
 
         for (i, object) in scene.get_objects().iter().enumerate() {
-            if let Some(mesh) = object.as_any().downcast_ref::<Mesh>() {
+            let placed = object.as_any().downcast_ref::<Instance>().map(|instance| (instance.mesh().as_ref(), instance.transform()))
+                .or_else(|| object.as_any().downcast_ref::<Mesh>().map(|mesh| (mesh, mesh.transform())));
+            if let Some((mesh, transform)) = placed {
                 let bvh = mesh.bvh.as_ref().unwrap();
-                traverse_leaf_nodes(&bvh, &mut |aabb: &AABB, _objects| {
-                    for (a, b) in aabb.edges() {
-                        match (camera.world_to_screen(a), camera.world_to_screen(b)) {
-                            (Some(pa), Some(pb)) => {
-                                canvas.draw_line(pa, pb, Color::random_from_seed(i as u32).to_u32());
-                            }
-                            _ => {},
+                traverse_leaf_nodes(bvh, &mut |aabb: &AABB, _indices| {
+                    for (a, b) in transform_aabb(aabb, &transform).edges() {
+                        if let (Some(pa), Some(pb)) = (camera.world_to_screen(a), camera.world_to_screen(b)) {
+                            canvas.draw_line(pa, pb, Color::random_from_seed(i as u32).to_u32());
                         }
                     }
                 });
@@ -58,11 +72,8 @@ impl Renderer {
 
             let aabb = object.to_aabb();
             for (a, b) in aabb.edges() {
-                match (camera.world_to_screen(a), camera.world_to_screen(b)) {
-                    (Some(pa), Some(pb)) => {
-                        canvas.draw_line(pa, pb, Color::random_from_seed(i as u32).to_u32());
-                    }
-                    _ => {},
+                if let (Some(pa), Some(pb)) = (camera.world_to_screen(a), camera.world_to_screen(b)) {
+                    canvas.draw_line(pa, pb, Color::random_from_seed(i as u32).to_u32());
                 }
             }
         }
@@ -73,24 +84,13 @@ impl Renderer {
         profiler_start("render gpu");
 
         if canvas.compute_pipeline.is_none() {
-            compute::setup_compute_pipeline(canvas, scene);
+            compute::setup_compute_pipeline(canvas, scene, self.max_bounces);
         }
 
-        let mut rays = Vec::with_capacity((canvas.width() * canvas.height()) as usize);
-        camera.for_each_pixel(|x, y| {
-            let ray = ray::get_ray_from_screen(camera, x, y);
-            rays.push(GpuRay {
-                origin: [ray.origin().x, ray.origin().y, ray.origin().z],
-                _pad0: 0.0,
-                direction: [ray.direction().x, ray.direction().y, ray.direction().z],
-                _pad1: 0.0,
-            });
-        });
-
         canvas.queue().write_buffer(
-            canvas.ray_buffer.as_ref().unwrap(),
+            canvas.camera_buffer.as_ref().unwrap(),
             0,
-            bytemuck::cast_slice(&rays)
+            bytemuck::bytes_of(&camera.to_gpu_camera()),
         );
 
         canvas.queue().write_buffer(
@@ -112,101 +112,223 @@ impl Renderer {
             compute_pass.set_pipeline(canvas.compute_pipeline.as_ref().unwrap());
             compute_pass.set_bind_group(0, canvas.compute_bind_group.as_ref().unwrap(), &[]);
 
-            let workgroups_x = (canvas.width() + 7) / 8;
-            let workgroups_y = (canvas.height() + 7) / 8;
+            let workgroups_x = canvas.width().div_ceil(8);
+            let workgroups_y = canvas.height().div_ceil(8);
 
             compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
         }
 
-        encoder.copy_buffer_to_buffer(
-            canvas.color_buffer.as_ref().unwrap(),
-            0,
-            canvas.staging_buffer.as_ref().unwrap(),
-            0,
-            (canvas.pixel_count() as usize * std::mem::size_of::<GpuColor>()) as u64,
-        );
-
         canvas.queue().submit(std::iter::once(encoder.finish()));
-        canvas.device().poll(PollType::Wait { submission_index: None, timeout: None })
-            .expect("GPU was NOT polled");
+        canvas.sample_count += 1;
 
         profiler_stop("render gpu");
-        profiler_start("cpu accumulation");
+    }
+    
+    #[allow(dead_code)]
+    pub fn clear(&self, camera: &Camera, canvas: &mut Canvas) {
+        camera.for_each_pixel(|x, y| {
+            canvas.paint_pixel(x, y, Color::black().to_u32());
+        });
+    }
+}
 
-        let buffer_slice = canvas.staging_buffer.as_ref().unwrap().slice(..);
-        let (tx, rx) = futures::channel::oneshot::channel();
+/// What stays the same over every bounce of a path.
+struct Path<'a> {
+    scene: &'a Scene,
+    emitters: &'a EmitterList,
+    lights: &'a [Light],
+    max_bounces: u32,
+}
 
-        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-            tx.send(result).unwrap();
-        });
+/// `brdf_pdf` is the density the BRDF sampled `ray` with, zero for camera rays and refractions which light sampling
+/// doesn't cover.
+fn recursive_bounce(ray: Ray, color: Color, path: &Path, bounce_num: u32, brdf_pdf: f32) -> Color {
+    let scene = path.scene;
+    let hit = scene.hit(&ray);
+    let max_t = hit.as_ref().map_or(f32::INFINITY, |info| info.t as f32);
+    if let Some(radiance) = hit_lights(&ray, max_t, bounce_num == 0, brdf_pdf, path.lights) {
+        return color * radiance;
+    }
 
-        canvas.device().poll(PollType::Wait { submission_index: None, timeout: None })
-            .expect("GPU was NOT polled");
-        pollster::block_on(rx).unwrap().unwrap();
+    let Some(mut info) = hit else {
+        let environment = scene.environment();
+        // light sampling may have picked this direction from the previous bounce as well
+        let weight = if brdf_pdf > 0.0 && environment.is_sampled() {
+            power_heuristic(brdf_pdf, environment.pdf(ray.direction()))
+        } else {
+            1.0
+        };
+        return color * environment.radiance(ray.direction()) * weight;
+    };
+
+    info.material = info.material.at_uv(info.uv, scene.textures());
+
+    if info.material.emission > 0.0 {
+        let radiance = info.material.emitted();
+        // light sampling already reached this emitter from the previous bounce, weigh the two against each other
+        if brdf_pdf > 0.0 && !info.moving {
+            let distance = info.t as f32 * ray.direction().length();
+            let cos_light = info.normal.normalize().dot(ray.direction().normalize()).abs();
+            let light_pdf = path.emitters.pdf(radiance, distance, cos_light);
+            return color * radiance * power_heuristic(brdf_pdf, light_pdf);
+        }
+        return color * radiance;
+    }
 
-        {
-            let data = buffer_slice.get_mapped_range();
-            let colors: &[GpuColor] = bytemuck::cast_slice(&data);
+    // the shader's loop ends here on its last bounce, before light sampling or scattering
+    if bounce_num + 1 >= path.max_bounces {
+        return Color::black();
+    }
 
-            camera.for_each_pixel(|x, y| {
-                let idx = (y * canvas.width() + x) as usize;
-                let gpu_color = &colors[idx];
-                let color = Color::new(gpu_color.r, gpu_color.g, gpu_color.b);
+    let normal = info.normal.normalize();
 
-                canvas.accum_buffer[idx] = canvas.accum_buffer[idx] + color;
-                let avg = canvas.accum_buffer[idx] / (canvas.sample_count as f32 + 1.0);
-                canvas.paint_pixel(x, y, avg.gamma_correct().to_u32());
-            });
-        }
+    if info.material.transmission > 0.0 && random::<f32>() < info.material.transmission {
+        let direction = ray.direction().normalize();
+        let out_dir = sample_dielectric(direction, normal, info.material.ior, info.material.roughness);
+        let entering = direction.dot(normal) < 0.0;
+        let final_color = if entering && out_dir.dot(normal) < 0.0 { color * info.material.albedo } else { color };
 
-        canvas.staging_buffer.as_ref().unwrap().unmap();
-        canvas.sample_count += 1;
+        let offset = normal * 0.001 * out_dir.dot(normal).signum();
+        return recursive_bounce(Ray::new(info.pos + offset, out_dir).with_time(ray.time()), final_color, path, bounce_num + 1, 0.0);
+    }
 
-        profiler_stop("cpu accumulation");
+    // opaque surfaces scatter off whichever side the ray arrives at
+    let view = -ray.direction().normalize();
+    let facing = if normal.dot(view) < 0.0 { -normal } else { normal };
+    let origin = info.pos + facing * 0.001;
+    let direct = sample_emitters(&info.material, origin, facing, view, ray.time(), path)
+        + sample_lights(&info.material, origin, facing, view, ray.time(), path)
+        + sample_environment(&info.material, origin, facing, view, ray.time(), scene);
+
+    let u = Vec3::new(random::<f32>(), random::<f32>(), random::<f32>());
+    let Some(scattered) = brdf::sample(&info.material, facing, view, u) else {
+        return color * direct;
+    };
+
+    let scattered_ray = Ray::new(info.pos + facing * 0.001, scattered.direction).with_time(ray.time());
+    color * direct + recursive_bounce(scattered_ray, color * scattered.weight, path, bounce_num + 1, scattered.pdf)
+}
+
+/// Light from one point on one emitter, picked by power, reaching `origin` unoccluded. Weighed against the BRDF
+/// finding the same point, the same as `sample_emitters` in raytracer.wgsl.
+fn sample_emitters(material: &Material, origin: Vec3, normal: Vec3, view: Vec3, time: f32, path: &Path) -> Color {
+    let Some(emitter) = path.emitters.pick(random::<f32>()) else {
+        return Color::black();
+    };
+    let (point, light_normal) = emitter.sample_point(Vec2::new(random::<f32>(), random::<f32>()), time);
+
+    let to_light = point - origin;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    let cos_light = light_normal.dot(direction).abs();
+    let n_dot_l = normal.dot(direction);
+    if n_dot_l <= 0.0 || cos_light <= 0.0 {
+        return Color::black();
     }
-    
-    #[allow(dead_code)]
-    pub fn clear(&self, camera: &Camera, canvas: &mut Canvas) {
-        camera.for_each_pixel(|x, y| {
-            canvas.paint_pixel(x, y, Color::black().to_u32());
-        });
+
+    let (value, brdf_pdf) = brdf::evaluate(material, normal, view, direction);
+    if brdf_pdf <= 0.0 {
+        return Color::black();
+    }
+
+    let shadow_ray = Ray::new(origin, direction).with_time(time);
+    if path.scene.hit(&shadow_ray).is_some_and(|hit| (hit.t as f32) < distance * (1.0 - SHADOW_EPSILON)) {
+        return Color::black();
     }
+
+    let light_pdf = path.emitters.pdf(emitter.radiance, distance, cos_light);
+    emitter.radiance * value * (n_dot_l / light_pdf * power_heuristic(light_pdf, brdf_pdf))
 }
 
-fn recursive_bounce(ray: Ray, color: Color, scene: &Scene, bounce_num: u8) -> Color {
-    let mut closest_hit: Option<HitInfo> = None;
-    let mut closest_t = f64::INFINITY;
+/// Light from one uniformly picked light reaching `origin` unoccluded, the same as `sample_lights` in raytracer.wgsl.
+/// Lights don't block each other's shadow rays.
+fn sample_lights(material: &Material, origin: Vec3, normal: Vec3, view: Vec3, time: f32, path: &Path) -> Color {
+    let lights = path.lights;
+    if lights.is_empty() {
+        return Color::black();
+    }
+    let light = &lights[((random::<f32>() * lights.len() as f32) as usize).min(lights.len() - 1)];
+    let Some(sample) = light.sample(origin, Vec2::new(random::<f32>(), random::<f32>())) else {
+        return Color::black();
+    };
+    let n_dot_l = normal.dot(sample.direction);
+    if n_dot_l <= 0.0 {
+        return Color::black();
+    }
 
-    for hittable in scene.get_objects() {
-        let info = hittable.hit(&ray);
-        if info.has_hit && info.t < closest_t {
-            closest_t = info.t;
-            closest_hit = Some(info);
-        }
+    let (value, brdf_pdf) = brdf::evaluate(material, normal, view, sample.direction);
+    if brdf_pdf <= 0.0 {
+        return Color::black();
     }
 
-    if let Some(info) = closest_hit {
-        if info.material.emission > 0.0 {
-            let color = color * info.material.albedo * info.material.emission;
-            return color;
-        }
+    let shadow_ray = Ray::new(origin, sample.direction).with_time(time);
+    if path.scene.hit(&shadow_ray).is_some_and(|hit| (hit.t as f32) < sample.distance * (1.0 - SHADOW_EPSILON)) {
+        return Color::black();
+    }
 
-        if bounce_num >= MAX_RECURSION {
-            return Color::black();
-        }
+    let pdf = sample.pdf / lights.len() as f32;
+    let weight = if sample.delta { 1.0 } else { power_heuristic(pdf, brdf_pdf) };
+    sample.radiance * value * (n_dot_l / pdf * weight)
+}
 
-        let normal = info.normal.normalize();
-        let diffuse_dir = ray::random_cosine_hemisphere(normal);
-        let diffuse_ray = Ray::new(info.pos + normal * 0.001, diffuse_dir);
+/// Light from one direction of the environment map, picked by brightness, reaching `origin` unblocked by the scene.
+/// The same as `sample_environment` in raytracer.wgsl.
+fn sample_environment(material: &Material, origin: Vec3, normal: Vec3, view: Vec3, time: f32, scene: &Scene) -> Color {
+    let environment = scene.environment();
+    if !environment.is_sampled() {
+        return Color::black();
+    }
+    let Some((direction, radiance, pdf)) = environment.sample(Vec2::new(random::<f32>(), random::<f32>())) else {
+        return Color::black();
+    };
+    let n_dot_l = normal.dot(direction);
+    if n_dot_l <= 0.0 {
+        return Color::black();
+    }
 
-        let specular_dir = ray.reflect(info.normal);
-        let specular_ray = Ray::new(info.pos + normal * 0.001, specular_dir.direction().normalize());
-        let final_ray = ray::lerp(&specular_ray, &diffuse_ray, info.material.roughness);
+    let (value, brdf_pdf) = brdf::evaluate(material, normal, view, direction);
+    if brdf_pdf <= 0.0 || scene.hit(&Ray::new(origin, direction).with_time(time)).is_some() {
+        return Color::black();
+    }
+    radiance * value * (n_dot_l / pdf * power_heuristic(pdf, brdf_pdf))
+}
 
-        let final_color = color * info.material.albedo * (info.material.metallic * specular_ray.dot() + (1.0 - info.material.metallic) * diffuse_ray.dot());
+/// Radiance of the closest light `ray` reaches before `max_t`, weighed against `sample_lights` finding it from the
+/// previous bounce like `hit_lights` in raytracer.wgsl. Camera rays pass through lights that aren't visible.
+fn hit_lights(ray: &Ray, max_t: f32, camera_ray: bool, brdf_pdf: f32, lights: &[Light]) -> Option<Color> {
+    let (t, radiance, light) = lights.iter()
+        .filter(|light| light.visible || !camera_ray)
+        .filter_map(|light| light.hit(ray, max_t).map(|(t, radiance)| (t, radiance, light)))
+        .min_by(|a, b| a.0.total_cmp(&b.0))?;
 
-        recursive_bounce(final_ray, final_color, scene, bounce_num + 1)
+    if brdf_pdf <= 0.0 {
+        return Some(radiance);
+    }
+    let distance = t * ray.direction().length();
+    let light_pdf = light.pdf(ray.origin(), ray.direction().normalize(), distance) / lights.len() as f32;
+    Some(radiance * power_heuristic(brdf_pdf, light_pdf))
+}
+
+fn sample_dielectric(direction: Vec3, normal: Vec3, ior: f32, roughness: f32) -> Vec3 {
+    let (mut normal, eta) = if direction.dot(normal) > 0.0 { (-normal, ior) } else { (normal, 1.0 / ior) };
+
+    if roughness > 0.0 {
+        let jittered = normal.lerp(ray::random_cosine_hemisphere(normal), roughness * roughness).normalize();
+        if direction.dot(jittered) < 0.0 {
+            normal = jittered;
+        }
+    }
+
+    let reflected = direction - 2.0 * direction.dot(normal) * normal;
+    let Some(refracted) = ray::refract(direction, normal, eta) else {
+        return reflected;
+    };
+
+    let cos_i = (-direction).dot(normal).min(1.0);
+    let cos_fresnel = if eta > 1.0 { (1.0 - eta * eta * (1.0 - cos_i * cos_i)).sqrt() } else { cos_i };
+    if random::<f32>() < ray::schlick_reflectance(cos_fresnel, ior) {
+        reflected
     } else {
-        Color::black()
+        refracted.normalize()
     }
-}
\ No newline at end of file
+}
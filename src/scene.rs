@@ -1,86 +1,424 @@
+use crate::camera::Camera;
+use crate::objects::{HitInfo, Hittable, Plane, Sphere, Triangle};
+use glam::{Mat4, Vec3};
+use crate::gpu_types::{GpuMaterial, GpuPlane, GpuSphere, GpuTexture, GpuTextureIds, GpuTriangle};
+use crate::environment::Environment;
+use crate::instance::Instance;
+use crate::light::Light;
 use crate::color::Color;
-use crate::importer::import_obj;
 use crate::material::Material;
-use crate::objects::{Hittable, Plane, Sphere, Triangle};
-use glam::Vec3;
-use crate::bvh::construct_bvh;
-use crate::gpu_types::{GpuPlane, GpuSphere, GpuTriangle};
 use crate::model::Mesh;
 use crate::profiler::{profiler_start, profiler_stop};
+use crate::ray::Ray;
+use crate::texture::Texture;
+
+/// One placement of a mesh from `Scene::export_meshes`, what a top-level BVH leaf refers to.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneInstance {
+    pub mesh: usize,
+    pub transform: Mat4,
+    /// Where the instance ends up at the end of the shutter, when it moves.
+    pub end_transform: Option<Mat4>,
+    pub material: Option<Material>,
+}
+
+/// What changed since the GPU last saw the scene, collected by the editing methods of `Scene` and consumed by
+/// `compute::apply_scene_changes`.
+#[derive(Debug, Default)]
+pub struct SceneChanges {
+    /// Objects were added or removed, or changed in a way the buffers cannot absorb in place.
+    pub structure: bool,
+    /// Objects whose material changed.
+    pub materials: Vec<usize>,
+    /// Loose triangles, spheres and planes that changed shape or moved, their BVH gets refit.
+    pub geometry: Vec<usize>,
+    /// Instances moved, the top-level BVH gets rebuilt.
+    pub transforms: bool,
+}
+
+impl SceneChanges {
+    pub fn is_empty(&self) -> bool {
+        !self.structure && self.materials.is_empty() && self.geometry.is_empty() && !self.transforms
+    }
+}
+
+/// Where an object's data sits on the GPU, the index into the buffer of its kind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GpuSlot {
+    Sphere(usize),
+    Plane(usize),
+    Triangle(usize),
+    Instance(usize),
+}
 
 pub struct Scene {
-    objects: Vec<Box<dyn Hittable>>
+    objects: Vec<Box<dyn Hittable>>,
+    camera: Camera,
+    textures: Vec<Texture>,
+    lights: Vec<Light>,
+    environment: Environment,
+    changes: SceneChanges,
 }
 
 impl Scene {
     pub fn new() -> Self {
-        Self { objects: Vec::new() }
+        Self {
+            objects: Vec::new(),
+            camera: Camera::look_at(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 4.0), Vec3::Y, 90.0),
+            textures: Vec::new(),
+            lights: Vec::new(),
+            environment: Environment::default(),
+            changes: SceneChanges::default(),
+        }
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
     }
     
     pub fn add_object(&mut self, object: Box<dyn Hittable>) -> &mut Self {
         self.objects.push(object);
+        self.changes.structure = true;
         self
     }
 
-    pub fn export_gpu_data(&self) -> (Vec<GpuSphere>, Vec<GpuTriangle>, Vec<GpuPlane>) {
-        profiler_start("export gpu data");
-        let mut spheres = Vec::new();
-        let mut triangles = Vec::new();
-        let mut planes = Vec::new();
+    pub fn remove_object(&mut self, object: usize) -> Option<Box<dyn Hittable>> {
+        if object >= self.objects.len() {
+            return None;
+        }
+        self.changes.structure = true;
+        Some(self.objects.remove(object))
+    }
 
-        for obj in self.get_objects() {
-            if let Some(sphere) = obj.as_any().downcast_ref::<Sphere>() {
-                let center = sphere.center();
-                let mat = sphere.material();
-                let albedo = mat.albedo();
-
-                spheres.push(GpuSphere {
-                    center: [center.x, center.y, center.z],
-                    radius: sphere.radius(),
-                    albedo: [albedo.r, albedo.g, albedo.b],
-                    emission: mat.emission(),
-                    metallic: mat.metallic(),
-                    roughness: mat.roughness(),
-                    _padding: [0.0, 0.0],
-                });
+    /// Swaps the object at `object` for another one. A loose primitive replaced by one of the same kind only
+    /// refits the BVH, anything else rebuilds the scene buffers.
+    pub fn replace_object(&mut self, object: usize, replacement: Box<dyn Hittable>) -> Option<Box<dyn Hittable>> {
+        let slot = self.gpu_slot(object)?;
+        let old = std::mem::replace(&mut self.objects[object], replacement);
+
+        match (slot, self.gpu_slot(object)) {
+            (GpuSlot::Instance(_), _) => self.changes.structure = true,
+            (old_slot, Some(new_slot)) if std::mem::discriminant(&old_slot) == std::mem::discriminant(&new_slot) => {
+                self.changes.geometry.push(object)
             }
+            _ => self.changes.structure = true,
+        }
+        Some(old)
+    }
+
+    /// Changes an object's material, for instances this sets the material override.
+    pub fn set_material(&mut self, object: usize, material: Material) -> bool {
+        if self.gpu_slot(object).is_none() {
+            return false;
+        }
+        self.objects[object].set_material(material);
+
+        // a mesh outside an instance has its material baked into its triangles
+        if self.objects[object].as_any().is::<Mesh>() {
+            self.changes.structure = true;
+        } else {
+            self.changes.materials.push(object);
+        }
+        true
+    }
 
-            else if let Some(plane) = obj.as_any().downcast_ref::<Plane>() {
-                let center = plane.center();
-                let normal = plane.normal();
-                let mat = plane.material();
-                let albedo = mat.albedo();
-
-                planes.push(GpuPlane {
-                    center: [center.x, center.y, center.z, 0.0],
-                    normal: [normal.x, normal.y, normal.z, 0.0],
-                    width: plane.width(),
-                    length: plane.length(),
-                    _pad2: [0.0, 0.0],
-                    albedo: [albedo.r, albedo.g, albedo.b, 0.0],
-                    emission: mat.emission(),
-                    metallic: mat.metallic(),
-                    roughness: mat.roughness(),
-                    _pad3: 0.0,
-                });
+    /// Moves the instance at `object`, returns false when that object is not an instance.
+    pub fn set_instance_transform(&mut self, object: usize, transform: Mat4) -> bool {
+        match self.objects.get_mut(object).and_then(|object| object.as_any_mut().downcast_mut::<Instance>()) {
+            Some(instance) => {
+                instance.set_transform(transform);
+                self.changes.transforms = true;
+                true
             }
+            None => false,
+        }
+    }
+
+    /// Brings the scene in line with `other`, another version of it such as its file loaded again, through the editing
+    /// methods so only what differs goes to the GPU again. The camera stays where it is.
+    pub fn update_from(&mut self, other: Scene) {
+        if self.export_gpu_textures() != other.export_gpu_textures() {
+            self.textures = other.textures;
+            self.changes.structure = true;
+        }
+
+        let gpu_lights = |lights: &[Light]| lights.iter().map(Light::gpu_light).collect::<Vec<_>>();
+        if bytemuck::cast_slice::<_, u8>(&gpu_lights(&self.lights)) != bytemuck::cast_slice::<_, u8>(&gpu_lights(&other.lights)) {
+            self.lights = other.lights;
+            self.changes.structure = true;
+        }
 
-            else if let Some(triangle) = obj.as_any().downcast_ref::<Triangle>() {
-                triangles.push(triangle_to_gpu_triangle(triangle));
+        let (old, new) = (&self.environment, &other.environment);
+        if bytemuck::bytes_of(&old.gpu_environment()) != bytemuck::bytes_of(&new.gpu_environment()) || old.gpu_data() != new.gpu_data() {
+            self.set_environment(other.environment);
+        }
+
+        for object in (other.objects.len()..self.objects.len()).rev() {
+            self.remove_object(object);
+        }
+        for (object, new) in other.objects.into_iter().enumerate() {
+            if object < self.objects.len() {
+                self.update_object(object, new);
+            } else {
+                self.add_object(new);
             }
-            else if let Some(mesh) = obj.as_any().downcast_ref::<Mesh>() {
-                for tri in mesh.get_triangles() {
-                    triangles.push(triangle_to_gpu_triangle(&tri));
+        }
+    }
+
+    /// Turns the object at `object` into `new` with the cheapest edit that gets it there.
+    fn update_object(&mut self, object: usize, new: Box<dyn Hittable>) {
+        let old = self.objects[object].as_any();
+        if let (Some(old), Some(new)) = (old.downcast_ref::<Instance>(), new.as_any().downcast_ref::<Instance>()) {
+            let (old_mesh, new_mesh) = (old.mesh(), new.mesh());
+            let same_mesh = old_mesh.source.is_some() && old_mesh.source == new_mesh.source
+                && old_mesh.crease_angle == new_mesh.crease_angle
+                && old_mesh.bvh.as_ref().map(|bvh| bvh.max_leaf_size) == new_mesh.bvh.as_ref().map(|bvh| bvh.max_leaf_size);
+            let gpu_material = |instance: &Instance| instance.material_override().map(material_to_gpu_material);
+            let material_changed = gpu_material(old).as_ref().map(bytemuck::bytes_of) != gpu_material(new).as_ref().map(bytemuck::bytes_of);
+
+            // an instance keeps its mesh, and so its BVH, when only its placement or material changed
+            if same_mesh && old.end_transform() == new.end_transform() && !(material_changed && new.material_override().is_none()) {
+                let (transform, material) = (new.transform(), new.material_override().copied());
+                if transform != old.transform() {
+                    self.set_instance_transform(object, transform);
+                }
+                if let Some(material) = material.filter(|_| material_changed) {
+                    self.set_material(object, material);
                 }
+                return;
             }
+        } else if loose_gpu_bytes(old).is_some_and(|bytes| Some(bytes) == loose_gpu_bytes(new.as_any())) {
+            return;
         }
+        self.replace_object(object, new);
+    }
+
+    /// Hands out everything that changed since the last call and starts collecting afresh.
+    pub fn take_changes(&mut self) -> SceneChanges {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Position of the object's data in the GPU buffers, following the order `export_gpu_data`, `build_blas` and
+    /// `export_instances` lay them out in.
+    pub fn gpu_slot(&self, object: usize) -> Option<GpuSlot> {
+        let target = self.objects.get(object)?.as_any();
+        let before = &self.objects[..object];
+        let count = |is: fn(&dyn Hittable) -> bool| before.iter().filter(|obj| is(obj.as_ref())).count();
+
+        if target.is::<Sphere>() {
+            Some(GpuSlot::Sphere(count(|obj| obj.as_any().is::<Sphere>())))
+        } else if target.is::<Plane>() {
+            Some(GpuSlot::Plane(count(|obj| obj.as_any().is::<Plane>())))
+        } else if target.is::<Triangle>() {
+            // loose triangles come first in the triangle buffer
+            Some(GpuSlot::Triangle(count(|obj| obj.as_any().is::<Triangle>())))
+        } else if target.is::<Instance>() || target.is::<Mesh>() {
+            let first = if self.has_loose_primitives() { 1 } else { 0 };
+            Some(GpuSlot::Instance(first + count(|obj| obj.as_any().is::<Instance>() || obj.as_any().is::<Mesh>())))
+        } else {
+            None
+        }
+    }
+
+    /// Adds a texture and returns the index materials use to refer to it.
+    pub fn add_texture(&mut self, texture: Texture) -> u32 {
+        self.textures.push(texture);
+        (self.textures.len() - 1) as u32
+    }
+
+    pub fn textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    pub fn add_light(&mut self, light: Light) -> &mut Self {
+        self.lights.push(light);
+        self.changes.structure = true;
+        self
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// The scene's lights and the sun of its sky, everything the renderers sample.
+    pub fn export_lights(&self) -> Vec<Light> {
+        self.lights.iter().copied().chain(self.environment.sun_light()).collect()
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+        self.changes.structure = true;
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Packs every texture into one word buffer: a `GpuTexture` header per texture, then the RGBA8 texels.
+    pub fn export_gpu_textures(&self) -> Vec<u32> {
+        let mut headers = Vec::new();
+        let mut texels = Vec::new();
+
+        for texture in &self.textures {
+            headers.push(GpuTexture {
+                offset: texels.len() as u32,
+                width: texture.width(),
+                height: texture.height(),
+                wrap: texture.wrap.as_u32(),
+            });
+            texels.extend(texture.texels().iter().map(|&texel| u32::from_le_bytes(texel)));
+        }
+
+        let mut words: Vec<u32> = bytemuck::cast_slice(&headers).to_vec();
+        words.extend(texels);
+        words
+    }
+
+    /// Spheres and planes in the order BVH leaves refer to them; triangles reach the GPU through `export_meshes`.
+    pub fn export_gpu_data(&self) -> (Vec<GpuSphere>, Vec<GpuPlane>) {
+        profiler_start("export gpu data");
+        let spheres = self.spheres().map(sphere_to_gpu_sphere).collect();
+        let planes = self.planes().map(plane_to_gpu_plane).collect();
         profiler_stop("export gpu data");
-        (spheres, triangles, planes)
+        (spheres, planes)
+    }
+
+    /// Triangles added directly to the scene rather than through a mesh.
+    pub fn loose_triangles(&self) -> Vec<Triangle> {
+        self.objects.iter().filter_map(|obj| obj.as_any().downcast_ref::<Triangle>()).copied().collect()
+    }
+
+    pub fn spheres(&self) -> impl Iterator<Item = &Sphere> {
+        self.objects.iter().filter_map(|obj| obj.as_any().downcast_ref::<Sphere>())
+    }
+
+    pub fn planes(&self) -> impl Iterator<Item = &Plane> {
+        self.objects.iter().filter_map(|obj| obj.as_any().downcast_ref::<Plane>())
+    }
+
+    /// Triangles, spheres and planes outside any mesh, they share one BVH drawn by an identity instance.
+    pub fn has_loose_primitives(&self) -> bool {
+        self.objects.iter().any(|obj| obj.as_any().is::<Triangle>() || obj.as_any().is::<Sphere>() || obj.as_any().is::<Plane>())
+    }
+
+    /// Every distinct mesh once, in object space, instances sharing a mesh share its entry.
+    pub fn export_meshes(&self) -> Vec<&Mesh> {
+        self.mesh_placements().0
+    }
+
+    /// Where every BVH is drawn, cheap enough to redo whenever an instance moves. When the scene has loose
+    /// primitives their BVH is mesh 0 and the meshes from `export_meshes` follow.
+    pub fn export_instances(&self) -> Vec<SceneInstance> {
+        let mut instances = self.mesh_placements().1;
+        if self.has_loose_primitives() {
+            instances.insert(0, SceneInstance { mesh: 0, transform: Mat4::IDENTITY, end_transform: None, material: None });
+        }
+        instances
+    }
+
+    /// Distinct meshes and their placements, mesh indices already account for the loose primitives.
+    fn mesh_placements(&self) -> (Vec<&Mesh>, Vec<SceneInstance>) {
+        let first = if self.has_loose_primitives() { 1 } else { 0 };
+        let mut meshes: Vec<&Mesh> = Vec::new();
+        let mut instances = Vec::new();
+
+        for obj in self.get_objects() {
+            let (mesh, transform, end_transform, material) = if let Some(instance) = obj.as_any().downcast_ref::<Instance>() {
+                (instance.mesh().as_ref(), instance.transform(), instance.end_transform(), instance.material_override().copied())
+            } else if let Some(mesh) = obj.as_any().downcast_ref::<Mesh>() {
+                (mesh, mesh.transform(), mesh.end_transform(), None)
+            } else {
+                continue;
+            };
+
+            let index = match meshes.iter().position(|&known| std::ptr::eq(known, mesh)) {
+                Some(index) => index,
+                None => {
+                    meshes.push(mesh);
+                    meshes.len() - 1
+                }
+            };
+            instances.push(SceneInstance { mesh: first + index, transform, end_transform, material });
+        }
+        (meshes, instances)
     }
 
     pub fn get_objects(&self) -> &[Box<dyn Hittable>] {
         &self.objects
     }
+
+    /// The closest hit over every object, tested one by one on the CPU.
+    pub fn hit(&self, ray: &Ray) -> Option<HitInfo> {
+        let mut closest_hit: Option<HitInfo> = None;
+        for hittable in &self.objects {
+            let info = hittable.hit(ray);
+            if info.has_hit && closest_hit.as_ref().is_none_or(|closest| info.t < closest.t) {
+                closest_hit = Some(info);
+            }
+        }
+        closest_hit
+    }
+}
+
+/// The GPU element of a loose triangle, sphere or plane, what tells whether it changed.
+fn loose_gpu_bytes(object: &dyn std::any::Any) -> Option<Vec<u8>> {
+    if let Some(sphere) = object.downcast_ref::<Sphere>() {
+        Some(bytemuck::bytes_of(&sphere_to_gpu_sphere(sphere)).to_vec())
+    } else if let Some(plane) = object.downcast_ref::<Plane>() {
+        Some(bytemuck::bytes_of(&plane_to_gpu_plane(plane)).to_vec())
+    } else {
+        object.downcast_ref::<Triangle>().map(|triangle| bytemuck::bytes_of(&triangle_to_gpu_triangle(triangle)).to_vec())
+    }
+}
+
+pub fn sphere_to_gpu_sphere(sphere: &Sphere) -> GpuSphere {
+    let center = sphere.center();
+    let mat = sphere.material();
+    let albedo = mat.albedo();
+
+    GpuSphere {
+        center: [center.x, center.y, center.z],
+        radius: sphere.radius(),
+        albedo: [albedo.r, albedo.g, albedo.b],
+        emission: mat.emission(),
+        metallic: mat.metallic(),
+        roughness: mat.roughness(),
+        transmission: mat.transmission(),
+        ior: mat.ior(),
+        textures: texture_ids(mat),
+        emission_color: color_array(mat.emission_color()),
+        _pad0: 0.0,
+        end_center: sphere.end_center().to_array(),
+        _pad1: 0.0,
+    }
+}
+
+pub fn plane_to_gpu_plane(plane: &Plane) -> GpuPlane {
+    let center = plane.center();
+    let normal = plane.normal();
+    let mat = plane.material();
+    let albedo = mat.albedo();
+
+    GpuPlane {
+        center: [center.x, center.y, center.z, 0.0],
+        normal: [normal.x, normal.y, normal.z, 0.0],
+        width: plane.width(),
+        length: plane.length(),
+        transmission: mat.transmission(),
+        ior: mat.ior(),
+        albedo: [albedo.r, albedo.g, albedo.b, 0.0],
+        emission: mat.emission(),
+        metallic: mat.metallic(),
+        roughness: mat.roughness(),
+        _pad3: 0.0,
+        textures: texture_ids(mat),
+        emission_color: color_array(mat.emission_color()),
+        _pad4: 0.0,
+    }
 }
 
 pub fn triangle_to_gpu_triangle(tri: &Triangle) -> GpuTriangle {
@@ -89,6 +427,9 @@ pub fn triangle_to_gpu_triangle(tri: &Triangle) -> GpuTriangle {
     let v2 = tri.v2();
     let mat = tri.material();
     let albedo = mat.albedo();
+    let [uv0, uv1, uv2] = tri.uvs();
+    let geometric = tri.geometric_normal();
+    let [n0, n1, n2] = tri.normals().unwrap_or([geometric; 3]);
 
     GpuTriangle {
         v0: [v0.x, v0.y, v0.z],
@@ -101,87 +442,49 @@ pub fn triangle_to_gpu_triangle(tri: &Triangle) -> GpuTriangle {
         emission: mat.emission(),
         metallic: mat.metallic(),
         roughness: mat.roughness(),
-        _padding: [0.0, 0.0],
+        transmission: mat.transmission(),
+        ior: mat.ior(),
+        uv0: uv0.to_array(),
+        uv1: uv1.to_array(),
+        uv2: uv2.to_array(),
+        _pad3: [0.0; 2],
+        textures: texture_ids(mat),
+        n0: n0.to_array(),
+        _pad4: 0.0,
+        n1: n1.to_array(),
+        _pad5: 0.0,
+        n2: n2.to_array(),
+        _pad6: 0.0,
+        emission_color: color_array(mat.emission_color()),
+        _pad7: 0.0,
     }
 }
 
-pub fn create_scene() -> Scene {
-    profiler_start("create scene");
-    profiler_start("load other models");
-    let mut scene = Scene::new();
-
-    // Floor
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(0.0, -2.5, 0.0), Vec3::new(0.0, 1.0, 0.0),
-        5.0, 5.0,
-        Material { albedo: Color::new(0.8, 0.8, 0.8), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    )));
-
-    // Ceiling
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(0.0, 2.5, 0.0), Vec3::new(0.0, -1.0, 0.0),
-        5.0, 5.0,
-        Material { albedo: Color::new(0.8, 0.8, 0.8), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    )));
-
-    // Back wall
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(0.0, 0.0, -2.5), Vec3::new(0.0, 0.0, 1.0),
-        5.0, 5.0,
-        Material { albedo: Color::new(0.8, 0.8, 0.8), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    )));
-
-    // Left wall
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(-2.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0),
-        5.0, 5.0,
-        Material { albedo: Color::new(0.9, 0.2, 0.2), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    )));
-
-    // Right wall
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(2.5, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0),
-        5.0, 5.0,
-        Material { albedo: Color::new(0.2, 0.9, 0.2), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    )));
-
-    // Ceiling light
-    scene.add_object(Box::new(Plane::new(
-        Vec3::new(0.0, 2.499, 0.0), Vec3::new(0.0, -1.0, 0.0),
-        3.0, 3.0,
-        Material { albedo: Color::new(1.0, 1.0, 1.0), roughness: 0.0, metallic: 0.0, emission: 1.0 },
-    )));
-
-    // Spheres
-    // scene.add_object(Box::new(Sphere::new(
-    //     Vec3::new(-1.5, -1.5, 0.0), 1.0,
-    //     Material { albedo: Color::new(0.7, 0.7, 0.7), roughness: 1.0, metallic: 0.0, emission: 0.0 },
-    // )));
-    //
-    // scene.add_object(Box::new(Sphere::new(
-    //     Vec3::new(0.0, -1.5, -1.5), 1.0,
-    //     Material { albedo: Color::new(0.8, 0.8, 0.9), roughness: 0.0, metallic: 1.0, emission: 0.0 },
-    // )));
-    //
-    // scene.add_object(Box::new(Sphere::new(
-    //     Vec3::new(1.5, -1.5, 0.0), 1.0,
-    //     Material { albedo: Color::new(0.9, 0.6, 0.2), roughness: 0.6, metallic: 0.0, emission: 0.0 },
-    // )));
-
-    profiler_stop("load other models");
-    profiler_start("load mesh");
-
-    let mut mesh = import_obj("src/models/standford_dragon.obj");
-    mesh.set_material(Material::new(Color::new(0.9, 0.9, 0.9), 1.0, 0.0, 0.0));
-    mesh.position = Vec3::new(0.0, -2.49, 0.0);
-    mesh.scale = 2.0;
-    mesh.rotation = Vec3::new(0.0, 20.0f32.to_radians(), 0.0);
-    profiler_start("construct_bvh");
-    mesh.add_bvh(construct_bvh(&mesh));
-    profiler_stop("construct_bvh");
-    scene.add_object(Box::new(mesh));
-
-    profiler_stop("load mesh");
-    profiler_stop("create scene");
-    scene
-}
\ No newline at end of file
+pub fn material_to_gpu_material(mat: &Material) -> GpuMaterial {
+    let albedo = mat.albedo();
+    GpuMaterial {
+        albedo: [albedo.r, albedo.g, albedo.b],
+        emission: mat.emission(),
+        emission_color: color_array(mat.emission_color()),
+        metallic: mat.metallic(),
+        roughness: mat.roughness(),
+        transmission: mat.transmission(),
+        ior: mat.ior(),
+        _pad: 0.0,
+        textures: texture_ids(mat),
+    }
+}
+
+fn color_array(color: &Color) -> [f32; 3] {
+    [color.r, color.g, color.b]
+}
+
+pub fn texture_ids(mat: &Material) -> GpuTextureIds {
+    let id = |texture: Option<u32>| texture.map_or(-1, |index| index as i32);
+    GpuTextureIds {
+        albedo: id(mat.albedo_texture),
+        roughness: id(mat.roughness_texture),
+        metallic: id(mat.metallic_texture),
+        _pad: 0,
+    }
+}
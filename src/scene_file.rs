@@ -0,0 +1,1140 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use glam::{EulerRot, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::bvh::{construct_bvh, DEFAULT_LEAF_SIZE, MAX_LEAF_SIZE};
+use crate::camera::{Camera, Projection};
+use crate::color::Color;
+use crate::environment::{Background, Environment, EnvironmentMap};
+use crate::importer::import_obj;
+use crate::instance::Instance;
+use crate::light::{rect_shape, Light, LightShape};
+use crate::material::Material;
+use crate::model::{object_to_world, Mesh};
+use crate::objects::{Plane, Sphere, Triangle};
+use crate::profiler::{profiler_start, profiler_stop};
+use crate::scene::Scene;
+use crate::sky::{day_of_year, sun_angles, sun_position, Sky};
+use crate::texture::{Texture, WrapMode};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: String, source: std::io::Error },
+    Parse { path: String, source: toml::de::Error },
+    Serialize(toml::ser::Error),
+    Invalid { key: String, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path, source),
+            SceneError::Parse { path, source } => write!(f, "{}: {}", path, source),
+            SceneError::Serialize(source) => write!(f, "failed to serialize scene: {}", source),
+            SceneError::Invalid { key, message } => write!(f, "invalid value for `{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    textures: BTreeMap<String, TextureDescription>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    objects: Vec<ObjectDescription>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lights: Vec<LightDescription>,
+    /// Black when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<EnvironmentDescription>,
+}
+
+impl SceneFile {
+    /// The texture, mesh and environment map paths the file refers to.
+    fn paths_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        let textures = self.textures.values_mut().map(|texture| &mut texture.path);
+        let meshes = self.objects.iter_mut().filter_map(|object| match object {
+            ObjectDescription::Mesh { path, .. } => Some(path),
+            _ => None,
+        });
+        let map = self.environment.iter_mut().filter_map(|environment| match &mut environment.background {
+            BackgroundDescription::Map { path } => Some(path),
+            _ => None,
+        });
+        textures.chain(meshes).chain(map)
+    }
+}
+
+/// `target` takes precedence over `direction` when both are given. `fov` is vertical and `roll` turns around the view
+/// direction, both in degrees. `ortho_height` is the world space height of the orthographic projection. A non-zero
+/// `aperture` radius turns on depth of field, focused at `focus_distance` or on the target when that is left out.
+/// Motion runs from time 0 to 1, the shutter is open from `shutter_open` to `shutter_close`. Giving `end_position` or
+/// `end_target` moves the camera over that time.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct CameraDescription {
+    position: [f32; 3],
+    direction: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<[f32; 3]>,
+    up: [f32; 3],
+    projection: Projection,
+    fov: f32,
+    ortho_height: f32,
+    roll: f32,
+    aperture: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    focus_distance: Option<f32>,
+    blades: u32,
+    blade_rotation: f32,
+    shutter_open: f32,
+    shutter_close: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_position: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_target: Option<[f32; 3]>,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self { position: [0.0, 0.0, 5.0], direction: [0.0, 0.0, -1.0], target: None, up: [0.0, 1.0, 0.0],
+            projection: Projection::Perspective, fov: 90.0, ortho_height: 2.0, roll: 0.0,
+            aperture: 0.0, focus_distance: None, blades: 0, blade_rotation: 0.0, shutter_open: 0.0, shutter_close: 0.0,
+            end_position: None, end_target: None }
+    }
+}
+
+/// A PNG image on disk, referenced by name from the `*_texture` fields of materials.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDescription {
+    path: String,
+    #[serde(default)]
+    wrap: WrapMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct MaterialDescription {
+    albedo: [f32; 3],
+    roughness: f32,
+    metallic: f32,
+    /// Radiance scale of `emission_color`, may go well above 1.
+    emission: f32,
+    emission_color: [f32; 3],
+    /// Black body temperature in Kelvin, replaces `emission_color`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emission_temperature: Option<f32>,
+    /// Total emitted power in watts, replaces `emission` with the radiance a Lambertian emitter of the object's
+    /// surface area needs to give off that much.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emission_power: Option<f32>,
+    transmission: f32,
+    ior: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    albedo_texture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roughness_texture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metallic_texture: Option<String>,
+}
+
+impl Default for MaterialDescription {
+    fn default() -> Self {
+        Self::from_material(&Material::default(), &[])
+    }
+}
+
+impl MaterialDescription {
+    fn from_material(material: &Material, textures: &[Texture]) -> Self {
+        let albedo = material.albedo();
+        let emission_color = material.emission_color();
+        let name = |index: Option<u32>| index.and_then(|i| textures.get(i as usize)).map(|texture| texture.name.clone());
+        Self {
+            albedo: [albedo.r, albedo.g, albedo.b],
+            roughness: material.roughness(),
+            metallic: material.metallic(),
+            emission: material.emission(),
+            emission_color: [emission_color.r, emission_color.g, emission_color.b],
+            emission_temperature: None,
+            emission_power: None,
+            transmission: material.transmission(),
+            ior: material.ior(),
+            albedo_texture: name(material.albedo_texture),
+            roughness_texture: name(material.roughness_texture),
+            metallic_texture: name(material.metallic_texture),
+        }
+    }
+
+    /// `area` is the surface area of the object the material ends up on, used to turn `emission_power` into radiance.
+    fn to_material(&self, textures: &BTreeMap<String, u32>, area: f32, key: &str) -> Result<Material, SceneError> {
+        let lookup = |name: &Option<String>, field: &str| match name {
+            None => Ok(None),
+            Some(name) => textures.get(name).copied().map(Some)
+                .ok_or_else(|| invalid(&format!("{}.{}", key, field), &format!("no texture named '{}' in [textures]", name))),
+        };
+
+        let mut material = Material::new(Color::new(self.albedo[0], self.albedo[1], self.albedo[2]), self.roughness, self.metallic, self.emission);
+        material.emission_color = Color::new(self.emission_color[0], self.emission_color[1], self.emission_color[2]);
+        material.transmission = self.transmission;
+        material.ior = self.ior;
+
+        if let Some(kelvin) = self.emission_temperature {
+            if !(1000.0..=40000.0).contains(&kelvin) {
+                return Err(invalid(&format!("{}.emission_temperature", key), "must be between 1000 and 40000 Kelvin"));
+            }
+            material.emission_color = Color::from_temperature(kelvin);
+        }
+        if let Some(watts) = self.emission_power {
+            if watts < 0.0 {
+                return Err(invalid(&format!("{}.emission_power", key), "must not be negative"));
+            }
+            if area <= 0.0 {
+                return Err(invalid(&format!("{}.emission_power", key), "the object has no surface area to emit from"));
+            }
+            // radiant exitance of a Lambertian emitter is pi * radiance
+            material.emission = watts / (std::f32::consts::PI * area);
+        }
+
+        material.albedo_texture = lookup(&self.albedo_texture, "albedo_texture")?;
+        material.roughness_texture = lookup(&self.roughness_texture, "roughness_texture")?;
+        material.metallic_texture = lookup(&self.metallic_texture, "metallic_texture")?;
+        Ok(material)
+    }
+}
+
+/// Either the name of an entry in `[materials]` or an inline material table.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum MaterialReference {
+    Named(String),
+    Inline(MaterialDescription),
+}
+
+impl Default for MaterialReference {
+    fn default() -> Self {
+        MaterialReference::Inline(MaterialDescription::default())
+    }
+}
+
+/// Spheres move with `end_center` and meshes with `end_position`, `end_rotation` and `end_scale`. Planes and loose
+/// triangles have no end placement and never blur, put a triangle in an OBJ mesh to move it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ObjectDescription {
+    Plane {
+        center: [f32; 3],
+        normal: [f32; 3],
+        width: f32,
+        length: f32,
+        #[serde(default)]
+        material: MaterialReference,
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        /// Where the centre has moved to by the end of the motion.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_center: Option<[f32; 3]>,
+        #[serde(default)]
+        material: MaterialReference,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        #[serde(default)]
+        material: MaterialReference,
+    },
+    /// Entries with the same path, crease angle and leaf size become instances of one shared mesh.
+    Mesh {
+        path: String,
+        #[serde(default)]
+        position: [f32; 3],
+        /// Euler angles in degrees, applied X then Y then Z.
+        #[serde(default)]
+        rotation: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: f32,
+        /// Placement at the end of the motion, each one falls back to its start value.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_position: Option<[f32; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_rotation: Option<[f32; 3]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_scale: Option<f32>,
+        /// Degrees; only used to generate smooth normals when the OBJ has no `vn` lines.
+        #[serde(default = "default_crease_angle")]
+        crease_angle: f32,
+        /// Largest number of triangles the BVH builder keeps in one leaf, at most `MAX_LEAF_SIZE`.
+        #[serde(default = "default_leaf_size")]
+        bvh_leaf_size: u32,
+        #[serde(default)]
+        material: MaterialReference,
+    },
+}
+
+/// `intensity` is radiant intensity for point and spot lights, irradiance for directional lights and radiance for rect
+/// and disk lights. Lights with `visible = false` still light the scene but the camera looks straight through them.
+#[derive(Debug, Serialize, Deserialize)]
+struct LightDescription {
+    #[serde(flatten)]
+    shape: LightShapeDescription,
+    #[serde(default = "default_light_color")]
+    color: [f32; 3],
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default = "default_visible")]
+    visible: bool,
+}
+
+/// Angles are in degrees. A zero `radius` makes point and spot lights true points, a zero `angular_diameter` gives the
+/// directional light perfectly sharp shadows. Rect and disk lights only shine towards `normal`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LightShapeDescription {
+    Point {
+        position: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+        /// Angle from the axis the light starts to fade at.
+        inner_angle: f32,
+        /// Angle from the axis the light is gone at.
+        outer_angle: f32,
+    },
+    Directional {
+        /// The way the light travels, from the sun towards the scene.
+        direction: [f32; 3],
+        #[serde(default = "default_angular_diameter")]
+        angular_diameter: f32,
+    },
+    Rect {
+        position: [f32; 3],
+        normal: [f32; 3],
+        width: f32,
+        height: f32,
+    },
+    Disk {
+        position: [f32; 3],
+        normal: [f32; 3],
+        radius: f32,
+    },
+}
+
+/// `rotation` turns the map around the vertical axis, in degrees. Other backgrounds can't be turned, a sky's sun is
+/// placed with its own azimuth.
+#[derive(Debug, Serialize, Deserialize)]
+struct EnvironmentDescription {
+    #[serde(flatten)]
+    background: BackgroundDescription,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default)]
+    rotation: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum BackgroundDescription {
+    Constant {
+        color: [f32; 3],
+    },
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    /// A Radiance `.hdr` lat-long image.
+    Map {
+        path: String,
+    },
+    /// Daylight with a matching sun. The sun sits at `elevation` above the horizon and `azimuth` clockwise from north
+    /// (-Z) towards east (+X), in degrees, or where it is seen from `latitude` and `longitude` at `time`, a TOML date
+    /// and time with its UTC offset like 2024-06-21T14:30:00-07:00.
+    Sky {
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        elevation: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        azimuth: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        latitude: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        longitude: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        time: Option<toml::value::Datetime>,
+        /// Scales the sun light, zero leaves only the sky.
+        #[serde(default = "default_intensity")]
+        sun_intensity: f32,
+        /// Angular diameter of the sun disk in degrees.
+        #[serde(default = "default_angular_diameter")]
+        sun_size: f32,
+    },
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+/// The sun's, seen from the earth.
+fn default_angular_diameter() -> f32 {
+    0.53
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_crease_angle() -> f32 {
+    60.0
+}
+
+fn default_leaf_size() -> u32 {
+    DEFAULT_LEAF_SIZE
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, SceneError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|source| SceneError::Io { path: path.to_string(), source })?;
+    parse_scene(&contents, path)
+}
+
+/// Parses a TOML scene description read from `origin`, which labels errors. Relative paths in it are relative to the
+/// directory `origin` is in.
+pub fn parse_scene(contents: &str, origin: &str) -> Result<Scene, SceneError> {
+    let mut file: SceneFile = toml::from_str(contents)
+        .map_err(|source| SceneError::Parse { path: origin.to_string(), source })?;
+    let directory = Path::new(origin).parent().unwrap_or(Path::new(""));
+    for path in file.paths_mut() {
+        *path = normalize(&directory.join(&*path)).to_string_lossy().into_owned();
+    }
+
+    profiler_start("build scene");
+    let scene = build_scene(&file);
+    profiler_stop("build scene");
+    scene
+}
+
+fn build_scene(file: &SceneFile) -> Result<Scene, SceneError> {
+    let mut scene = Scene::new();
+    scene.set_camera(build_camera(&file.camera)?);
+
+    let mut textures = BTreeMap::new();
+    for (name, description) in &file.textures {
+        profiler_start("load texture");
+        let texture = Texture::load_png(name, &description.path, description.wrap);
+        profiler_stop("load texture");
+
+        let texture = texture
+            .map_err(|err| invalid(&format!("textures.{}.path", name), &format!("failed to load '{}': {}", description.path, err)))?;
+        textures.insert(name.clone(), scene.add_texture(texture));
+    }
+
+    // entries loading the same file the same way share one mesh and BVH
+    let mut meshes: HashMap<(&str, u32, u32), Arc<Mesh>> = HashMap::new();
+
+    for (i, object) in file.objects.iter().enumerate() {
+        let key = format!("objects[{}]", i);
+
+        match object {
+            ObjectDescription::Plane { center, normal, width, length, material } => {
+                if to_vec3(*normal).length_squared() == 0.0 {
+                    return Err(invalid(&format!("{}.normal", key), "must not be the zero vector"));
+                }
+                let material = resolve_material(material, &file.materials, &textures, width * length, &key)?;
+                scene.add_object(Box::new(Plane::new(to_vec3(*center), to_vec3(*normal).normalize(), *width, *length, material)));
+            }
+            ObjectDescription::Sphere { center, radius, end_center, material } => {
+                if *radius <= 0.0 {
+                    return Err(invalid(&format!("{}.radius", key), "must be positive"));
+                }
+                let area = 4.0 * std::f32::consts::PI * radius * radius;
+                let material = resolve_material(material, &file.materials, &textures, area, &key)?;
+                let sphere = Sphere::new(to_vec3(*center), *radius, material);
+                let sphere = match end_center {
+                    Some(end_center) => sphere.with_end_center(to_vec3(*end_center)),
+                    None => sphere,
+                };
+                scene.add_object(Box::new(sphere));
+            }
+            ObjectDescription::Triangle { vertices, material } => {
+                let triangle = Triangle::new(to_vec3(vertices[0]), to_vec3(vertices[1]), to_vec3(vertices[2]), Material::default());
+                let material = resolve_material(material, &file.materials, &textures, triangle.area(), &key)?;
+                scene.add_object(Box::new(triangle.with_material(material)));
+            }
+            ObjectDescription::Mesh { path, position, rotation, scale, end_position, end_rotation, end_scale, crease_angle,
+                bvh_leaf_size, material } => {
+                if !(1..=MAX_LEAF_SIZE).contains(bvh_leaf_size) {
+                    return Err(invalid(&format!("{}.bvh_leaf_size", key), &format!("must be between 1 and {}", MAX_LEAF_SIZE)));
+                }
+                if end_scale.is_some_and(|scale| scale <= 0.0) {
+                    return Err(invalid(&format!("{}.end_scale", key), "must be positive"));
+                }
+
+                let cache_key = (path.as_str(), crease_angle.to_bits(), *bvh_leaf_size);
+                let mesh = match meshes.get(&cache_key) {
+                    Some(mesh) => mesh.clone(),
+                    None => {
+                        let mesh = Arc::new(load_mesh(path, *crease_angle, *bvh_leaf_size, &key)?);
+                        meshes.insert(cache_key, mesh.clone());
+                        mesh
+                    }
+                };
+
+                let to_radians = |degrees: [f32; 3]| Vec3::from_array(degrees.map(f32::to_radians));
+                let mut instance = Instance::new(mesh, object_to_world(to_vec3(*position), to_radians(*rotation), *scale));
+                if end_position.is_some() || end_rotation.is_some() || end_scale.is_some() {
+                    instance = instance.with_end_transform(object_to_world(to_vec3(end_position.unwrap_or(*position)),
+                        to_radians(end_rotation.unwrap_or(*rotation)), end_scale.unwrap_or(*scale)));
+                }
+
+                let area = instance.triangles().iter().map(Triangle::area).sum();
+                let material = resolve_material(material, &file.materials, &textures, area, &key)?;
+                scene.add_object(Box::new(instance.with_material(material)));
+            }
+        }
+    }
+
+    if let Some(description) = &file.environment {
+        scene.set_environment(build_environment(description)?);
+    }
+
+    for (i, description) in file.lights.iter().enumerate() {
+        let light = build_light(description, &format!("lights[{}]", i))?;
+        scene.add_light(light);
+    }
+
+    Ok(scene)
+}
+
+fn build_environment(description: &EnvironmentDescription) -> Result<Environment, SceneError> {
+    let color = |[r, g, b]: [f32; 3]| Color::new(r, g, b);
+    let background = match &description.background {
+        BackgroundDescription::Constant { color: constant } => Background::Constant(color(*constant)),
+        BackgroundDescription::Gradient { zenith, horizon, ground } => {
+            Background::Gradient { zenith: color(*zenith), horizon: color(*horizon), ground: color(*ground) }
+        }
+        BackgroundDescription::Sky { turbidity, elevation, azimuth, latitude, longitude, time, sun_intensity, sun_size } => {
+            if !(1.7..=10.0).contains(turbidity) {
+                return Err(invalid("environment.turbidity", "must be between 1.7 and 10"));
+            }
+            if *sun_intensity < 0.0 {
+                return Err(invalid("environment.sun_intensity", "must not be negative"));
+            }
+            if !(0.0..180.0).contains(sun_size) {
+                return Err(invalid("environment.sun_size", "must be at least 0 and below 180 degrees"));
+            }
+
+            let (elevation, azimuth) = match (elevation, latitude, longitude, time) {
+                (Some(elevation), None, None, None) => {
+                    if !(-90.0..=90.0).contains(elevation) {
+                        return Err(invalid("environment.elevation", "must be between -90 and 90 degrees"));
+                    }
+                    (elevation.to_radians(), azimuth.unwrap_or(180.0).to_radians())
+                }
+                (None, Some(latitude), Some(longitude), Some(time)) if azimuth.is_none() => {
+                    sun_at(*latitude, *longitude, time)?
+                }
+                _ => return Err(invalid("environment", "give the sun's elevation and azimuth, or latitude, longitude and time")),
+            };
+
+            let mut sky = Sky::from_angles(elevation, azimuth, *turbidity);
+            sky.sun_intensity = *sun_intensity;
+            sky.sun_size = sun_size.to_radians();
+            Background::Sky(sky)
+        }
+        BackgroundDescription::Map { path } => {
+            profiler_start("load environment");
+            let map = EnvironmentMap::load_hdr(path);
+            profiler_stop("load environment");
+            Background::Map(map.map_err(|err| invalid("environment.path", &format!("failed to load '{}': {}", path, err)))?)
+        }
+    };
+
+    if description.intensity < 0.0 {
+        return Err(invalid("environment.intensity", "must not be negative"));
+    }
+    if description.rotation != 0.0 && !matches!(background, Background::Map(_)) {
+        let message = match background {
+            Background::Sky(_) => "only applies to maps, turn the sun with azimuth instead",
+            _ => "only applies to maps",
+        };
+        return Err(invalid("environment.rotation", message));
+    }
+    Ok(Environment::new(background).with_intensity(description.intensity).with_rotation(description.rotation.to_radians()))
+}
+
+/// Elevation and azimuth of the sun in radians, seen from `latitude` and `longitude` at a local `time`.
+fn sun_at(latitude: f32, longitude: f32, time: &toml::value::Datetime) -> Result<(f32, f32), SceneError> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(invalid("environment.latitude", "must be between -90 and 90 degrees"));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(invalid("environment.longitude", "must be between -180 and 180 degrees"));
+    }
+    let (Some(date), Some(clock), Some(offset)) = (time.date, time.time, time.offset) else {
+        return Err(invalid("environment.time", "needs a date, a time of day and a UTC offset"));
+    };
+
+    let offset_minutes = match offset {
+        toml::value::Offset::Z => 0,
+        toml::value::Offset::Custom { minutes } => minutes as i32,
+    };
+    let local_hours = clock.hour as f32 + clock.minute as f32 / 60.0 + clock.second.unwrap_or(0) as f32 / 3600.0;
+    let day = day_of_year(date.year as u32, date.month as u32, date.day as u32);
+    Ok(sun_position(latitude, longitude, day, local_hours - offset_minutes as f32 / 60.0))
+}
+
+fn build_light(description: &LightDescription, key: &str) -> Result<Light, SceneError> {
+    let unit = |v: [f32; 3], field: &str| {
+        let v = to_vec3(v);
+        if v.length_squared() == 0.0 {
+            return Err(invalid(&format!("{}.{}", key, field), "must not be the zero vector"));
+        }
+        Ok(v.normalize())
+    };
+    let non_negative = |value: f32, field: &str| {
+        if value < 0.0 {
+            return Err(invalid(&format!("{}.{}", key, field), "must not be negative"));
+        }
+        Ok(value)
+    };
+
+    let shape = match description.shape {
+        LightShapeDescription::Point { position, radius } => {
+            LightShape::Point { position: to_vec3(position), radius: non_negative(radius, "radius")? }
+        }
+        LightShapeDescription::Spot { position, direction, radius, inner_angle, outer_angle } => {
+            if !(0.0..=180.0).contains(&outer_angle) {
+                return Err(invalid(&format!("{}.outer_angle", key), "must be between 0 and 180 degrees"));
+            }
+            if !(0.0..=outer_angle).contains(&inner_angle) {
+                return Err(invalid(&format!("{}.inner_angle", key), "must be between 0 degrees and outer_angle"));
+            }
+            LightShape::Spot {
+                position: to_vec3(position),
+                radius: non_negative(radius, "radius")?,
+                direction: unit(direction, "direction")?,
+                cos_inner: inner_angle.to_radians().cos(),
+                cos_outer: outer_angle.to_radians().cos(),
+            }
+        }
+        LightShapeDescription::Directional { direction, angular_diameter } => {
+            if !(0.0..180.0).contains(&angular_diameter) {
+                return Err(invalid(&format!("{}.angular_diameter", key), "must be at least 0 and below 180 degrees"));
+            }
+            LightShape::Directional { direction: unit(direction, "direction")?, cos_radius: (angular_diameter * 0.5).to_radians().cos() }
+        }
+        LightShapeDescription::Rect { position, normal, width, height } => {
+            if width <= 0.0 || height <= 0.0 {
+                return Err(invalid(&format!("{}.{}", key, if width <= 0.0 { "width" } else { "height" }), "must be positive"));
+            }
+            rect_shape(to_vec3(position), unit(normal, "normal")?, width, height)
+        }
+        LightShapeDescription::Disk { position, normal, radius } => {
+            if radius <= 0.0 {
+                return Err(invalid(&format!("{}.radius", key), "must be positive"));
+            }
+            LightShape::Disk { position: to_vec3(position), normal: unit(normal, "normal")?, radius }
+        }
+    };
+
+    let [r, g, b] = description.color;
+    let intensity = non_negative(description.intensity, "intensity")?;
+    Ok(Light::new(shape, Color::new(r, g, b), intensity).with_visible(description.visible))
+}
+
+/// Loads an OBJ in object space, with generated normals when it has none and its BVH.
+fn load_mesh(path: &str, crease_angle: f32, bvh_leaf_size: u32, key: &str) -> Result<Mesh, SceneError> {
+    profiler_start("load mesh");
+    let mesh = import_obj(path);
+    profiler_stop("load mesh");
+
+    let mut mesh = mesh
+        .map_err(|err| invalid(&format!("{}.path", key), &format!("failed to load '{}': {}", path, err)))?;
+
+    if !mesh.has_normals() {
+        profiler_start("generate normals");
+        mesh.generate_normals(crease_angle.to_radians());
+        profiler_stop("generate normals");
+    }
+
+    profiler_start("construct_bvh");
+    let bvh = construct_bvh(&mesh, bvh_leaf_size);
+    profiler_stop("construct_bvh");
+
+    println!("{}: BVH with {} nodes, SAH cost {:.2}", path, bvh.node_count(), bvh.sah_cost());
+    mesh.add_bvh(bvh);
+    Ok(mesh)
+}
+
+fn build_camera(description: &CameraDescription) -> Result<Camera, SceneError> {
+    let position = to_vec3(description.position);
+    let target = match description.target {
+        Some(target) if to_vec3(target) == position => return Err(invalid("camera.target", "must differ from the position")),
+        Some(target) => to_vec3(target),
+        None if to_vec3(description.direction).length_squared() == 0.0 => {
+            return Err(invalid("camera.direction", "must not be the zero vector"));
+        }
+        None => position + to_vec3(description.direction),
+    };
+    if to_vec3(description.up).length_squared() == 0.0 {
+        return Err(invalid("camera.up", "must not be the zero vector"));
+    }
+    // a fisheye can see all the way around, the perspective projection breaks down at 180
+    let max_fov = if description.projection == Projection::Fisheye { 360.0 } else { 180.0 };
+    if !(description.fov > 0.0 && description.fov < max_fov) {
+        return Err(invalid("camera.fov", &format!("must be between 0 and {} degrees", max_fov)));
+    }
+    if description.ortho_height <= 0.0 {
+        return Err(invalid("camera.ortho_height", "must be positive"));
+    }
+    if description.aperture < 0.0 {
+        return Err(invalid("camera.aperture", "must not be negative"));
+    }
+    if description.focus_distance.is_some_and(|distance| distance <= 0.0) {
+        return Err(invalid("camera.focus_distance", "must be positive"));
+    }
+    if !(0.0..=1.0).contains(&description.shutter_open) {
+        return Err(invalid("camera.shutter_open", "must be between 0 and 1"));
+    }
+    if !(description.shutter_open..=1.0).contains(&description.shutter_close) {
+        return Err(invalid("camera.shutter_close", "must be between shutter_open and 1"));
+    }
+
+    let mut camera = Camera::look_at(position, target, to_vec3(description.up), description.fov)
+        .with_roll(description.roll.to_radians())
+        .with_projection(description.projection, description.ortho_height)
+        .with_aperture_blades(description.blades, description.blade_rotation.to_radians())
+        .with_shutter(description.shutter_open, description.shutter_close);
+    if description.end_position.is_some() || description.end_target.is_some() {
+        // moving without an end target keeps the view direction
+        let end_position = description.end_position.map_or(position, to_vec3);
+        let end_target = description.end_target.map_or(end_position + (target - position), to_vec3);
+        if end_target == end_position {
+            return Err(invalid("camera.end_target", "must differ from the end position"));
+        }
+        camera = camera.with_end_look_at(end_position, end_target);
+    }
+    let focus_distance = description.focus_distance.unwrap_or_else(|| camera.focus_distance());
+    Ok(camera.with_lens(description.aperture, focus_distance))
+}
+
+#[allow(dead_code)]
+pub fn save_scene(scene: &Scene, path: &str) -> Result<(), SceneError> {
+    let contents = scene_to_string(scene, path)?;
+    std::fs::write(path, contents).map_err(|source| SceneError::Io { path: path.to_string(), source })
+}
+
+/// Writes `scene` as TOML to be saved at `origin`, with paths relative to the directory `origin` is in.
+#[allow(dead_code)]
+pub fn scene_to_string(scene: &Scene, origin: &str) -> Result<String, SceneError> {
+    let camera = scene.camera();
+    let mut file = SceneFile {
+        camera: CameraDescription {
+            position: camera.position().to_array(),
+            direction: camera.direction().to_array(),
+            target: None,
+            up: camera.up().to_array(),
+            projection: camera.projection(),
+            fov: camera.fov(),
+            ortho_height: camera.ortho_height(),
+            roll: camera.roll().to_degrees(),
+            aperture: camera.aperture(),
+            focus_distance: Some(camera.focus_distance()),
+            blades: camera.blades(),
+            blade_rotation: camera.blade_rotation().to_degrees(),
+            shutter_open: camera.shutter().0,
+            shutter_close: camera.shutter().1,
+            end_position: camera.end_position().map(|position| position.to_array()),
+            end_target: camera.end_position().zip(camera.end_direction()).map(|(position, direction)| (position + direction).to_array()),
+        },
+        textures: BTreeMap::new(),
+        materials: BTreeMap::new(),
+        objects: Vec::new(),
+        lights: Vec::new(),
+        environment: None,
+    };
+
+    for texture in scene.textures() {
+        let path = texture.source.clone()
+            .ok_or_else(|| invalid(&format!("textures.{}", texture.name), "texture was not loaded from a file and cannot be saved"))?;
+        file.textures.insert(texture.name.clone(), TextureDescription { path, wrap: texture.wrap });
+    }
+    let textures = scene.textures();
+
+    for (i, object) in scene.get_objects().iter().enumerate() {
+        let any = object.as_any();
+
+        let description = if let Some(plane) = any.downcast_ref::<Plane>() {
+            ObjectDescription::Plane {
+                center: plane.center().to_array(),
+                normal: plane.normal().to_array(),
+                width: plane.width(),
+                length: plane.length(),
+                material: inline_material(plane.material(), textures),
+            }
+        } else if let Some(sphere) = any.downcast_ref::<Sphere>() {
+            ObjectDescription::Sphere {
+                center: sphere.center().to_array(),
+                radius: sphere.radius(),
+                end_center: (sphere.end_center() != sphere.center()).then(|| sphere.end_center().to_array()),
+                material: inline_material(sphere.material(), textures),
+            }
+        } else if let Some(triangle) = any.downcast_ref::<Triangle>() {
+            ObjectDescription::Triangle {
+                vertices: triangle.get_vertices().map(|v| v.to_array()),
+                material: inline_material(triangle.material(), textures),
+            }
+        } else if let Some(mesh) = any.downcast_ref::<Mesh>() {
+            let path = mesh.source.clone()
+                .ok_or_else(|| invalid(&format!("objects[{}]", i), "mesh was not loaded from a file and cannot be saved"))?;
+            ObjectDescription::Mesh {
+                path,
+                position: mesh.position.to_array(),
+                rotation: [mesh.rotation.x.to_degrees(), mesh.rotation.y.to_degrees(), mesh.rotation.z.to_degrees()],
+                scale: mesh.scale,
+                end_position: mesh.motion.map(|(position, _, _)| position.to_array()),
+                end_rotation: mesh.motion.map(|(_, rotation, _)| rotation.to_array().map(f32::to_degrees)),
+                end_scale: mesh.motion.map(|(_, _, scale)| scale),
+                crease_angle: mesh.crease_angle.map_or(default_crease_angle(), f32::to_degrees),
+                bvh_leaf_size: mesh.bvh.as_ref().map_or(DEFAULT_LEAF_SIZE, |bvh| bvh.max_leaf_size),
+                material: inline_material(&mesh.material(), textures),
+            }
+        } else if let Some(instance) = any.downcast_ref::<Instance>() {
+            let mesh = instance.mesh();
+            let path = mesh.source.clone()
+                .ok_or_else(|| invalid(&format!("objects[{}]", i), "mesh was not loaded from a file and cannot be saved"))?;
+            let (position, rotation, scale) = placement(&instance.transform());
+            let end = instance.end_transform().map(|end| placement(&end));
+            ObjectDescription::Mesh {
+                path,
+                position,
+                rotation,
+                scale,
+                end_position: end.map(|(position, _, _)| position),
+                end_rotation: end.map(|(_, rotation, _)| rotation),
+                end_scale: end.map(|(_, _, scale)| scale),
+                crease_angle: mesh.crease_angle.map_or(default_crease_angle(), f32::to_degrees),
+                bvh_leaf_size: mesh.bvh.as_ref().map_or(DEFAULT_LEAF_SIZE, |bvh| bvh.max_leaf_size),
+                material: inline_material(&instance.material(), textures),
+            }
+        } else {
+            return Err(invalid(&format!("objects[{}]", i), "unsupported object type"));
+        };
+
+        file.objects.push(description);
+    }
+
+    let environment = scene.environment();
+    let to_array = |color: &Color| [color.r, color.g, color.b];
+    let background = match &environment.background {
+        Background::Constant(color) if to_array(color) == [0.0; 3] => None,
+        Background::Constant(color) => Some(BackgroundDescription::Constant { color: to_array(color) }),
+        Background::Gradient { zenith, horizon, ground } => Some(BackgroundDescription::Gradient {
+            zenith: to_array(zenith),
+            horizon: to_array(horizon),
+            ground: to_array(ground),
+        }),
+        Background::Map(map) => {
+            let path = map.source.clone()
+                .ok_or_else(|| invalid("environment", "map was not loaded from a file and cannot be saved"))?;
+            Some(BackgroundDescription::Map { path })
+        }
+        Background::Sky(sky) => {
+            let (elevation, azimuth) = sun_angles(sky.sun_direction());
+            Some(BackgroundDescription::Sky {
+                turbidity: sky.turbidity(),
+                elevation: Some(elevation.to_degrees()),
+                azimuth: Some(azimuth.to_degrees()),
+                latitude: None,
+                longitude: None,
+                time: None,
+                sun_intensity: sky.sun_intensity,
+                sun_size: sky.sun_size.to_degrees(),
+            })
+        }
+    };
+    file.environment = background.map(|background| EnvironmentDescription {
+        background,
+        intensity: environment.intensity,
+        rotation: environment.rotation.to_degrees(),
+    });
+
+    for light in scene.lights() {
+        let shape = match light.shape {
+            LightShape::Point { position, radius } => LightShapeDescription::Point { position: position.to_array(), radius },
+            LightShape::Spot { position, radius, direction, cos_inner, cos_outer } => LightShapeDescription::Spot {
+                position: position.to_array(),
+                direction: direction.to_array(),
+                radius,
+                inner_angle: cos_inner.acos().to_degrees(),
+                outer_angle: cos_outer.acos().to_degrees(),
+            },
+            LightShape::Directional { direction, cos_radius } => LightShapeDescription::Directional {
+                direction: direction.to_array(),
+                angular_diameter: 2.0 * cos_radius.acos().to_degrees(),
+            },
+            LightShape::Rect { position, u, v } => LightShapeDescription::Rect {
+                position: position.to_array(),
+                normal: u.cross(v).normalize().to_array(),
+                width: 2.0 * u.length(),
+                height: 2.0 * v.length(),
+            },
+            LightShape::Disk { position, normal, radius } => {
+                LightShapeDescription::Disk { position: position.to_array(), normal: normal.to_array(), radius }
+            }
+        };
+        file.lights.push(LightDescription {
+            shape,
+            color: [light.color.r, light.color.g, light.color.b],
+            intensity: light.intensity,
+            visible: light.visible,
+        });
+    }
+
+    let directory = Path::new(origin).parent().unwrap_or(Path::new(""));
+    for path in file.paths_mut() {
+        *path = relative_to(directory, path);
+    }
+
+    toml::to_string(&file).map_err(SceneError::Serialize)
+}
+
+/// Drops `.` and folds `name/..` without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` as seen from `directory`, both relative to the working directory. Absolute paths, and paths that cannot be
+/// made absolute, are kept as they are.
+fn relative_to(directory: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    let absolute = |path: &Path| std::path::absolute(path).map(|path| normalize(&path));
+    let (Ok(directory), Ok(target)) = (absolute(directory), absolute(Path::new(path))) else {
+        return path.to_string();
+    };
+
+    let common = directory.components().zip(target.components()).take_while(|(a, b)| a == b).count();
+    let mut relative: PathBuf = directory.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(target.components().skip(common));
+    relative.to_string_lossy().into_owned()
+}
+
+/// Position, rotation in degrees and uniform scale of an object to world transform, the inverse of `object_to_world`.
+fn placement(transform: &Mat4) -> ([f32; 3], [f32; 3], f32) {
+    let (scale, rotation, position) = transform.to_scale_rotation_translation();
+    let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+    (position.to_array(), [x.to_degrees(), y.to_degrees(), z.to_degrees()], scale.x)
+}
+
+fn resolve_material(reference: &MaterialReference, materials: &BTreeMap<String, MaterialDescription>, textures: &BTreeMap<String, u32>, area: f32, key: &str) -> Result<Material, SceneError> {
+    match reference {
+        MaterialReference::Inline(description) => description.to_material(textures, area, &format!("{}.material", key)),
+        MaterialReference::Named(name) => materials.get(name)
+            .ok_or_else(|| invalid(&format!("{}.material", key), &format!("no material named '{}' in [materials]", name)))?
+            .to_material(textures, area, &format!("materials.{}", name)),
+    }
+}
+
+fn inline_material(material: &Material, textures: &[Texture]) -> MaterialReference {
+    MaterialReference::Inline(MaterialDescription::from_material(material, textures))
+}
+
+fn invalid(key: &str, message: &str) -> SceneError {
+    SceneError::Invalid { key: key.to_string(), message: message.to_string() }
+}
+
+fn to_vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::from_array(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::Hittable;
+
+    #[test]
+    fn bundled_scenes_round_trip() {
+        let mut paths: Vec<_> = std::fs::read_dir("scenes").unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let path = path.to_str().unwrap();
+            let mut original: SceneFile = toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            let scene = load_scene(path).unwrap_or_else(|err| panic!("{}", err));
+            let saved = scene_to_string(&scene, path).unwrap();
+            let reloaded = parse_scene(&saved, path).unwrap_or_else(|err| panic!("{}", err));
+
+            // the saved file describes the same things, with the paths written the same way
+            let mut described: SceneFile = toml::from_str(&saved).unwrap();
+            let kinds = |file: &SceneFile| file.objects.iter().map(std::mem::discriminant).collect::<Vec<_>>();
+            assert_eq!(kinds(&described), kinds(&original), "{}", path);
+            let files = |file: &mut SceneFile| file.paths_mut().map(|path| path.clone()).collect::<Vec<_>>();
+            assert_eq!(files(&mut described), files(&mut original), "{}", path);
+            let background = |file: &SceneFile| file.environment.as_ref().map(|environment| std::mem::discriminant(&environment.background));
+            assert_eq!(background(&described), background(&original), "{}", path);
+            assert_eq!(described.textures.keys().collect::<Vec<_>>(), original.textures.keys().collect::<Vec<_>>(), "{}", path);
+            assert_eq!(described.lights.len(), original.lights.len(), "{}", path);
+
+            // and loads into the same scene
+            assert_eq!(reloaded.camera().projection(), scene.camera().projection(), "{}", path);
+            assert_all_close(&camera_values(reloaded.camera()), &camera_values(scene.camera()), &format!("{} camera", path));
+            assert_eq!(reloaded.get_objects().len(), scene.get_objects().len(), "{}", path);
+            for (i, (object, original)) in reloaded.get_objects().iter().zip(scene.get_objects()).enumerate() {
+                let key = format!("{} objects[{}]", path, i);
+                assert_eq!(object.as_any().type_id(), original.as_any().type_id(), "{}", key);
+                let (material, original) = (object_material(object.as_ref()), object_material(original.as_ref()));
+                let textures = |material: &Material| (material.albedo_texture, material.roughness_texture, material.metallic_texture);
+                assert_eq!(textures(&material), textures(&original), "{}", key);
+                assert_all_close(&material_values(&material), &material_values(&original), &format!("{}.material", key));
+            }
+            assert_eq!(reloaded.lights().len(), scene.lights().len(), "{}", path);
+            assert_eq!(reloaded.textures().len(), scene.textures().len(), "{}", path);
+
+            // and saves the same again
+            let first: toml::Value = toml::from_str(&saved).unwrap();
+            let second: toml::Value = toml::from_str(&scene_to_string(&reloaded, path).unwrap()).unwrap();
+            assert_close(&first, &second, path);
+        }
+    }
+
+    fn camera_values(camera: &Camera) -> Vec<f32> {
+        let mut values = [camera.position(), camera.direction(), camera.up()].iter().flat_map(|v| v.to_array()).collect::<Vec<_>>();
+        values.extend([camera.fov(), camera.ortho_height(), camera.roll(), camera.aperture(), camera.focus_distance()]);
+        values.extend([camera.blades() as f32, camera.blade_rotation(), camera.shutter().0, camera.shutter().1]);
+        values.extend(camera.end_position().into_iter().chain(camera.end_direction()).flat_map(|v| v.to_array()));
+        values
+    }
+
+    fn object_material(object: &dyn Hittable) -> Material {
+        let any = object.as_any();
+        if let Some(sphere) = any.downcast_ref::<Sphere>() {
+            *sphere.material()
+        } else if let Some(plane) = any.downcast_ref::<Plane>() {
+            *plane.material()
+        } else if let Some(triangle) = any.downcast_ref::<Triangle>() {
+            *triangle.material()
+        } else if let Some(mesh) = any.downcast_ref::<Mesh>() {
+            mesh.material()
+        } else if let Some(instance) = any.downcast_ref::<Instance>() {
+            instance.material()
+        } else {
+            panic!("unsupported object type")
+        }
+    }
+
+    fn material_values(material: &Material) -> Vec<f32> {
+        let (albedo, emission) = (material.albedo, material.emission_color);
+        vec![albedo.r, albedo.g, albedo.b, material.roughness, material.metallic, material.emission, emission.r, emission.g,
+            emission.b, material.transmission, material.ior]
+    }
+
+    #[test]
+    fn only_maps_can_be_rotated() {
+        let backgrounds = [
+            "type = \"sky\"\nelevation = 30.0",
+            "type = \"gradient\"\nzenith = [0.2, 0.4, 0.9]\nhorizon = [0.9, 0.9, 0.8]\nground = [0.1, 0.1, 0.1]",
+            "type = \"constant\"\ncolor = [0.5, 0.5, 0.5]",
+        ];
+        for background in backgrounds {
+            let rotated = format!("[environment]\n{}\nrotation = 90.0\n", background);
+            match parse_scene(&rotated, "rotated") {
+                Err(SceneError::Invalid { key, .. }) => assert_eq!(key, "environment.rotation", "{}", background),
+                other => panic!("{} was accepted with a rotation: {:?}", background, other.err()),
+            }
+            parse_scene(&format!("[environment]\n{}\nrotation = 0.0\n", background), "unrotated").unwrap();
+        }
+    }
+
+    #[test]
+    fn mesh_leaves_fit_the_shader() {
+        let mesh = |leaf_size: u32| {
+            format!("[[objects]]\ntype = \"mesh\"\npath = \"src/models/teapot.obj\"\nbvh_leaf_size = {}\n", leaf_size)
+        };
+        for leaf_size in [0, MAX_LEAF_SIZE + 1] {
+            match parse_scene(&mesh(leaf_size), "leaf size") {
+                Err(SceneError::Invalid { key, .. }) => assert_eq!(key, "objects[0].bvh_leaf_size", "{}", leaf_size),
+                other => panic!("a leaf size of {} was accepted: {:?}", leaf_size, other.err()),
+            }
+        }
+        parse_scene(&mesh(MAX_LEAF_SIZE), "leaf size").unwrap();
+    }
+
+    fn assert_all_close(a: &[f32], b: &[f32], key: &str) {
+        assert_eq!(a.len(), b.len(), "{}", key);
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            assert!((a - b).abs() <= 1.0e-4 * a.abs().max(1.0), "{}[{}]: {} != {}", key, i, a, b);
+        }
+    }
+
+    // Equal apart from the rounding a transform picks up going through a matrix and back, and angles a whole turn
+    // apart.
+    fn assert_close(a: &toml::Value, b: &toml::Value, key: &str) {
+        match (a, b) {
+            (toml::Value::Float(a), toml::Value::Float(b)) => {
+                let mut difference = (a - b).abs();
+                if key.contains("rotation") {
+                    difference %= 360.0;
+                    difference = difference.min(360.0 - difference);
+                }
+                assert!(difference <= 1.0e-4 * a.abs().max(1.0), "{}: {} != {}", key, a, b);
+            }
+            (toml::Value::Array(a), toml::Value::Array(b)) => {
+                assert_eq!(a.len(), b.len(), "{}", key);
+                for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                    assert_close(a, b, &format!("{}[{}]", key, i));
+                }
+            }
+            (toml::Value::Table(a), toml::Value::Table(b)) => {
+                assert_eq!(a.keys().collect::<Vec<_>>(), b.keys().collect::<Vec<_>>(), "{}", key);
+                for (name, value) in a {
+                    assert_close(value, &b[name], &format!("{}.{}", key, name));
+                }
+            }
+            _ => assert_eq!(a, b, "{}", key),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Metallic-roughness BRDF: a Lambert diffuse lobe under a GGX specular lobe with height-correlated Smith masking.
+// Mirrors brdf.rs line for line so the CPU and GPU renderers converge to the same image.
+
+// keeps the GGX distribution finite for perfectly smooth surfaces
+const MIN_ALPHA: f32 = 1.0e-3;
+
+struct BrdfEval {
+    value: vec3<f32>,
+    pdf: f32,
+}
+
+// weight is the BRDF times cosine over the pdf, a pdf of zero means the sample was rejected
+struct BrdfSample {
+    direction: vec3<f32>,
+    weight: vec3<f32>,
+    pdf: f32,
+}
+
+// BRDF value and the pdf brdf_sample picks `L` with. N has to face the viewer.
+fn brdf_evaluate(albedo: vec3<f32>, metallic: f32, roughness: f32, N: vec3<f32>, V: vec3<f32>, L: vec3<f32>) -> BrdfEval {
+    let NdotL = dot(N, L);
+    let NdotV = dot(N, V);
+    if (NdotL <= 0.0 || NdotV <= 0.0) {
+        return BrdfEval(vec3<f32>(0.0), 0.0);
+    }
+
+    let alpha = brdf_alpha(roughness);
+    let H = normalize(V + L);
+    let NdotH = max(dot(N, H), 0.0);
+    let VdotH = max(dot(V, H), 0.0);
+
+    let F0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = fresnel_schlick(F0, VdotH);
+    let D = ggx_d(NdotH, alpha);
+    let G = smith_g2(NdotV, NdotL, alpha);
+
+    let specular = fresnel * (D * G / (4.0 * NdotV * NdotL));
+    let diffuse = (1.0 - fresnel) * albedo * (1.0 - metallic) / PI;
+
+    let p_specular = specular_probability(albedo, metallic, NdotV);
+    let pdf = p_specular * vndf_pdf(NdotV, NdotH, alpha) + (1.0 - p_specular) * NdotL / PI;
+    return BrdfEval(specular + diffuse, pdf);
+}
+
+// Picks the specular or the diffuse lobe, then a direction from it. u.x selects the lobe, u.yz the direction.
+fn brdf_sample(albedo: vec3<f32>, metallic: f32, roughness: f32, N: vec3<f32>, V: vec3<f32>, u: vec3<f32>) -> BrdfSample {
+    let NdotV = dot(N, V);
+    if (NdotV <= 0.0) {
+        return BrdfSample(vec3<f32>(0.0), vec3<f32>(0.0), 0.0);
+    }
+
+    let frame = tangent_frame(N);
+    var L: vec3<f32>;
+    if (u.x < specular_probability(albedo, metallic, NdotV)) {
+        let local_v = vec3<f32>(dot(V, frame[0]), dot(V, frame[1]), dot(V, N));
+        let h = sample_vndf(local_v, brdf_alpha(roughness), u.y, u.z);
+        let H = frame[0] * h.x + frame[1] * h.y + N * h.z;
+        L = 2.0 * dot(V, H) * H - V;
+    } else {
+        let l = cosine_hemisphere(u.y, u.z);
+        L = frame[0] * l.x + frame[1] * l.y + N * l.z;
+    }
+
+    let eval = brdf_evaluate(albedo, metallic, roughness, N, V, L);
+    if (eval.pdf <= 0.0) {
+        return BrdfSample(vec3<f32>(0.0), vec3<f32>(0.0), 0.0);
+    }
+    return BrdfSample(L, eval.value * (dot(N, L) / eval.pdf), eval.pdf);
+}
+
+fn brdf_alpha(roughness: f32) -> f32 {
+    return max(roughness * roughness, MIN_ALPHA);
+}
+
+fn fresnel_schlick(F0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return F0 + (1.0 - F0) * pow(1.0 - cos_theta, 5.0);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// chance of sampling the specular lobe, from how much each lobe reflects head on from this angle
+fn specular_probability(albedo: vec3<f32>, metallic: f32, NdotV: f32) -> f32 {
+    let fresnel = fresnel_schlick(mix(vec3<f32>(0.04), albedo, metallic), NdotV);
+    let specular = luminance(fresnel);
+    let diffuse = luminance((1.0 - fresnel) * albedo * (1.0 - metallic));
+    if (specular + diffuse <= 0.0) {
+        return 1.0;
+    }
+    return specular / (specular + diffuse);
+}
+
+fn ggx_d(NdotH: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = max(1.0 - cos2, 0.0) / cos2;
+    return 0.5 * (sqrt(1.0 + alpha * alpha * tan2) - 1.0);
+}
+
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(cos_theta, alpha));
+}
+
+fn smith_g2(NdotV: f32, NdotL: f32, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + smith_lambda(NdotV, alpha) + smith_lambda(NdotL, alpha));
+}
+
+// density of the reflected direction when the half vector comes from the visible normals
+fn vndf_pdf(NdotV: f32, NdotH: f32, alpha: f32) -> f32 {
+    return smith_g1(NdotV, alpha) * ggx_d(NdotH, alpha) / (4.0 * NdotV);
+}
+
+// Heitz 2018, "Sampling the GGX Distribution of Visible Normals", in the local frame with the normal along +Z
+fn sample_vndf(V: vec3<f32>, alpha: f32, u1: f32, u2: f32) -> vec3<f32> {
+    let Vh = normalize(vec3<f32>(alpha * V.x, alpha * V.y, V.z));
+    let len_sq = Vh.x * Vh.x + Vh.y * Vh.y;
+    var T1 = vec3<f32>(1.0, 0.0, 0.0);
+    if (len_sq > 0.0) {
+        T1 = vec3<f32>(-Vh.y, Vh.x, 0.0) / sqrt(len_sq);
+    }
+    let T2 = cross(Vh, T1);
+
+    let r = sqrt(u1);
+    let phi = 2.0 * PI * u2;
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + Vh.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+
+    let Nh = T1 * p1 + T2 * p2 + Vh * sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0));
+    return normalize(vec3<f32>(alpha * Nh.x, alpha * Nh.y, max(Nh.z, 0.0)));
+}
+
+fn cosine_hemisphere(u1: f32, u2: f32) -> vec3<f32> {
+    let r = sqrt(u1);
+    let phi = 2.0 * PI * u2;
+    return vec3<f32>(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u1, 0.0)));
+}
+
+// Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+fn tangent_frame(N: vec3<f32>) -> array<vec3<f32>, 2> {
+    let sign = select(-1.0, 1.0, N.z >= 0.0);
+    let a = -1.0 / (sign + N.z);
+    let b = N.x * N.y * a;
+    return array<vec3<f32>, 2>(
+        vec3<f32>(1.0 + sign * N.x * N.x * a, sign * b, -sign * N.x),
+        vec3<f32>(b, sign + N.y * N.y * a, -N.y),
+    );
+}
@@ -0,0 +1,186 @@
+// What rays leaving the scene see: a constant colour, a gradient, a lat-long map picked by brightness for light
+// sampling or a daylight sky. Mirrors environment.rs and sky.rs.
+
+// matches the ENVIRONMENT_ constants in environment.rs
+const ENVIRONMENT_CONSTANT: u32 = 0u;
+const ENVIRONMENT_GRADIENT: u32 = 1u;
+const ENVIRONMENT_MAP: u32 = 2u;
+const ENVIRONMENT_SKY: u32 = 3u;
+
+// a pdf of zero means nothing was picked
+struct EnvironmentSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+    pdf: f32,
+}
+
+// radiance arriving from direction D, like Environment::radiance
+fn environment_radiance(D: vec3<f32>) -> vec3<f32> {
+    let direction = normalize(D);
+    var radiance: vec3<f32>;
+    switch (environment.kind) {
+        case ENVIRONMENT_GRADIENT: {
+            if (direction.y >= 0.0) {
+                radiance = mix(environment.horizon, environment.color, direction.y);
+            } else {
+                radiance = mix(environment.horizon, environment.ground, -direction.y);
+            }
+        }
+        case ENVIRONMENT_MAP: {
+            radiance = environment_lookup(environment_direction_to_uv(direction));
+        }
+        case ENVIRONMENT_SKY: {
+            radiance = sky_radiance(direction);
+        }
+        default: {
+            radiance = environment.color;
+        }
+    }
+    return radiance * environment.intensity;
+}
+
+// Preetham daylight without the sun, like Sky::radiance. Directions below the horizon see the sky just above it.
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let cos_theta = max(direction.y, 1.0e-3);
+    let gamma = acos(clamp(dot(direction, environment.sun_direction), -1.0, 1.0));
+    let cos_gamma = cos(gamma);
+
+    let A = environment.perez[0].xyz;
+    let B = environment.perez[1].xyz;
+    let C = environment.perez[2].xyz;
+    let D = environment.perez[3].xyz;
+    let E = environment.perez[4].xyz;
+    let perez = (1.0 + A * exp(B / cos_theta)) * (1.0 + C * exp(D * gamma) + E * cos_gamma * cos_gamma);
+    // luminance first, then the x and y chromaticities
+    let Yxy = environment.zenith * perez;
+
+    if (Yxy.z <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let luminance = Yxy.x;
+    let X = Yxy.y * luminance / Yxy.z;
+    let Z = (1.0 - Yxy.y - Yxy.z) * luminance / Yxy.z;
+    let rgb = vec3<f32>(
+        3.2406 * X - 1.5372 * luminance - 0.4986 * Z,
+        -0.9689 * X + 1.8758 * luminance + 0.0415 * Z,
+        0.0557 * X - 0.2040 * luminance + 1.0570 * Z,
+    );
+    return max(rgb, vec3<f32>(0.0)) * environment.sky_scale;
+}
+
+// A direction picked in proportion to the map's brightness, like Environment::sample.
+fn sample_environment_direction(u: vec2<f32>) -> EnvironmentSample {
+    let none = EnvironmentSample(vec3<f32>(0.0), vec3<f32>(0.0), 0.0);
+    let width = environment.width;
+    let height = environment.height;
+    let marginal = width * height;
+
+    let row = environment_sample_cdf(marginal, height, u.y);
+    let column = environment_sample_cdf(marginal + height + u32(row.x) * width, width, u.x);
+    let uv = vec2<f32>((column.x + column.y) / f32(width), (row.x + row.y) / f32(height));
+
+    let sin_theta = sin(PI * uv.y);
+    if (sin_theta <= 0.0) {
+        return none;
+    }
+    let direction = environment_uv_to_direction(uv);
+    let pdf = row.z * column.z / (2.0 * PI * PI * sin_theta);
+    return EnvironmentSample(direction, environment_radiance(direction), pdf);
+}
+
+// solid angle density sample_environment_direction picks D with, like Environment::pdf
+fn environment_pdf(D: vec3<f32>) -> f32 {
+    if (environment.sampled == 0u) {
+        return 0.0;
+    }
+    let uv = environment_direction_to_uv(normalize(D));
+    let sin_theta = sin(PI * uv.y);
+    if (sin_theta <= 0.0) {
+        return 0.0;
+    }
+    let width = environment.width;
+    let height = environment.height;
+    let marginal = width * height;
+    let row = min(u32(uv.y * f32(height)), height - 1u);
+    let column = min(u32(uv.x * f32(width)), width - 1u);
+    let row_pdf = environment_cdf_step(marginal, height, row);
+    let column_pdf = environment_cdf_step(marginal + height + row * width, width, column);
+    return row_pdf * column_pdf / (2.0 * PI * PI * sin_theta);
+}
+
+fn environment_direction_to_uv(D: vec3<f32>) -> vec2<f32> {
+    let phi = atan2(D.x, -D.z);
+    let u = fract((phi - environment.rotation) / (2.0 * PI) + 0.5);
+    return vec2<f32>(u, acos(clamp(D.y, -1.0, 1.0)) / PI);
+}
+
+fn environment_uv_to_direction(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI + environment.rotation;
+    let theta = uv.y * PI;
+    return vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+// bilinear, wrapping around horizontally and clamped at the poles like EnvironmentMap::lookup
+fn environment_lookup(uv: vec2<f32>) -> vec3<f32> {
+    let x = uv.x * f32(environment.width) - 0.5;
+    let y = uv.y * f32(environment.height) - 0.5;
+    let x0 = floor(x);
+    let y0 = floor(y);
+    let fx = x - x0;
+    let fy = y - y0;
+    let ix = i32(x0);
+    let iy = i32(y0);
+
+    let top = environment_texel(ix, iy) * (1.0 - fx) + environment_texel(ix + 1, iy) * fx;
+    let bottom = environment_texel(ix, iy + 1) * (1.0 - fx) + environment_texel(ix + 1, iy + 1) * fx;
+    return top * (1.0 - fy) + bottom * fy;
+}
+
+fn environment_texel(x: i32, y: i32) -> vec3<f32> {
+    let width = i32(environment.width);
+    let wrapped_x = ((x % width) + width) % width;
+    let clamped_y = clamp(y, 0, i32(environment.height) - 1);
+    let rgbe = environment_data[u32(clamped_y * width + wrapped_x)];
+
+    let exponent = rgbe >> 24u;
+    if (exponent == 0u) {
+        return vec3<f32>(0.0);
+    }
+    let mantissa = vec3<f32>(f32(rgbe & 0xffu), f32((rgbe >> 8u) & 0xffu), f32((rgbe >> 16u) & 0xffu)) + 0.5;
+    return mantissa * ldexp(1.0, i32(exponent) - 136);
+}
+
+// The first of the `count` CDF entries from `start` above u, how far into it u falls and the chance of picking it
+// relative to picking evenly, like sample_cdf in environment.rs.
+fn environment_sample_cdf(start: u32, count: u32, u: f32) -> vec3<f32> {
+    var low = 0u;
+    var high = count;
+    while (low < high) {
+        let middle = (low + high) / 2u;
+        if (bitcast<f32>(environment_data[start + middle]) <= u) {
+            low = middle + 1u;
+        } else {
+            high = middle;
+        }
+    }
+    let index = min(low, count - 1u);
+
+    var before = 0.0;
+    if (index > 0u) {
+        before = bitcast<f32>(environment_data[start + index - 1u]);
+    }
+    let step = bitcast<f32>(environment_data[start + index]) - before;
+    var offset = 0.5;
+    if (step > 0.0) {
+        offset = clamp((u - before) / step, 0.0, 1.0);
+    }
+    return vec3<f32>(f32(index), offset, step * f32(count));
+}
+
+fn environment_cdf_step(start: u32, count: u32, index: u32) -> f32 {
+    var before = 0.0;
+    if (index > 0u) {
+        before = bitcast<f32>(environment_data[start + index - 1u]);
+    }
+    return (bitcast<f32>(environment_data[start + index]) - before) * f32(count);
+}
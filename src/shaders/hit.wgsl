@@ -1,12 +1,22 @@
 
+// Longitude/latitude mapping, matches sphere_uv in objects.rs.
+fn sphere_uv(normal: vec3<f32>) -> vec2<f32> {
+    let u = 0.5 + atan2(normal.z, -normal.x) / (2.0 * 3.14159265359);
+    let v = 0.5 + asin(clamp(normal.y, -1.0, 1.0)) / 3.14159265359;
+    return vec2<f32>(u, v);
+}
+
+// When in the shutter interval the current path was sent, set once per pixel sample. Every bounce shares it.
+var<private> ray_time: f32;
+
 fn hit_sphere(sphere: Sphere, ray: Ray) -> HitInfo {
     var hit: HitInfo;
     hit.has_hit = 0u;
     hit.t = 1e10;
-    hit._pad0 = vec2<f32>(0.0, 0.0);
-    hit._pad1 = 0.0;
+    hit.moving = 0u;
 
-    let oc = ray.origin - sphere.center;
+    let center = mix(sphere.center, sphere.end_center, ray_time);
+    let oc = ray.origin - center;
     let a = dot(ray.direction, ray.direction);
     let b = 2.0 * dot(oc, ray.direction);
     let c = dot(oc, oc) - sphere.radius * sphere.radius;
@@ -25,24 +35,36 @@ fn hit_sphere(sphere: Sphere, ray: Ray) -> HitInfo {
             hit.t = t;
             let pos = ray.origin + ray.direction * t;
             hit.pos = vec4<f32>(pos, 0.0);
-            let normal = normalize(pos - sphere.center);
+            let normal = normalize(pos - center);
             hit.normal = vec4<f32>(normal, 0.0);
             hit.albedo = vec4<f32>(sphere.albedo, 0.0);
             hit.emission = sphere.emission;
+            hit.emission_color = sphere.emission_color;
             hit.metallic = sphere.metallic;
             hit.roughness = sphere.roughness;
+            hit.transmission = sphere.transmission;
+            hit.ior = sphere.ior;
+            hit.uv = sphere_uv(normal);
+            hit.textures = sphere.textures;
         }
     }
 
     return hit;
 }
 
+fn normal_or(v: vec3<f32>, fallback: vec3<f32>) -> vec3<f32> {
+    let length_sq = dot(v, v);
+    if (length_sq > 0.0) {
+        return v * inverseSqrt(length_sq);
+    }
+    return fallback;
+}
+
 fn hit_triangle(tri: Triangle, ray: Ray) -> HitInfo {
     var hit: HitInfo;
     hit.has_hit = 0u;
     hit.t = 1e10;
-    hit._pad0 = vec2<f32>(0.0, 0.0);
-    hit._pad1 = 0.0;
+    hit.moving = 0u;
 
     let eps = 1e-6;
     let edge1 = tri.v1 - tri.v0;
@@ -76,12 +98,18 @@ fn hit_triangle(tri: Triangle, ray: Ray) -> HitInfo {
         hit.t = t;
         let pos = ray.origin + ray.direction * t;
         hit.pos = vec4<f32>(pos, 0.0);
-        let normal = normalize(cross(edge1, edge2));
-        hit.normal = vec4<f32>(normal, 0.0);
+        let geometric = normal_or(cross(edge1, edge2), vec3<f32>(0.0, 1.0, 0.0));
+        let shading = normal_or(tri.n0 * (1.0 - u - v) + tri.n1 * u + tri.n2 * v, geometric);
+        hit.normal = vec4<f32>(select(shading, -shading, dot(shading, geometric) < 0.0), 0.0);
         hit.albedo = vec4<f32>(tri.albedo, 0.0);
         hit.emission = tri.emission;
+        hit.emission_color = tri.emission_color;
         hit.metallic = tri.metallic;
         hit.roughness = tri.roughness;
+        hit.transmission = tri.transmission;
+        hit.ior = tri.ior;
+        hit.uv = tri.uv0 * (1.0 - u - v) + tri.uv1 * u + tri.uv2 * v;
+        hit.textures = tri.textures;
     }
 
     return hit;
@@ -91,8 +119,7 @@ fn hit_plane(plane: Plane, ray: Ray) -> HitInfo {
     var hit: HitInfo;
     hit.has_hit = 0u;
     hit.t = 1e10;
-    hit._pad0 = vec2<f32>(0.0, 0.0);
-    hit._pad1 = 0.0;
+    hit.moving = 0u;
 
     let eps = 1e-6;
 
@@ -137,8 +164,13 @@ fn hit_plane(plane: Plane, ray: Ray) -> HitInfo {
     hit.normal = vec4<f32>(n, 0.0);
     hit.albedo = vec4<f32>(plane.albedo);
     hit.emission = plane.emission;
+    hit.emission_color = plane.emission_color;
     hit.metallic = plane.metallic;
     hit.roughness = plane.roughness;
+    hit.transmission = plane.transmission;
+    hit.ior = plane.ior;
+    hit.uv = vec2<f32>(u_dist / plane.width + 0.5, v_dist / plane.length + 0.5);
+    hit.textures = plane.textures;
 
     return hit;
 }
@@ -154,32 +186,45 @@ fn intersect_aabb(ray: Ray, aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> f32 {
     let tmin_max = max(max(tmin.x, tmin.y), tmin.z);
     let tmax_min = min(min(tmax.x, tmax.y), tmax.z);
 
+    // a ray starting inside the box enters it at 0 rather than behind its origin
     if (tmax_min >= max(0.001, tmin_max)) {
-        return tmin_max;
+        return max(tmin_max, 0.0);
     }
     return -1.0;
 }
 
-fn traverse_bvh(ray: Ray) -> HitInfo {
-    var closest_hit: HitInfo;
-    closest_hit.has_hit = 0u;
-    var closest_t = 3.402823466e+38;
+// Leaf entries carry the primitive type in their top two bits, see primitive_ref in bvh.rs.
+fn hit_primitive(entry: u32, ray: Ray) -> HitInfo {
+    let kind = entry >> PRIMITIVE_SHIFT;
+    let index = entry & PRIMITIVE_INDEX_MASK;
 
-    if (counts.bvh_node_count == 0u) {
-        return closest_hit;
+    if (kind == PRIMITIVE_TRIANGLE && index < counts.triangle_count) {
+        return hit_triangle(triangles[index], ray);
+    }
+    if (kind == PRIMITIVE_SPHERE && index < counts.sphere_count) {
+        return hit_sphere(spheres[index], ray);
+    }
+    if (kind == PRIMITIVE_PLANE && index < counts.plane_count) {
+        return hit_plane(planes[index], ray);
     }
 
-    var stack: array<u32, 24>;
-    var stack_ptr = 0;
+    var miss: HitInfo;
+    miss.has_hit = 0u;
+    return miss;
+}
 
-    stack[0] = 0u;
-    stack_ptr = 1;
+// Closest primitive below `root` that is nearer than `max_t`, the ray is in the BVH's object space.
+fn traverse_blas(ray: Ray, root: u32, max_t: f32) -> HitInfo {
+    var closest_hit: HitInfo;
+    closest_hit.has_hit = 0u;
+    var closest_t = max_t;
 
-    var iterations = 0u;
-    let max_iterations = 1000u;
+    var stack: array<u32, 64>;
+    var stack_ptr = 1;
+    stack[0] = root;
 
-    while (stack_ptr > 0 && iterations < max_iterations) {
-        iterations += 1u;
+    // a tree pushes every node at most once, so the stack empties on its own
+    while (stack_ptr > 0) {
         stack_ptr -= 1;
 
         let node_idx = stack[stack_ptr];
@@ -198,6 +243,7 @@ fn traverse_bvh(ray: Ray) -> HitInfo {
         if (node.is_leaf == 1u) {
             let first_tri = node.left_first;
             let tri_count = node.right_count;
+            // bvh::MAX_LEAF_SIZE, the scene loader refuses larger leaves
             let safe_tri_count = min(tri_count, 100u);
 
             for (var i = 0u; i < safe_tri_count; i++) {
@@ -207,12 +253,7 @@ fn traverse_bvh(ray: Ray) -> HitInfo {
                     break;
                 }
 
-                let tri_idx = bvh_indices[idx_offset];
-                if (tri_idx >= counts.triangle_count) {
-                    continue;
-                }
-
-                let hit = hit_triangle(triangles[tri_idx], ray);
+                let hit = hit_primitive(bvh_indices[idx_offset], ray);
                 if (hit.has_hit != 0u && hit.t < closest_t) {
                     closest_t = hit.t;
                     closest_hit = hit;
@@ -221,13 +262,13 @@ fn traverse_bvh(ray: Ray) -> HitInfo {
         } else {
             let left_child = node.left_first;
             let right_child = node.right_count;
-            if (stack_ptr < 22) {
+            if (stack_ptr < 64) {
                 if (right_child < counts.bvh_node_count) {
                     stack[stack_ptr] = right_child;
                     stack_ptr += 1;
                 }
             }
-            if (stack_ptr < 22) {
+            if (stack_ptr < 64) {
                 if (left_child < counts.bvh_node_count) {
                     stack[stack_ptr] = left_child;
                     stack_ptr += 1;
@@ -239,41 +280,143 @@ fn traverse_bvh(ray: Ray) -> HitInfo {
     return closest_hit;
 }
 
-fn trace_scene(ray: Ray) -> HitInfo {
+// Same as glam's Quat * Vec3 for a unit `q`.
+fn quat_rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Same as glam's Quat::slerp.
+fn quat_slerp(a: vec4<f32>, b_in: vec4<f32>, t: f32) -> vec4<f32> {
+    var b = b_in;
+    var d = dot(a, b);
+    if (d < 0.0) {
+        b = -b;
+        d = -d;
+    }
+    if (d > 0.9995) {
+        return normalize(mix(a, b, t));
+    }
+    let theta = acos(d);
+    return (a * sin(theta * (1.0 - t)) + b * sin(theta * t)) / sin(theta);
+}
+
+// The pose of a moving instance at the current ray time, like instance::interpolate_transform.
+fn instance_at(instance: Instance) -> Pose {
+    return Pose(
+        mix(instance.start.translation, instance.end.translation, ray_time),
+        quat_slerp(instance.start.rotation, instance.end.rotation, ray_time),
+        mix(instance.start.scale, instance.end.scale, ray_time),
+    );
+}
+
+// Moves the ray into the instance's object space, traces its BLAS and brings the hit back to world space.
+fn hit_instance(instance: Instance, ray: Ray, max_t: f32) -> HitInfo {
+    let m = instance.world_to_object;
+    // the direction is not normalised so t means the same in both spaces
+    var local_ray = Ray((m * vec4<f32>(ray.origin, 1.0)).xyz, (m * vec4<f32>(ray.direction, 0.0)).xyz);
+    var pose: Pose;
+    if (instance.moving == 1u) {
+        pose = instance_at(instance);
+        let inverse_rotation = vec4<f32>(-pose.rotation.xyz, pose.rotation.w);
+        local_ray = Ray(
+            quat_rotate(inverse_rotation, ray.origin - pose.translation) / pose.scale,
+            quat_rotate(inverse_rotation, ray.direction) / pose.scale,
+        );
+    }
+
+    var hit = traverse_blas(local_ray, instance.blas_root, max_t);
+    if (hit.has_hit == 0u) {
+        return hit;
+    }
+
+    hit.pos = vec4<f32>(ray.origin + ray.direction * hit.t, 0.0);
+    hit.moving = instance.moving;
+    if (instance.moving == 1u) {
+        hit.normal = vec4<f32>(normalize(quat_rotate(pose.rotation, hit.normal.xyz / pose.scale)), 0.0);
+    } else {
+        // normals go through the inverse transpose of object to world, which is the transpose of m
+        hit.normal = vec4<f32>(normalize((vec4<f32>(hit.normal.xyz, 0.0) * m).xyz), 0.0);
+    }
+
+    if (instance.override_material == 1u) {
+        let material = instance.material;
+        hit.albedo = vec4<f32>(material.albedo, 0.0);
+        hit.emission = material.emission;
+        hit.emission_color = material.emission_color;
+        hit.metallic = material.metallic;
+        hit.roughness = material.roughness;
+        hit.transmission = material.transmission;
+        hit.ior = material.ior;
+        hit.textures = material.textures;
+    }
+    return hit;
+}
+
+// Walks the top-level BVH, whose leaves index into the instance buffer.
+fn traverse_tlas(ray: Ray) -> HitInfo {
     var closest_hit: HitInfo;
     closest_hit.has_hit = 0u;
     var closest_t = 3.402823466e+38;
 
-    for (var i = 0u; i < counts.sphere_count; i++) {
-        let hit = hit_sphere(spheres[i], ray);
-        if (hit.has_hit != 0u && hit.t < closest_t) {
-            closest_t = hit.t;
-            closest_hit = hit;
+    var stack: array<u32, 64>;
+    var stack_ptr = 1;
+    stack[0] = counts.tlas_root;
+
+    var iterations = 0u;
+    let max_iterations = 2u * counts.instance_count;
+
+    while (stack_ptr > 0 && iterations < max_iterations) {
+        iterations += 1u;
+        stack_ptr -= 1;
+
+        let node_idx = stack[stack_ptr];
+        if (node_idx >= counts.bvh_node_count) {
+            continue;
         }
-    }
-    if (counts.bvh_node_count > 1u) {
-        let bvh_hit = traverse_bvh(ray);
-        if (bvh_hit.has_hit != 0u && bvh_hit.t < closest_t) {
-            closest_t = bvh_hit.t;
-            closest_hit = bvh_hit;
+
+        let node = bvh_nodes[node_idx];
+        let aabb_t = intersect_aabb(ray, node.min, node.max);
+        if (aabb_t < 0.0 || aabb_t > closest_t) {
+            continue;
         }
-    } else {
-        for (var i = 0u; i < counts.triangle_count; i++) {
-            let hit = hit_triangle(triangles[i], ray);
-            if (hit.has_hit != 0u && hit.t < closest_t) {
-                closest_t = hit.t;
-                closest_hit = hit;
+
+        if (node.is_leaf == 1u) {
+            for (var i = 0u; i < node.right_count; i++) {
+                let idx_offset = node.left_first + i;
+                if (idx_offset >= counts.bvh_index_count) {
+                    break;
+                }
+
+                let instance_idx = bvh_indices[idx_offset];
+                if (instance_idx >= counts.instance_count) {
+                    continue;
+                }
+
+                let hit = hit_instance(instances[instance_idx], ray, closest_t);
+                if (hit.has_hit != 0u && hit.t < closest_t) {
+                    closest_t = hit.t;
+                    closest_hit = hit;
+                }
+            }
+        } else {
+            if (stack_ptr < 63) {
+                stack[stack_ptr] = node.right_count;
+                stack[stack_ptr + 1] = node.left_first;
+                stack_ptr += 2;
             }
         }
     }
 
-    for (var i = 0u; i < counts.plane_count; i++) {
-        let hit = hit_plane(planes[i], ray);
-        if (hit.has_hit != 0u && hit.t < closest_t) {
-            closest_t = hit.t;
-            closest_hit = hit;
-        }
+    return closest_hit;
+}
+
+fn trace_scene(ray: Ray) -> HitInfo {
+    var closest_hit: HitInfo;
+    closest_hit.has_hit = 0u;
+
+    if (counts.instance_count > 0u) {
+        closest_hit = traverse_tlas(ray);
     }
 
-    return closest_hit;
+    return apply_textures(closest_hit);
 }
@@ -0,0 +1,214 @@
+// Point, spot, directional, rect and disk lights. Mirrors light.rs so the CPU and GPU renderers sample them alike.
+
+// matches the LIGHT_ constants in light.rs
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+const LIGHT_DIRECTIONAL: u32 = 2u;
+const LIGHT_RECT: u32 = 3u;
+const LIGHT_DISK: u32 = 4u;
+
+// distance to anything a ray leaving the scene sees, the sun among it
+const FAR_AWAY: f32 = 3.0e38;
+
+// a pdf of zero means the light can't be seen from the origin, delta lights have a pdf of one and their fall off
+// already in the radiance
+struct LightSample {
+    direction: vec3<f32>,
+    distance: f32,
+    radiance: vec3<f32>,
+    pdf: f32,
+    delta: bool,
+}
+
+fn light_is_delta(light: Light) -> bool {
+    switch (light.kind) {
+        case LIGHT_POINT, LIGHT_SPOT: {
+            return light.radius <= 0.0;
+        }
+        case LIGHT_DIRECTIONAL: {
+            return light.cos_outer >= 1.0;
+        }
+        default: {
+            return false;
+        }
+    }
+}
+
+// A direction towards the light from `origin`, spread by `u`, like Light::sample.
+fn sample_light(light: Light, origin: vec3<f32>, u: vec2<f32>) -> LightSample {
+    let none = LightSample(vec3<f32>(0.0), 0.0, vec3<f32>(0.0), 0.0, false);
+    switch (light.kind) {
+        case LIGHT_POINT, LIGHT_SPOT: {
+            let to_center = light.position - origin;
+            let center_distance = length(to_center);
+            if (light.radius <= 0.0) {
+                let L = to_center / center_distance;
+                let radiance = light.radiance * (spot_falloff(light, -L) / (center_distance * center_distance));
+                return LightSample(L, center_distance, radiance, 1.0, true);
+            }
+            if (center_distance <= light.radius) {
+                return none;
+            }
+
+            let sin2_max = light.radius * light.radius / (center_distance * center_distance);
+            let cos_max = sqrt(max(1.0 - sin2_max, 0.0));
+            let L = sample_cone(to_center / center_distance, cos_max, u);
+            var distance = light_sphere_distance(origin, L, light.position, light.radius);
+            if (distance <= 0.0) {
+                distance = center_distance;
+            }
+            return LightSample(L, distance, light.radiance * spot_falloff(light, -L), 1.0 / cone_solid_angle(cos_max), false);
+        }
+        case LIGHT_DIRECTIONAL: {
+            if (light.cos_outer >= 1.0) {
+                return LightSample(-light.direction, FAR_AWAY, light.radiance, 1.0, true);
+            }
+            let L = sample_cone(-light.direction, light.cos_outer, u);
+            return LightSample(L, FAR_AWAY, light.radiance, 1.0 / cone_solid_angle(light.cos_outer), false);
+        }
+        default: {
+            var point: vec3<f32>;
+            var area: f32;
+            if (light.kind == LIGHT_RECT) {
+                point = light.position + light.u * (2.0 * u.x - 1.0) + light.v * (2.0 * u.y - 1.0);
+                area = 4.0 * length(light.u) * length(light.v);
+            } else {
+                let frame = tangent_frame(light.direction);
+                let r = light.radius * sqrt(u.x);
+                let phi = 2.0 * PI * u.y;
+                point = light.position + (frame[0] * cos(phi) + frame[1] * sin(phi)) * r;
+                area = PI * light.radius * light.radius;
+            }
+
+            let to_light = point - origin;
+            let distance = length(to_light);
+            let L = to_light / distance;
+            let pdf = light_area_pdf(light.direction, area, L, distance);
+            if (pdf <= 0.0) {
+                return none;
+            }
+            return LightSample(L, distance, light.radiance, pdf, false);
+        }
+    }
+}
+
+// Solid angle density sample_light picks `L` with, for a ray that hit the light `distance` away, like Light::pdf.
+fn light_pdf(light: Light, origin: vec3<f32>, L: vec3<f32>, distance: f32) -> f32 {
+    switch (light.kind) {
+        case LIGHT_POINT, LIGHT_SPOT: {
+            let center_distance = length(light.position - origin);
+            if (light.radius <= 0.0 || center_distance <= light.radius) {
+                return 0.0;
+            }
+            let sin2_max = light.radius * light.radius / (center_distance * center_distance);
+            return 1.0 / cone_solid_angle(sqrt(max(1.0 - sin2_max, 0.0)));
+        }
+        case LIGHT_DIRECTIONAL: {
+            if (light.cos_outer >= 1.0) {
+                return 0.0;
+            }
+            return 1.0 / cone_solid_angle(light.cos_outer);
+        }
+        case LIGHT_RECT: {
+            return light_area_pdf(light.direction, 4.0 * length(light.u) * length(light.v), L, distance);
+        }
+        default: {
+            return light_area_pdf(light.direction, PI * light.radius * light.radius, L, distance);
+        }
+    }
+}
+
+// Distance along the ray to the light when it is closer than max_t, negative otherwise, like Light::hit. The sun is only
+// seen by rays leaving the scene, with a max_t of FAR_AWAY.
+fn hit_light(light: Light, ray: Ray, max_t: f32) -> f32 {
+    let scale = length(ray.direction);
+    let D = ray.direction / scale;
+    var t = -1.0;
+    switch (light.kind) {
+        case LIGHT_POINT, LIGHT_SPOT: {
+            if (light.radius > 0.0) {
+                t = light_sphere_distance(ray.origin, D, light.position, light.radius);
+            }
+        }
+        case LIGHT_DIRECTIONAL: {
+            if (max_t >= FAR_AWAY && light.cos_outer < 1.0 && dot(D, -light.direction) >= light.cos_outer) {
+                return FAR_AWAY;
+            }
+        }
+        case LIGHT_RECT: {
+            t = facing_plane_distance(ray.origin, D, light.position, light.direction);
+            let local = ray.origin + D * t - light.position;
+            if (abs(dot(local, light.u)) > dot(light.u, light.u) || abs(dot(local, light.v)) > dot(light.v, light.v)) {
+                t = -1.0;
+            }
+        }
+        default: {
+            t = facing_plane_distance(ray.origin, D, light.position, light.direction);
+            let offset = ray.origin + D * t - light.position;
+            if (dot(offset, offset) > light.radius * light.radius) {
+                t = -1.0;
+            }
+        }
+    }
+
+    // t along the normalised direction, max_t along the ray's own
+    if (t <= 0.0 || t / scale >= max_t) {
+        return -1.0;
+    }
+    return t / scale;
+}
+
+// how much of the spot's light leaves along `direction`, one for every other light
+fn spot_falloff(light: Light, direction: vec3<f32>) -> f32 {
+    if (light.kind != LIGHT_SPOT) {
+        return 1.0;
+    }
+    let cos_angle = dot(direction, light.direction);
+    if (light.cos_outer >= light.cos_inner) {
+        return select(0.0, 1.0, cos_angle >= light.cos_inner);
+    }
+    return smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+}
+
+// solid angle of a cone, written so it keeps its precision for tiny ones
+fn cone_solid_angle(cos_max: f32) -> f32 {
+    let sin2 = max(1.0 - cos_max * cos_max, 0.0);
+    return 2.0 * PI * sin2 / (1.0 + cos_max);
+}
+
+fn sample_cone(axis: vec3<f32>, cos_max: f32, u: vec2<f32>) -> vec3<f32> {
+    let cos_theta = 1.0 - u.x * (1.0 - cos_max);
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * PI * u.y;
+    let frame = tangent_frame(axis);
+    return normalize(frame[0] * (sin_theta * cos(phi)) + frame[1] * (sin_theta * sin(phi)) + axis * cos_theta);
+}
+
+fn light_area_pdf(normal: vec3<f32>, area: f32, L: vec3<f32>, distance: f32) -> f32 {
+    let cos_light = -dot(normal, L);
+    if (cos_light <= 0.0) {
+        return 0.0;
+    }
+    return distance * distance / (cos_light * area);
+}
+
+// nearest distance along the unit direction to the sphere from outside of it, negative when it's missed
+fn light_sphere_distance(origin: vec3<f32>, D: vec3<f32>, center: vec3<f32>, radius: f32) -> f32 {
+    let oc = origin - center;
+    let b = dot(oc, D);
+    let c = dot(oc, oc) - radius * radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return -1.0;
+    }
+    return -b - sqrt(discriminant);
+}
+
+// distance along the unit direction to the plane, negative unless the ray arrives at its front
+fn facing_plane_distance(origin: vec3<f32>, D: vec3<f32>, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let denom = dot(D, normal);
+    if (denom >= 0.0) {
+        return -1.0;
+    }
+    return dot(position - origin, normal) / denom;
+}
@@ -1,4 +1,4 @@
-@group(0) @binding(0) var<storage, read> rays: array<Ray>;
+@group(0) @binding(0) var<uniform> camera: Camera;
 @group(0) @binding(1) var<storage, read_write> output_colors: array<vec4<f32>>;
 @group(0) @binding(2) var<storage, read> spheres: array<Sphere>;
 @group(0) @binding(3) var<storage, read> triangles: array<Triangle>;
@@ -6,46 +6,158 @@
 @group(0) @binding(5) var<uniform> counts: Counts;
 @group(0) @binding(6) var<storage, read> bvh_nodes: array<BVHNode>;
 @group(0) @binding(7) var<storage, read> bvh_indices: array<u32>;
+// texture_count headers of 4 words followed by the RGBA8 texels of every texture
+@group(0) @binding(8) var<storage, read> textures: array<u32>;
+@group(0) @binding(9) var<storage, read> instances: array<Instance>;
+// emissive primitives picked by power for light sampling, counts.emitter_count of them
+@group(0) @binding(10) var<storage, read> emitters: array<Emitter>;
+// analytic lights picked uniformly for light sampling, counts.light_count of them
+@group(0) @binding(11) var<storage, read> lights: array<Light>;
+@group(0) @binding(12) var<uniform> environment: Environment;
+// RGBE texels and CDFs of the environment map, see Environment
+@group(0) @binding(13) var<storage, read> environment_data: array<u32>;
 
-const MAX_BOUNCES: u32 = 10u;
 const PI: f32 = 3.14159265359;
 
+// matches PixelFilter::gpu_id
+const FILTER_BOX: u32 = 0u;
+const FILTER_TENT: u32 = 1u;
+const FILTER_GAUSSIAN: u32 = 2u;
+const FILTER_BLACKMAN_HARRIS: u32 = 3u;
+
+// matches Projection::as_u32
+const PROJECTION_PERSPECTIVE: u32 = 0u;
+const PROJECTION_ORTHOGRAPHIC: u32 = 1u;
+const PROJECTION_FISHEYE: u32 = 2u;
+const PROJECTION_EQUIRECTANGULAR: u32 = 3u;
+
+// matches the EMITTER_ constants in emitter.rs
+const EMITTER_SPHERE: u32 = 0u;
+const EMITTER_PLANE: u32 = 1u;
+const EMITTER_TRIANGLE: u32 = 2u;
+
+// shadow rays count as unoccluded when they stop this close to the light, relative to its distance
+const SHADOW_EPSILON: f32 = 1.0e-3;
+
+fn schlick_reflectance(cos_theta: f32, ior: f32) -> f32 {
+    let r0 = (1.0 - ior) / (1.0 + ior);
+    let r0_sq = r0 * r0;
+    return r0_sq + (1.0 - r0_sq) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Picks between reflection and refraction with the Fresnel term, so the path weight stays the transmission tint.
+// Rough glass jitters the normal towards a cosine sample, blurring both the reflection and the refraction.
+fn sample_dielectric(direction: vec3<f32>, normal: vec3<f32>, ior: f32, roughness: f32, seed: ptr<function, u32>) -> vec3<f32> {
+    var N = normal;
+    var eta = 1.0 / ior;
+    if (dot(direction, N) > 0.0) {
+        N = -N;
+        eta = ior;
+    }
+
+    if (roughness > 0.0) {
+        let jittered = normalize(mix(N, random_cosine_hemisphere(N, seed), roughness * roughness));
+        if (dot(direction, jittered) < 0.0) {
+            N = jittered;
+        }
+    }
+
+    let cos_i = min(dot(-direction, N), 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if (sin2_t > 1.0) {
+        return reflect(direction, N);
+    }
+
+    // going from the denser medium the reflectance is driven by the transmitted angle
+    let cos_fresnel = select(cos_i, sqrt(1.0 - sin2_t), eta > 1.0);
+    if (random_float(seed) < schlick_reflectance(cos_fresnel, ior)) {
+        return reflect(direction, N);
+    }
+
+    return refract(direction, N, eta);
+}
+
 fn trace_path(initial_ray: Ray, seed: ptr<function, u32>) -> vec3<f32> {
     var ray = initial_ray;
     var throughput = vec3<f32>(1.0);
     var accumulated_light = vec3<f32>(0.0);
+    // density the BRDF sampled the current ray with, zero for camera rays and refractions which light sampling doesn't cover
+    var brdf_pdf = 0.0;
 
-    for (var bounce = 0u; bounce < MAX_BOUNCES; bounce++) {
+    for (var bounce = 0u; bounce < counts.max_bounces; bounce++) {
         let hit = trace_scene(ray);
 
+        if (counts.light_count > 0u) {
+            let light_hit = hit_lights(ray, select(FAR_AWAY, hit.t, hit.has_hit == 1u), bounce == 0u, brdf_pdf);
+            if (light_hit.hit) {
+                accumulated_light += throughput * light_hit.radiance;
+                break;
+            }
+        }
+
         if (hit.has_hit == 0u) {
+            var weight = 1.0;
+            // light sampling may have picked this direction from the previous bounce as well
+            if (brdf_pdf > 0.0 && environment.sampled == 1u) {
+                weight = power_heuristic(brdf_pdf, environment_pdf(ray.direction));
+            }
+            accumulated_light += throughput * environment_radiance(ray.direction) * weight;
             break;
         }
 
         if (hit.emission > 0.0) {
-            accumulated_light += throughput * hit.albedo.xyz * hit.emission;
+            let radiance = hit.emission_color * hit.emission;
+            var weight = 1.0;
+            // light sampling already reached this emitter from the previous bounce, weigh the two against each other
+            if (brdf_pdf > 0.0 && hit.moving == 0u) {
+                let distance = hit.t * length(ray.direction);
+                let cos_light = abs(dot(normalize(hit.normal.xyz), normalize(ray.direction)));
+                weight = power_heuristic(brdf_pdf, emitter_pdf(radiance, distance, cos_light));
+            }
+            accumulated_light += throughput * radiance * weight;
             break;
         }
 
         let N = normalize(hit.normal.xyz);
         let V = normalize(-ray.direction);
 
-        // shit im not smart enought to understand
-        // https://en.wikipedia.org/wiki/Schlick%27s_approximation
-        let F0 = mix(vec3<f32>(0.04), hit.albedo.xyz, hit.metallic);
-        let NdotV = max(dot(N, V), 0.0);
-        let fresnel = F0 + (1.0 - F0) * pow(1.0 - NdotV, 5.0);
+        if (hit.transmission > 0.0 && random_float(seed) < hit.transmission) {
+            let out_dir = sample_dielectric(ray.direction, N, hit.ior, hit.roughness, seed);
+            let entering = dot(ray.direction, N) < 0.0;
+            if (entering && dot(out_dir, N) < 0.0) {
+                throughput *= hit.albedo.xyz;
+            }
+
+            ray.origin = hit.pos.xyz + sign(dot(out_dir, N)) * N * 0.001;
+            ray.direction = out_dir;
+            brdf_pdf = 0.0;
+            continue;
+        }
 
-        let diffuse_dir = random_cosine_hemisphere(N, seed);
-        let specular_dir = reflect(ray.direction, N);
-        let out_dir = normalize(mix(specular_dir, diffuse_dir, hit.roughness));
-        let diffuse_weight = (1.0 - hit.metallic) * (1.0 - fresnel);
-        let specular_weight = fresnel;
+        // opaque surfaces scatter off whichever side the ray arrives at
+        let facing = select(N, -N, dot(N, V) < 0.0);
+        let origin = hit.pos.xyz + facing * 0.001;
+        // the last bounce can't see the emitter its BRDF sample would hit, so it doesn't sample one either
+        if (counts.emitter_count > 0u && bounce + 1u < counts.max_bounces) {
+            accumulated_light += throughput * sample_emitters(hit, origin, facing, V, seed);
+        }
+        if (counts.light_count > 0u && bounce + 1u < counts.max_bounces) {
+            accumulated_light += throughput * sample_lights(hit, origin, facing, V, seed);
+        }
+        if (environment.sampled == 1u && bounce + 1u < counts.max_bounces) {
+            accumulated_light += throughput * sample_environment(hit, origin, facing, V, seed);
+        }
 
-        throughput *= diffuse_weight * hit.albedo.xyz + specular_weight;
+        let u = vec3<f32>(random_float(seed), random_float(seed), random_float(seed));
+        let scattered = brdf_sample(hit.albedo.xyz, hit.metallic, hit.roughness, facing, V, u);
+        if (scattered.pdf <= 0.0) {
+            break;
+        }
 
-        ray.origin = hit.pos.xyz + N * 0.001;
-        ray.direction = out_dir;
+        throughput *= scattered.weight;
+        ray.origin = origin;
+        ray.direction = scattered.direction;
+        brdf_pdf = scattered.pdf;
 
         if (max(throughput.x, max(throughput.y, throughput.z)) < 0.001) {
             break;
@@ -55,6 +167,172 @@ fn trace_path(initial_ray: Ray, seed: ptr<function, u32>) -> vec3<f32> {
     return accumulated_light;
 }
 
+// Light from one point on one emitter, picked by power, reaching `origin` unoccluded. Weighed against the BRDF finding
+// the same point, the same as sample_emitters in renderer.rs.
+fn sample_emitters(hit: HitInfo, origin: vec3<f32>, N: vec3<f32>, V: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let emitter = emitters[pick_emitter(random_float(seed))];
+    let u = vec2<f32>(random_float(seed), random_float(seed));
+    let point = sample_emitter_point(emitter, u);
+
+    let to_light = point[0] - origin;
+    let distance = length(to_light);
+    let L = to_light / distance;
+    let cos_light = abs(dot(point[1], L));
+    let NdotL = dot(N, L);
+    if (NdotL <= 0.0 || cos_light <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let brdf = brdf_evaluate(hit.albedo.xyz, hit.metallic, hit.roughness, N, V, L);
+    if (brdf.pdf <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let shadow = trace_scene(Ray(origin, L));
+    if (shadow.has_hit == 1u && shadow.t < distance * (1.0 - SHADOW_EPSILON)) {
+        return vec3<f32>(0.0);
+    }
+
+    let light_pdf = emitter_pdf(emitter.radiance, distance, cos_light);
+    return emitter.radiance * brdf.value * (NdotL / light_pdf * power_heuristic(light_pdf, brdf.pdf));
+}
+
+// Light from one uniformly picked light reaching `origin` unoccluded, the same as sample_lights in renderer.rs. Lights
+// don't block each other's shadow rays.
+fn sample_lights(hit: HitInfo, origin: vec3<f32>, N: vec3<f32>, V: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let index = min(u32(random_float(seed) * f32(counts.light_count)), counts.light_count - 1u);
+    let light = lights[index];
+    let sample = sample_light(light, origin, vec2<f32>(random_float(seed), random_float(seed)));
+    let NdotL = dot(N, sample.direction);
+    if (sample.pdf <= 0.0 || NdotL <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let brdf = brdf_evaluate(hit.albedo.xyz, hit.metallic, hit.roughness, N, V, sample.direction);
+    if (brdf.pdf <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let shadow = trace_scene(Ray(origin, sample.direction));
+    if (shadow.has_hit == 1u && shadow.t < sample.distance * (1.0 - SHADOW_EPSILON)) {
+        return vec3<f32>(0.0);
+    }
+
+    let pdf = sample.pdf / f32(counts.light_count);
+    let weight = select(power_heuristic(pdf, brdf.pdf), 1.0, sample.delta);
+    return sample.radiance * brdf.value * (NdotL / pdf * weight);
+}
+
+// Light from one direction of the environment map, picked by brightness, reaching `origin` unblocked by the scene. The
+// same as sample_environment in renderer.rs.
+fn sample_environment(hit: HitInfo, origin: vec3<f32>, N: vec3<f32>, V: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let sample = sample_environment_direction(vec2<f32>(random_float(seed), random_float(seed)));
+    let NdotL = dot(N, sample.direction);
+    if (sample.pdf <= 0.0 || NdotL <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+
+    let brdf = brdf_evaluate(hit.albedo.xyz, hit.metallic, hit.roughness, N, V, sample.direction);
+    if (brdf.pdf <= 0.0 || trace_scene(Ray(origin, sample.direction)).has_hit == 1u) {
+        return vec3<f32>(0.0);
+    }
+    return sample.radiance * brdf.value * (NdotL / sample.pdf * power_heuristic(sample.pdf, brdf.pdf));
+}
+
+struct LightHit {
+    radiance: vec3<f32>,
+    hit: bool,
+}
+
+// The closest light the ray reaches before max_t, weighed against sample_lights finding it from the previous bounce.
+// Camera rays pass through lights that aren't visible.
+fn hit_lights(ray: Ray, max_t: f32, camera_ray: bool, brdf_pdf: f32) -> LightHit {
+    var closest_t = max_t;
+    var closest = 0u;
+    var found = false;
+    for (var i = 0u; i < counts.light_count; i++) {
+        if (camera_ray && lights[i].visible == 0u) {
+            continue;
+        }
+        let t = hit_light(lights[i], ray, closest_t);
+        if (t > 0.0 && (t < closest_t || t >= FAR_AWAY)) {
+            closest_t = t;
+            closest = i;
+            found = true;
+        }
+    }
+    if (!found) {
+        return LightHit(vec3<f32>(0.0), false);
+    }
+
+    let light = lights[closest];
+    let D = normalize(ray.direction);
+    var weight = 1.0;
+    if (brdf_pdf > 0.0) {
+        let distance = closest_t * length(ray.direction);
+        weight = power_heuristic(brdf_pdf, light_pdf(light, ray.origin, D, distance) / f32(counts.light_count));
+    }
+    return LightHit(light.radiance * spot_falloff(light, -D) * weight, true);
+}
+
+// The first emitter whose cdf is above u, like EmitterList::pick.
+fn pick_emitter(u: f32) -> u32 {
+    var low = 0u;
+    var high = counts.emitter_count;
+    while (low < high) {
+        let middle = (low + high) / 2u;
+        if (emitters[middle].cdf <= u) {
+            low = middle + 1u;
+        } else {
+            high = middle;
+        }
+    }
+    return min(low, counts.emitter_count - 1u);
+}
+
+// A point spread uniformly over the emitter at the ray time and the surface normal there, like Emitter::sample_point.
+fn sample_emitter_point(emitter: Emitter, u: vec2<f32>) -> array<vec3<f32>, 2> {
+    switch (emitter.kind) {
+        case EMITTER_SPHERE: {
+            let z = 1.0 - 2.0 * u.x;
+            let r = sqrt(max(1.0 - z * z, 0.0));
+            let phi = 2.0 * PI * u.y;
+            let normal = vec3<f32>(r * cos(phi), r * sin(phi), z);
+            return array<vec3<f32>, 2>(mix(emitter.p0, emitter.p1, ray_time) + normal * emitter.radius, normal);
+        }
+        case EMITTER_PLANE: {
+            let point = emitter.p0 + emitter.p1 * (2.0 * u.x - 1.0) + emitter.p2 * (2.0 * u.y - 1.0);
+            return array<vec3<f32>, 2>(point, normalize(cross(emitter.p1, emitter.p2)));
+        }
+        default: {
+            let su = sqrt(u.x);
+            let b0 = 1.0 - su;
+            let b1 = u.y * su;
+            let point = emitter.p0 * b0 + emitter.p1 * b1 + emitter.p2 * (1.0 - b0 - b1);
+            return array<vec3<f32>, 2>(point, normalize(cross(emitter.p1 - emitter.p0, emitter.p2 - emitter.p0)));
+        }
+    }
+}
+
+// Solid angle density of light sampling reaching a point with `radiance`, like EmitterList::pdf. Picking by power and
+// then by area leaves the emitter's area out of it entirely.
+fn emitter_pdf(radiance: vec3<f32>, distance: f32, cos_light: f32) -> f32 {
+    if (counts.emitter_power <= 0.0 || cos_light <= 0.0) {
+        return 0.0;
+    }
+    return luminance(radiance) / counts.emitter_power * distance * distance / cos_light;
+}
+
+// Veach's power heuristic with an exponent of two, the weight of the strategy that had `pdf`
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if (a + b <= 0.0) {
+        return 0.0;
+    }
+    return a / (a + b);
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let dims = vec2<u32>(counts.width, counts.height);
@@ -67,8 +345,150 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     var rng_state = (pixel_coords.y * 1973u + pixel_coords.x) * 9277u + counts.frame_number * 26699u;
 
-    let ray = Ray(rays[idx].origin, rays[idx].direction);
-    let color = trace_path(ray, &rng_state);
+    ray_time = mix(camera.shutter_open, camera.shutter_close, random_float(&rng_state));
+    let frame = camera_frame(ray_time);
+
+    let offset = pixel_offset(pixel_coords);
+    let ndc = (vec2<f32>(pixel_coords) + 0.5 + offset) / vec2<f32>(f32(dims.x), f32(dims.y)) * 2.0 - 1.0;
+    let pinhole = pinhole_ray(vec2<f32>(ndc.x, -ndc.y), frame);
+    let weight = filter_weight(offset);
+    var color = vec3<f32>(0.0);
+    // w is zero outside the fisheye circle
+    if (pinhole.w > 0.0) {
+        color = finite_or_black(trace_path(lens_ray(pinhole.ray, frame, &rng_state), &rng_state));
+    }
+
+    // frame zero starts a new accumulation, which is how the CPU resets it without clearing the buffer
+    if (counts.frame_number == 0u) {
+        output_colors[idx] = vec4<f32>(color * weight, weight);
+    } else {
+        output_colors[idx] += vec4<f32>(color * weight, weight);
+    }
+}
+
+// The R2 sequence over the frames, shifted per pixel so neighbours don't share a pattern, spread over the filter.
+fn pixel_offset(pixel: vec2<u32>) -> vec2<f32> {
+    var seed = pixel.y * 7919u + pixel.x * 104729u + 1u;
+    let shift = vec2<f32>(random_float(&seed), random_float(&seed));
+    let r2 = fract(shift + f32(counts.frame_number) * vec2<f32>(0.7548776662, 0.5698402910));
+    return (r2 * 2.0 - 1.0) * camera.filter_radius;
+}
+
+// Offsets are spread uniformly over the footprint, so the filter shape only comes from this weight.
+fn filter_weight(offset: vec2<f32>) -> f32 {
+    let r = camera.filter_radius;
+    switch (camera.filter_kind) {
+        case FILTER_TENT: {
+            let w = max(vec2<f32>(0.0), 1.0 - abs(offset) / r);
+            return w.x * w.y;
+        }
+        case FILTER_GAUSSIAN: {
+            // sigma 0.5, shifted down so it reaches zero at the edge of the footprint
+            let edge = exp(-2.0 * r * r);
+            let w = max(vec2<f32>(0.0), exp(-2.0 * offset * offset) - edge);
+            return w.x * w.y;
+        }
+        case FILTER_BLACKMAN_HARRIS: {
+            let t = 2.0 * PI * (offset / r * 0.5 + 0.5);
+            let w = 0.35875 - 0.48829 * cos(t) + 0.14128 * cos(2.0 * t) - 0.01168 * cos(3.0 * t);
+            return w.x * w.y;
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
+
+struct CameraFrame {
+    origin: vec3<f32>,
+    right: vec3<f32>,
+    up: vec3<f32>,
+    forward: vec3<f32>,
+}
+
+// Where the camera is and how it's turned at `time`, the same as Camera::frame_at.
+fn camera_frame(time: f32) -> CameraFrame {
+    if (camera.moving == 0u) {
+        return CameraFrame(camera.origin, camera.right, camera.up, camera.forward);
+    }
+    let forward = normalize(mix(camera.forward, camera.end_forward, time));
+    let right = normalize(cross(forward, mix(camera.up, camera.end_up, time)));
+    return CameraFrame(mix(camera.origin, camera.end_origin, time), right, cross(right, forward), forward);
+}
+
+struct PinholeRay {
+    ray: Ray,
+    w: f32,
+}
+
+// The ray through a point in normalised device coordinates (y up) from the centre of the lens, like Camera::pinhole_ray.
+fn pinhole_ray(ndc: vec2<f32>, frame: CameraFrame) -> PinholeRay {
+    let p = ndc * vec2<f32>(camera.aspect, 1.0);
+    switch (camera.projection) {
+        case PROJECTION_ORTHOGRAPHIC: {
+            let offset = p * camera.ortho_height * 0.5;
+            return PinholeRay(Ray(frame.origin + frame.right * offset.x + frame.up * offset.y, frame.forward), 1.0);
+        }
+        case PROJECTION_FISHEYE: {
+            let theta = length(p) * camera.half_fov;
+            let phi = atan2(p.y, p.x);
+            let side = frame.right * cos(phi) + frame.up * sin(phi);
+            return PinholeRay(Ray(frame.origin, frame.forward * cos(theta) + side * sin(theta)), select(1.0, 0.0, length(p) > 1.0));
+        }
+        case PROJECTION_EQUIRECTANGULAR: {
+            let longitude = ndc.x * PI;
+            let latitude = ndc.y * PI * 0.5;
+            let direction = (frame.forward * cos(longitude) + frame.right * sin(longitude)) * cos(latitude) + frame.up * sin(latitude);
+            return PinholeRay(Ray(frame.origin, direction), 1.0);
+        }
+        default: {
+            let screen = p * camera.tan_half_fov;
+            return PinholeRay(Ray(frame.origin, normalize(frame.forward + frame.right * screen.x + frame.up * screen.y)), 1.0);
+        }
+    }
+}
+
+// Moves the ray's origin over the aperture and aims it back at its focus point, the same as Camera::lens_ray.
+fn lens_ray(pinhole: Ray, frame: CameraFrame, seed: ptr<function, u32>) -> Ray {
+    if (camera.aperture <= 0.0) {
+        return pinhole;
+    }
+
+    // the planar projections focus on a plane, the wide angle ones on a sphere around the camera
+    var focus_t = camera.focus_distance;
+    if (camera.projection == PROJECTION_PERSPECTIVE || camera.projection == PROJECTION_ORTHOGRAPHIC) {
+        focus_t = camera.focus_distance / dot(pinhole.direction, frame.forward);
+    }
+    let focus_point = pinhole.origin + pinhole.direction * focus_t;
+    let u = vec2<f32>(random_float(seed), random_float(seed));
+    let lens = sample_aperture(u, camera.blades, camera.blade_rotation) * camera.aperture;
+    let origin = pinhole.origin + frame.right * lens.x + frame.up * lens.y;
+    return Ray(origin, normalize(focus_point - origin));
+}
+
+// A uniform point on the unit disk, or on the regular polygon inscribed in it from three blades up.
+fn sample_aperture(u: vec2<f32>, blades: u32, rotation: f32) -> vec2<f32> {
+    if (blades < 3u) {
+        let r = sqrt(u.x);
+        let phi = 2.0 * PI * u.y;
+        return vec2<f32>(r * cos(phi), r * sin(phi));
+    }
 
-    output_colors[idx] = vec4<f32>(color, 1.0);
+    let scaled = u.x * f32(blades);
+    let sector = min(floor(scaled), f32(blades) - 1.0);
+    let v = scaled - sector;
+    let step = 2.0 * PI / f32(blades);
+    let a = rotation + sector * step;
+    let corner_a = vec2<f32>(cos(a), sin(a));
+    let corner_b = vec2<f32>(cos(a + step), sin(a + step));
+    return sqrt(v) * (corner_a * (1.0 - u.y) + corner_b * u.y);
+}
+
+// checks the exponent bits, comparisons against NaN may be optimised away
+fn finite_or_black(color: vec3<f32>) -> vec3<f32> {
+    let exponents = bitcast<vec3<u32>>(color) & vec3<u32>(0x7f800000u);
+    if (any(exponents == vec3<u32>(0x7f800000u))) {
+        return vec3<f32>(0.0);
+    }
+    return color;
 }
\ No newline at end of file
@@ -4,6 +4,19 @@ var screen_tex: texture_2d<f32>;
 @group(0) @binding(1)
 var screen_sampler: sampler;
 
+struct ScreenParams {
+    width: u32,
+    height: u32,
+    _pad: vec2<u32>,
+};
+
+// filter weighted sum of every sample with the sum of the weights in alpha
+@group(0) @binding(2)
+var<storage, read> accumulation: array<vec4<f32>>;
+
+@group(0) @binding(3)
+var<uniform> params: ScreenParams;
+
 struct VSOut {
     @builtin(position) pos: vec4<f32>,
     @location(0) uv: vec2<f32>,
@@ -29,8 +42,33 @@ fn vs_main(@builtin(vertex_index) idx: u32) -> VSOut {
     return out;
 }
 
+// same curve as Color::tonemap
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let c = max(x, vec3<f32>(0.0));
+    return (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+}
+
+// the surface is sRGB and encodes what we return, undo that so the sqrt gamma of Color::gamma_correct survives
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
 @fragment
 fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
-    let color = textureSample(screen_tex, screen_sampler, in.uv);
-    return color;
+    let overlay = textureSample(screen_tex, screen_sampler, in.uv);
+    if (overlay.a > 0.0) {
+        return overlay;
+    }
+
+    let pixel = vec2<u32>(in.pos.xy);
+    if (pixel.x >= params.width || pixel.y >= params.height) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let sum = accumulation[pixel.y * params.width + pixel.x];
+    let average = select(vec3<f32>(0.0), sum.rgb / sum.a, sum.a > 0.0);
+    let display = sqrt(clamp(aces(average), vec3<f32>(0.0), vec3<f32>(1.0)));
+    return vec4<f32>(srgb_to_linear(display), 1.0);
 }
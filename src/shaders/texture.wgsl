@@ -0,0 +1,66 @@
+const WRAP_REPEAT: u32 = 0u;
+const WRAP_CLAMP: u32 = 1u;
+const WRAP_MIRROR: u32 = 2u;
+
+fn wrap_coord(i: i32, size: i32, mode: u32) -> i32 {
+    if (mode == WRAP_CLAMP) {
+        return clamp(i, 0, size - 1);
+    }
+    if (mode == WRAP_MIRROR) {
+        let t = ((i % (size * 2)) + size * 2) % (size * 2);
+        return select(t, size * 2 - 1 - t, t >= size);
+    }
+    return ((i % size) + size) % size;
+}
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+fn texel(tex: Texture, x: i32, y: i32, srgb: bool) -> vec3<f32> {
+    let wx = u32(wrap_coord(x, i32(tex.width), tex.wrap));
+    let wy = u32(wrap_coord(y, i32(tex.height), tex.wrap));
+    let packed = textures[counts.texture_count * 4u + tex.offset + wy * tex.width + wx];
+    let color = unpack4x8unorm(packed).rgb;
+    if (srgb) {
+        return srgb_to_linear(color);
+    }
+    return color;
+}
+
+// Bilinear lookup with v pointing up, matches Texture::sample in texture.rs.
+fn sample_texture(index: i32, uv: vec2<f32>, srgb: bool) -> vec3<f32> {
+    let base = u32(index) * 4u;
+    let tex = Texture(textures[base], textures[base + 1u], textures[base + 2u], textures[base + 3u]);
+
+    let x = uv.x * f32(tex.width) - 0.5;
+    let y = (1.0 - uv.y) * f32(tex.height) - 0.5;
+    let x0 = floor(x);
+    let y0 = floor(y);
+    let fx = x - x0;
+    let fy = y - y0;
+    let ix = i32(x0);
+    let iy = i32(y0);
+
+    let top = mix(texel(tex, ix, iy, srgb), texel(tex, ix + 1, iy, srgb), fx);
+    let bottom = mix(texel(tex, ix, iy + 1, srgb), texel(tex, ix + 1, iy + 1, srgb), fx);
+    return mix(top, bottom, fy);
+}
+
+fn apply_textures(hit: HitInfo) -> HitInfo {
+    var result = hit;
+    if (hit.has_hit == 0u) {
+        return result;
+    }
+
+    if (hit.textures.albedo >= 0) {
+        result.albedo = vec4<f32>(hit.albedo.xyz * sample_texture(hit.textures.albedo, hit.uv, true), hit.albedo.w);
+    }
+    if (hit.textures.roughness >= 0) {
+        result.roughness = hit.roughness * sample_texture(hit.textures.roughness, hit.uv, false).r;
+    }
+    if (hit.textures.metallic >= 0) {
+        result.metallic = hit.metallic * sample_texture(hit.textures.metallic, hit.uv, false).r;
+    }
+    return result;
+}
@@ -1,3 +1,10 @@
+// Leaf index entries: primitive type in the top two bits, index into that type's buffer below.
+const PRIMITIVE_TRIANGLE: u32 = 0u;
+const PRIMITIVE_SPHERE: u32 = 1u;
+const PRIMITIVE_PLANE: u32 = 2u;
+const PRIMITIVE_SHIFT: u32 = 30u;
+const PRIMITIVE_INDEX_MASK: u32 = 0x3fffffffu;
+
 struct Ray {
     origin: vec3<f32>,
     direction: vec3<f32>,
@@ -10,6 +17,11 @@ struct Sphere {
     emission: f32,
     metallic: f32,
     roughness: f32,
+    transmission: f32,
+    ior: f32,
+    textures: TextureIds,
+    emission_color: vec3<f32>,
+    end_center: vec3<f32>,
 }
 
 struct Triangle {
@@ -20,6 +32,18 @@ struct Triangle {
     emission: f32,
     metallic: f32,
     roughness: f32,
+    transmission: f32,
+    ior: f32,
+    uv0: vec2<f32>,
+    uv1: vec2<f32>,
+    uv2: vec2<f32>,
+    _pad3: vec2<f32>,
+    textures: TextureIds,
+    // vertex normals, all equal to the face normal for flat triangles
+    n0: vec3<f32>,
+    n1: vec3<f32>,
+    n2: vec3<f32>,
+    emission_color: vec3<f32>,
 }
 
 struct Plane {
@@ -27,25 +51,107 @@ struct Plane {
     normal: vec4<f32>,
     width: f32,
     length: f32,
-    _pad2: vec2<f32>,
+    transmission: f32,
+    ior: f32,
     albedo: vec4<f32>,
     emission: f32,
     metallic: f32,
     roughness: f32,
     _pad3: f32,
+    textures: TextureIds,
+    emission_color: vec3<f32>,
+}
+
+struct Material {
+    albedo: vec3<f32>,
+    emission: f32,
+    emission_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    transmission: f32,
+    ior: f32,
+    _pad: f32,
+    textures: TextureIds,
+}
+
+// A placement of a bottom-level BVH, the TLAS leaves index into the instance buffer.
+struct Instance {
+    world_to_object: mat4x4<f32>,
+    material: Material,
+    blas_root: u32,
+    override_material: u32,
+    moving: u32,
+    _pad0: u32,
+    start: Pose,
+    end: Pose,
+}
+
+// Object to world split into its parts, rotation is a quaternion
+struct Pose {
+    translation: vec3<f32>,
+    rotation: vec4<f32>,
+    scale: vec3<f32>,
+}
+
+// Indices into the texture table, -1 when the material has no map.
+struct TextureIds {
+    albedo: i32,
+    roughness: i32,
+    metallic: i32,
+    _pad: i32,
+}
+
+// Header of one texture in the packed texture buffer, offset counts texels after the headers.
+struct Texture {
+    offset: u32,
+    width: u32,
+    height: u32,
+    wrap: u32,
 }
 
 struct HitInfo {
     has_hit: u32,
     t: f32,
-    _pad0: vec2<f32>,
+    transmission: f32,
+    ior: f32,
     pos: vec4<f32>,
     normal: vec4<f32>,
     albedo: vec4<f32>,
+    uv: vec2<f32>,
+    _pad0: vec2<f32>,
+    textures: TextureIds,
     emission: f32,
     metallic: f32,
     roughness: f32,
-    _pad1: f32,
+    // hits of moving instances, whose emissive triangles aren't in the emitter list
+    moving: u32,
+    emission_color: vec3<f32>,
+}
+
+struct Camera {
+    origin: vec3<f32>,
+    tan_half_fov: f32,
+    right: vec3<f32>,
+    aspect: f32,
+    up: vec3<f32>,
+    filter_radius: f32,
+    forward: vec3<f32>,
+    filter_kind: u32,
+    aperture: f32,
+    focus_distance: f32,
+    blades: u32,
+    blade_rotation: f32,
+    projection: u32,
+    half_fov: f32,
+    ortho_height: f32,
+    _pad: u32,
+    end_origin: vec3<f32>,
+    shutter_open: f32,
+    end_right: vec3<f32>,
+    shutter_close: f32,
+    end_up: vec3<f32>,
+    moving: u32,
+    end_forward: vec3<f32>,
 }
 
 struct Counts {
@@ -57,6 +163,66 @@ struct Counts {
     frame_number: u32,
     bvh_node_count: u32,
     bvh_index_count: u32,
+    max_bounces: u32,
+    texture_count: u32,
+    instance_count: u32,
+    tlas_root: u32,
+    emitter_count: u32,
+    emitter_power: f32,
+    light_count: u32,
+    _pad0: u32,
+}
+
+// p0 is the sphere's centre, the plane's centre or the first vertex. p1 and p2 are the sphere's end centre, the
+// plane's half axes or the other two vertices.
+struct Emitter {
+    p0: vec3<f32>,
+    kind: u32,
+    p1: vec3<f32>,
+    radius: f32,
+    p2: vec3<f32>,
+    cdf: f32,
+    radiance: vec3<f32>,
+}
+
+// direction is the spot axis, the sun's travel direction or the facing of rect and disk lights. u and v are the rect's
+// half axes, cos_outer doubles as the sun's angular radius. radiance is already scaled by the light's size.
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    radius: f32,
+    radiance: vec3<f32>,
+    cos_inner: f32,
+    u: vec3<f32>,
+    cos_outer: f32,
+    v: vec3<f32>,
+    visible: u32,
+}
+
+// color is the constant colour or the gradient's zenith. Maps keep width * height RGBE texels in environment_data,
+// followed by the row CDF and then every row's column CDF.
+struct Environment {
+    kind: u32,
+    width: u32,
+    height: u32,
+    intensity: f32,
+    rotation: f32,
+    sampled: u32,
+    _pad0: vec2<u32>,
+    color: vec3<f32>,
+    _pad1: f32,
+    horizon: vec3<f32>,
+    _pad2: f32,
+    ground: vec3<f32>,
+    _pad3: f32,
+    // Everything below only matters to the sky. perez holds the coefficients A to E for the luminance and the two
+    // chromaticities, zenith the values the distribution gets scaled by.
+    sun_direction: vec3<f32>,
+    sky_scale: f32,
+    perez: array<vec4<f32>, 5>,
+    zenith: vec3<f32>,
+    _pad4: f32,
 }
 
 struct BVHNode {
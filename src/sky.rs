@@ -0,0 +1,178 @@
+use std::f32::consts::PI;
+use glam::Vec3;
+use crate::color::Color;
+use crate::light::{Light, LightShape};
+
+// Preetham, Shirley and Smits 1999, "A Practical Analytic Model for Daylight". The sky's luminance and chromaticity
+// each follow a Perez distribution around the sun, scaled to the zenith values of the turbidity and sun angle.
+
+/// Radiance per kcd/m² of sky luminance, keeps a clear noon sky around one.
+const SKY_SCALE: f32 = 0.05;
+/// Illuminance of the sun above the atmosphere in klux, before SKY_SCALE.
+const SUN_ILLUMINANCE: f32 = 127.0;
+
+/// The daylight sky without the sun itself, which `sun_light` adds as a directional light.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: f32,
+    /// Scales the sun light, zero leaves it out.
+    pub sun_intensity: f32,
+    /// Angular diameter of the sun disk in radians.
+    pub sun_size: f32,
+    /// Perez coefficients A to E of the luminance and the two chromaticities.
+    perez: [[f32; 3]; 5],
+    /// Zenith luminance and chromaticities over the Perez function at the zenith, what the distribution is scaled by.
+    zenith: [f32; 3],
+    /// Fades the sky out while the sun sinks up to 6 degrees below the horizon.
+    twilight: f32,
+}
+
+impl Sky {
+    /// `sun_direction` points towards the sun. Turbidity runs from about 2 for a very clear sky to 10 for haze.
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let sun_direction = sun_direction.normalize();
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608],
+            [-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092],
+            [-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102],
+            [0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537],
+            [-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529],
+        ];
+
+        // the model only covers suns above the horizon
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (t2, s, s2, s3) = (t * t, theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t2 * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+        let zenith_y = t2 * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+
+        let mut zenith = [zenith_luminance.max(0.0), zenith_x, zenith_y];
+        for (channel, value) in zenith.iter_mut().enumerate() {
+            *value /= perez_function(&perez, channel, 1.0, theta_s);
+        }
+
+        let elevation = sun_direction.y.clamp(-1.0, 1.0).asin();
+        let twilight = (1.0 + elevation / 6f32.to_radians()).clamp(0.0, 1.0);
+        Self { sun_direction, turbidity, sun_intensity: 1.0, sun_size: 0.53f32.to_radians(), perez, zenith, twilight }
+    }
+
+    /// Elevation above the horizon and azimuth clockwise from north, both in radians. North is -Z and east is +X.
+    pub fn from_angles(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        Self::new(sun_from_angles(elevation, azimuth), turbidity)
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f32 {
+        self.turbidity
+    }
+
+    /// Radiance of the sky arriving from `direction`, the same as `sky_radiance` in environment.wgsl. Directions below
+    /// the horizon see the sky just above it.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let direction = direction.normalize();
+        let cos_theta = direction.y.max(1.0e-3);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let [luminance, x, y] = std::array::from_fn(|channel| {
+            self.zenith[channel] * perez_function(&self.perez, channel, cos_theta, gamma)
+        });
+        xyy_to_rgb(x, y, luminance) * (SKY_SCALE * self.twilight)
+    }
+
+    /// The sun disk as a directional light, reddened by the air it shines through. `None` once it has set.
+    pub fn sun_light(&self, intensity: f32) -> Option<Light> {
+        let elevation = self.sun_direction.y.clamp(-1.0, 1.0).asin();
+        if elevation <= 0.0 || self.sun_intensity <= 0.0 {
+            return None;
+        }
+
+        // Kasten and Young's relative air mass, Rayleigh and Angstrom optical depths at 650, 550 and 450 nm
+        let zenith_degrees = 90.0 - elevation.to_degrees();
+        let air_mass = 1.0 / (elevation.sin() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |wavelength: f32| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        };
+        let color = Color::new(transmittance(0.65), transmittance(0.55), transmittance(0.45));
+
+        let shape = LightShape::Directional { direction: -self.sun_direction, cos_radius: (self.sun_size * 0.5).cos() };
+        Some(Light::new(shape, color, SUN_ILLUMINANCE * SKY_SCALE * self.sun_intensity * intensity))
+    }
+
+    /// Perez coefficients, zenith values, sun direction and twilight fade packed for `GpuEnvironment`.
+    pub fn gpu_coefficients(&self) -> ([[f32; 3]; 5], [f32; 3], [f32; 3], f32) {
+        (self.perez, self.zenith, self.sun_direction.to_array(), SKY_SCALE * self.twilight)
+    }
+}
+
+/// Direction towards a sun at `elevation` above the horizon and `azimuth` clockwise from north (-Z), in radians.
+pub fn sun_from_angles(elevation: f32, azimuth: f32) -> Vec3 {
+    Vec3::new(azimuth.sin() * elevation.cos(), elevation.sin(), -azimuth.cos() * elevation.cos())
+}
+
+/// Elevation and azimuth of a direction towards the sun, the inverse of `sun_from_angles`.
+pub fn sun_angles(direction: Vec3) -> (f32, f32) {
+    let direction = direction.normalize();
+    (direction.y.clamp(-1.0, 1.0).asin(), direction.x.atan2(-direction.z).rem_euclid(2.0 * PI))
+}
+
+/// Elevation and azimuth of the sun in radians, seen from `latitude` and `longitude` in degrees (north and east
+/// positive) on `day_of_year` (1 for January 1st) at `hours_utc`. NOAA's approximate solar position equations, good to
+/// a fraction of a degree.
+pub fn sun_position(latitude: f32, longitude: f32, day_of_year: u32, hours_utc: f32) -> (f32, f32) {
+    let year_angle = 2.0 * PI / 365.0 * (day_of_year as f32 - 1.0 + (hours_utc - 12.0) / 24.0);
+    let equation_of_time = 229.18 * (0.000075 + 0.001868 * year_angle.cos() - 0.032077 * year_angle.sin()
+        - 0.014615 * (2.0 * year_angle).cos() - 0.040849 * (2.0 * year_angle).sin());
+    let declination = 0.006918 - 0.399912 * year_angle.cos() + 0.070257 * year_angle.sin()
+        - 0.006758 * (2.0 * year_angle).cos() + 0.000907 * (2.0 * year_angle).sin()
+        - 0.002697 * (3.0 * year_angle).cos() + 0.00148 * (3.0 * year_angle).sin();
+
+    // minutes of true solar time, four per degree of longitude
+    let solar_minutes = hours_utc * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+    let latitude = latitude.to_radians();
+
+    let elevation = (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0).asin();
+    let azimuth = (-hour_angle.sin()).atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+    (elevation, azimuth.rem_euclid(2.0 * PI))
+}
+
+/// Day of the year for a calendar date, 1 for January 1st.
+pub fn day_of_year(year: u32, month: u32, day: u32) -> u32 {
+    const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+    DAYS_BEFORE_MONTH[(month.clamp(1, 12) - 1) as usize] + day + (leap && month > 2) as u32
+}
+
+fn perez_function(perez: &[[f32; 3]; 5], channel: usize, cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = perez.map(|coefficients| coefficients[channel]);
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Color {
+    if y <= 0.0 {
+        return Color::black();
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    Color::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    ).clamp(0.0, f32::MAX)
+}
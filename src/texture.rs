@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::BufReader;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use crate::color::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    pub fn as_u32(self) -> u32 {
+        match self {
+            WrapMode::Repeat => 0,
+            WrapMode::Clamp => 1,
+            WrapMode::Mirror => 2,
+        }
+    }
+
+    fn apply(self, i: i32, size: i32) -> i32 {
+        match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let t = i.rem_euclid(size * 2);
+                if t >= size { size * 2 - 1 - t } else { t }
+            }
+        }
+    }
+}
+
+/// An 8-bit RGBA image kept in its stored encoding; albedo lookups decode sRGB, scalar maps read the red channel as is.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub name: String,
+    pub source: Option<String>,
+    pub wrap: WrapMode,
+    width: u32,
+    height: u32,
+    texels: Vec<[u8; 4]>,
+}
+
+impl Texture {
+    pub fn new(name: &str, width: u32, height: u32, texels: Vec<[u8; 4]>, wrap: WrapMode) -> Self {
+        assert_eq!(texels.len(), (width * height) as usize, "texture size does not match its texel count");
+        Self { name: name.to_string(), source: None, wrap, width, height, texels }
+    }
+
+    pub fn load_png(name: &str, path: &str, wrap: WrapMode) -> std::io::Result<Self> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(std::io::Error::other)?;
+
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader.next_frame(&mut buffer).map_err(std::io::Error::other)?;
+        let bytes = &buffer[..info.buffer_size()];
+
+        let texels = match info.color_type {
+            png::ColorType::Rgba => bytes.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+            png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Grayscale => bytes.iter().map(|&g| [g, g, g, 255]).collect(),
+            png::ColorType::Indexed => return Err(std::io::Error::other("indexed PNG was not expanded")),
+        };
+
+        let mut texture = Self::new(name, info.width, info.height, texels, wrap);
+        texture.source = Some(path.to_string());
+        Ok(texture)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn texels(&self) -> &[[u8; 4]] {
+        &self.texels
+    }
+
+    /// Bilinear lookup with v pointing up, like OBJ texture coordinates. Matches `sample_texture` in texture.wgsl.
+    pub fn sample(&self, uv: Vec2, srgb: bool) -> Color {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.texel(x0, y0, srgb) * (1.0 - fx) + self.texel(x0 + 1, y0, srgb) * fx;
+        let bottom = self.texel(x0, y0 + 1, srgb) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1, srgb) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn texel(&self, x: i32, y: i32, srgb: bool) -> Color {
+        let x = self.wrap.apply(x, self.width as i32);
+        let y = self.wrap.apply(y, self.height as i32);
+        let [r, g, b, _] = self.texels[(y as u32 * self.width + x as u32) as usize];
+
+        let decode = |c: u8| {
+            let c = c as f32 / 255.0;
+            if srgb { srgb_to_linear(c) } else { c }
+        };
+        Color::new(decode(r), decode(g), decode(b))
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
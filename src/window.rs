@@ -1,37 +1,74 @@
 use crate::color::Color;
-use glfw::{fail_on_errors, Action, Context, CursorMode, Glfw, GlfwReceiver, Key, PWindow, WindowEvent};
+use glfw::{fail_on_errors, Action, Context, CursorMode, Glfw, GlfwReceiver, Key, MouseButton, PWindow, WindowEvent};
 use wgpu::TextureUsages;
 use crate::camera::Camera;
-
-#[allow(dead_code)]
-pub struct Canvas {
+use crate::compute::GpuScene;
+use crate::gpu_types::GpuColor;
+use crate::output;
+use bytemuck::{Pod, Zeroable};
+use wgpu::PollType;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What screen.wgsl needs to index the accumulation buffer.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct ScreenParams {
     width: u32,
     height: u32,
+    _pad: [u32; 2],
+}
+
+#[allow(dead_code)]
+struct Display {
     window: PWindow,
     events: GlfwReceiver<(f64, WindowEvent)>,
     glfw: Glfw,
     surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     sampler: wgpu::Sampler,
-    pixel_buffer: Vec<u32>,
     pixel_texture: wgpu::Texture,
+    params_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
     bind_group_layout: wgpu::BindGroupLayout,
     render_pipeline: wgpu::RenderPipeline,
+}
+
+#[allow(dead_code)]
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    display: Option<Display>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Overlay drawn over the image, a zero pixel is transparent. Only re-uploaded after it was painted on.
+    pixel_buffer: Vec<u32>,
+    overlay_dirty: bool,
+    /// Nothing was painted since the overlay was last cleared.
+    overlay_empty: bool,
+    /// Only used by the CPU renderer, which uploads it to `accumulation_buffer` after every sample.
     pub(crate) accum_buffer: Vec<Color>,
+    /// The filter weights summed into `accum_buffer` per pixel.
+    pub(crate) accum_weights: Vec<f32>,
+    /// Per pixel `vec4<f32>` of the filter weighted colour sum and the weight sum, written by the compute shader and
+    /// averaged in screen.wgsl.
+    pub accumulation_buffer: wgpu::Buffer,
     pub(crate) sample_count: u32,
+    clicked: bool,
     pub compute_pipeline: Option<wgpu::ComputePipeline>,
     pub compute_bind_group: Option<wgpu::BindGroup>,
+    pub compute_bind_group_layout: Option<wgpu::BindGroupLayout>,
     pub sphere_buffer: Option<wgpu::Buffer>,
     pub triangle_buffer: Option<wgpu::Buffer>,
     pub plane_buffer: Option<wgpu::Buffer>,
-    pub ray_buffer: Option<wgpu::Buffer>,
+    pub camera_buffer: Option<wgpu::Buffer>,
     pub hit_buffer: Option<wgpu::Buffer>,
-    pub color_buffer: Option<wgpu::Buffer>,
-    pub staging_buffer: Option<wgpu::Buffer>,
     pub counts_buffer: Option<wgpu::Buffer>,
+    pub bvh_node_buffer: Option<wgpu::Buffer>,
+    pub bvh_index_buffer: Option<wgpu::Buffer>,
+    pub instance_buffer: Option<wgpu::Buffer>,
+    pub emitter_buffer: Option<wgpu::Buffer>,
+    pub light_buffer: Option<wgpu::Buffer>,
+    pub gpu_scene: Option<GpuScene>,
 }
 
 impl Canvas {
@@ -46,9 +83,6 @@ impl Canvas {
         window.make_current();
         window.set_cursor_mode(CursorMode::Disabled);
 
-        let accum_buffer = vec![Color::black(); (width * height) as usize];
-        let sample_count = 0;
-
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             ..Default::default()
@@ -68,17 +102,7 @@ impl Canvas {
             .await
             .expect("Failed to find an adapter");
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::default(),
-                    experimental_features: Default::default(),
-                    memory_hints: wgpu::MemoryHints::default(),
-                    trace: Default::default(),
-                },
-            ).await.expect("Failed to create device");
+        let (device, queue) = request_device(&adapter).await;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -101,23 +125,7 @@ impl Canvas {
 
         surface.configure(&device, &config);
 
-        let pixel_buffer = vec![0u32; (width * height) as usize];
-
-        let pixel_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Pixel Texture"),
-            size: wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-
+        let pixel_texture = create_pixel_texture(&device, width, height);
         let texture_view = pixel_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -146,24 +154,40 @@ impl Canvas {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Screen Bind Group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
+        let accumulation_buffer = create_accumulation_buffer(&device, width, height);
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screen Params Buffer"),
+            size: std::mem::size_of::<ScreenParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
+        let bind_group = create_screen_bind_group(&device, &bind_group_layout, &texture_view, &sampler,
+                                                  &accumulation_buffer, &params_buffer);
+
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Screen Shader"),
             source: wgpu::ShaderSource::Wgsl(
@@ -205,10 +229,57 @@ impl Canvas {
                 cache: None,
             });
 
-        Self { width, height, window, events, glfw, surface, device, queue, config, pixel_buffer,
-            pixel_texture, bind_group, bind_group_layout, render_pipeline, sampler, accum_buffer, sample_count,
-            compute_pipeline: None, compute_bind_group: None, sphere_buffer: None, triangle_buffer: None,
-            plane_buffer: None, ray_buffer: None, hit_buffer: None, color_buffer: None, staging_buffer: None, counts_buffer: None, }
+        let display = Display { window, events, glfw, surface, config, sampler, pixel_texture, params_buffer, bind_group,
+            bind_group_layout, render_pipeline };
+
+        Self::with_device(width, height, Some(display), device, queue, accumulation_buffer)
+    }
+
+    /// Creates a canvas without a window or surface, for offline rendering.
+    /// Prefers a real adapter and falls back to a software one (llvmpipe, WARP, ...) when none is available.
+    pub async fn new_headless(width: u32, height: u32) -> Self {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let adapter = match instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await
+        {
+            Ok(adapter) => adapter,
+            Err(_) => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter: true,
+                    compatible_surface: None,
+                })
+                .await
+                .expect("Failed to find an adapter, not even a fallback one"),
+        };
+
+        println!("Headless adapter: {}", adapter.get_info().name);
+
+        let (device, queue) = request_device(&adapter).await;
+        let accumulation_buffer = create_accumulation_buffer(&device, width, height);
+
+        Self::with_device(width, height, None, device, queue, accumulation_buffer)
+    }
+
+    fn with_device(width: u32, height: u32, display: Option<Display>, device: wgpu::Device, queue: wgpu::Queue,
+                   accumulation_buffer: wgpu::Buffer) -> Self {
+        let pixel_buffer = vec![0u32; (width * height) as usize];
+        let sample_count = 0;
+
+        Self { width, height, display, device, queue, pixel_buffer, overlay_dirty: true, overlay_empty: true,
+            accum_buffer: Vec::new(), accum_weights: Vec::new(), accumulation_buffer, sample_count, clicked: false,
+            compute_pipeline: None, compute_bind_group: None, compute_bind_group_layout: None, sphere_buffer: None, triangle_buffer: None,
+            plane_buffer: None, camera_buffer: None, hit_buffer: None, counts_buffer: None,
+            bvh_node_buffer: None, bvh_index_buffer: None, instance_buffer: None, emitter_buffer: None, light_buffer: None, gpu_scene: None, }
     }
 
     fn resize(&mut self, width: u32, height: u32) {
@@ -219,83 +290,70 @@ impl Canvas {
         self.width = width;
         self.height = height;
 
-        self.config.width = width;
-        self.config.height = height;
-        self.surface.configure(&self.device, &self.config);
-
-        self.pixel_buffer = vec![0u32; (width * height) as usize];
-        self.accum_buffer = vec![Color::black(); (width * height) as usize];
-        self.sample_count = 0;
+        self.accumulation_buffer = create_accumulation_buffer(&self.device, width, height);
 
-        self.pixel_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Pixel Texture"),
-            size: wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        if let Some(display) = self.display.as_mut() {
+            display.config.width = width;
+            display.config.height = height;
+            display.surface.configure(&self.device, &display.config);
 
-        let texture_view = self.pixel_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            display.pixel_texture = create_pixel_texture(&self.device, width, height);
+            let texture_view = display.pixel_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            display.bind_group = create_screen_bind_group(&self.device, &display.bind_group_layout, &texture_view, &display.sampler,
+                                                          &self.accumulation_buffer, &display.params_buffer);
+        }
 
-        self.bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Screen Bind Group"),
-            layout: &self.bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.sampler),
-                },
-            ],
-        });
+        self.pixel_buffer = vec![0u32; (width * height) as usize];
+        self.overlay_dirty = true;
+        self.overlay_empty = true;
+        self.accum_buffer.clear();
+        self.accum_weights.clear();
 
         self.reset_accumulation();
         self.compute_pipeline = None;
         self.compute_bind_group = None;
-        self.ray_buffer = None;
-        self.color_buffer = None;
-        self.staging_buffer = None;
+        self.camera_buffer = None;
         self.counts_buffer = None;
     }
 
     fn render(&mut self, clear_color: wgpu::Color) -> Result<(), wgpu::SurfaceError> {
-        let rgba_data: Vec<u8> = self.pixel_buffer
-            .iter()
-            .flat_map(|&color| {
-                [
-                    ((color >> 16) & 0xFF) as u8,
-                    ((color >> 8) & 0xFF) as u8,
-                    (color & 0xFF) as u8,
-                    ((color >> 24) & 0xFF) as u8,
-                ]
-            }).collect();
-
-        self.queue.write_texture(
-            self.pixel_texture.as_image_copy(),
-            &rgba_data,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * self.width),
-                rows_per_image: Some(self.height),
-            },
-            wgpu::Extent3d {
-                width: self.width,
-                height: self.height,
-                depth_or_array_layers: 1,
-            },
-        );
+        let Some(display) = self.display.as_ref() else {
+            return Ok(());
+        };
 
-        let output = self.surface.get_current_texture()?;
+        if self.overlay_dirty {
+            let rgba_data: Vec<u8> = self.pixel_buffer
+                .iter()
+                .flat_map(|&color| {
+                    [
+                        ((color >> 16) & 0xFF) as u8,
+                        ((color >> 8) & 0xFF) as u8,
+                        (color & 0xFF) as u8,
+                        if color == 0 { 0 } else { 0xFF },
+                    ]
+                }).collect();
+
+            self.queue.write_texture(
+                display.pixel_texture.as_image_copy(),
+                &rgba_data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * self.width),
+                    rows_per_image: Some(self.height),
+                },
+                wgpu::Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            self.overlay_dirty = false;
+        }
+
+        let params = ScreenParams { width: self.width, height: self.height, _pad: [0; 2] };
+        self.queue.write_buffer(&display.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let output = display.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Render Encoder"), });
@@ -318,8 +376,8 @@ impl Canvas {
                 multiview_mask: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_pipeline(&display.render_pipeline);
+            render_pass.set_bind_group(0, &display.bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         }
 
@@ -332,7 +390,15 @@ impl Canvas {
     fn handle_event(&mut self, event: WindowEvent) {
         match event {
             WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
-                self.window.set_should_close(true);
+                if let Some(display) = self.display.as_mut() {
+                    display.window.set_should_close(true);
+                }
+            }
+            WindowEvent::Key(Key::F12, _, Action::Press, _) => {
+                self.save_screenshot();
+            }
+            WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => {
+                self.clicked = true;
             }
             WindowEvent::Size(width, height) => {
                 self.resize(width as u32, height as u32);
@@ -341,10 +407,22 @@ impl Canvas {
         }
     }
 
+    fn save_screenshot(&self) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = format!("screenshot_{}.png", timestamp);
+
+        match output::write_image(&path, self) {
+            Ok(()) => println!("Saved screenshot to {} ({} samples)", path, self.sample_count),
+            Err(err) => eprintln!("Failed to save screenshot {}: {}", path, err),
+        }
+    }
+
     pub fn paint_pixel(&mut self, x: u32, y: u32, color: u32) {
         if x < self.width && y < self.height {
             let index = (y * self.width + x) as usize;
             self.pixel_buffer[index] = color;
+            self.overlay_dirty = true;
+            self.overlay_empty &= color == 0;
         }
     }
 
@@ -382,8 +460,13 @@ impl Canvas {
     }
 
     pub fn update(&mut self) {
-        self.glfw.poll_events();
-        let events: Vec<_> = glfw::flush_messages(&self.events).collect();
+        let events: Vec<_> = match self.display.as_mut() {
+            Some(display) => {
+                display.glfw.poll_events();
+                glfw::flush_messages(&display.events).collect()
+            }
+            None => return,
+        };
         for (_, event) in events {
             self.handle_event(event);
         }
@@ -399,21 +482,70 @@ impl Canvas {
         })
     }
 
+    /// Starts accumulating afresh and clears the overlay, which was drawn for the old view.
     pub fn reset_accumulation(&mut self) {
         self.accum_buffer.fill(Color::black());
+        self.accum_weights.fill(0.0);
         self.sample_count = 0;
+        if !self.overlay_empty {
+            self.pixel_buffer.fill(0);
+            self.overlay_dirty = true;
+            self.overlay_empty = true;
+        }
+    }
+
+    /// The accumulated samples divided by their filter weights, in linear space.
+    /// Reads the accumulation back from the GPU and waits for it, so only call it for screenshots and exports.
+    pub fn averaged_accumulation(&self) -> Vec<Color> {
+        let size = self.accumulation_buffer.size();
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Readback Encoder"), });
+        encoder.copy_buffer_to_buffer(&self.accumulation_buffer, 0, &staging_buffer, 0, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = staging_buffer.slice(..);
+        let (tx, rx) = futures::channel::oneshot::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        self.device.poll(PollType::Wait { submission_index: None, timeout: None })
+            .expect("GPU was NOT polled");
+        pollster::block_on(rx).unwrap().unwrap();
+
+        let data = buffer_slice.get_mapped_range();
+        let colors: &[GpuColor] = bytemuck::cast_slice(&data);
+        colors.iter().map(|color| {
+            if color.weight > 0.0 { Color::new(color.r, color.g, color.b) / color.weight } else { Color::black() }
+        }).collect()
+    }
+
+    /// Replaces the GPU accumulation with `sums`, for renderers that accumulate on the CPU.
+    /// `weights` is what each pixel's sum gets divided by, the filter weights of its samples added up.
+    pub fn write_accumulation(&self, sums: &[Color], weights: &[f32]) {
+        let colors: Vec<GpuColor> = sums.iter().zip(weights)
+            .map(|(color, &weight)| GpuColor { r: color.r, g: color.g, b: color.b, weight })
+            .collect();
+        self.queue.write_buffer(&self.accumulation_buffer, 0, bytemuck::cast_slice(&colors));
     }
 
     pub fn pixel_count(&self) -> u32 {
         self.width * self.height
     }
-    
+
     pub fn is_open(&self) -> bool {
-        !self.window.should_close()
+        self.display.as_ref().is_some_and(|display| !display.window.should_close())
     }
 
     pub fn set_window_title(&mut self, title: &str) {
-        self.window.set_title(title);
+        if let Some(display) = self.display.as_mut() {
+            display.window.set_title(title);
+        }
     }
 
     pub fn width(&self) -> u32 {
@@ -425,11 +557,16 @@ impl Canvas {
     }
 
     pub fn is_key_down(&self, key: Key) -> bool {
-        self.window.get_key(key) == Action::Press
+        self.display.as_ref().is_some_and(|display| display.window.get_key(key) == Action::Press)
+    }
+
+    /// Whether the left mouse button was pressed since the last call.
+    pub fn take_click(&mut self) -> bool {
+        std::mem::take(&mut self.clicked)
     }
 
     pub fn get_mouse_pos(&self) -> (f64, f64) {
-        self.window.get_cursor_pos()
+        self.display.as_ref().map_or((0.0, 0.0), |display| display.window.get_cursor_pos())
     }
 
     pub fn device(&self) -> &wgpu::Device {
@@ -439,4 +576,77 @@ impl Canvas {
     pub fn queue(&self) -> &wgpu::Queue {
         &self.queue
     }
-}
\ No newline at end of file
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                required_features: wgpu::Features::empty(),
+                // more storage buffers than the default eight, one per kind of scene data, and as large ones as the
+                // adapter allows for big environment maps
+                required_limits: wgpu::Limits {
+                    max_storage_buffers_per_shader_stage: adapter.limits().max_storage_buffers_per_shader_stage.min(16),
+                    max_storage_buffer_binding_size: adapter.limits().max_storage_buffer_binding_size,
+                    max_buffer_size: adapter.limits().max_buffer_size,
+                    ..wgpu::Limits::default()
+                },
+                experimental_features: Default::default(),
+                memory_hints: wgpu::MemoryHints::default(),
+                trace: Default::default(),
+            },
+        ).await.expect("Failed to create device")
+}
+
+fn create_pixel_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Pixel Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn create_accumulation_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Accumulation Buffer"),
+        size: (width * height) as u64 * std::mem::size_of::<GpuColor>() as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_screen_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView,
+                            sampler: &wgpu::Sampler, accumulation: &wgpu::Buffer, params: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Screen Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: accumulation.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: params.as_entire_binding(),
+            },
+        ],
+    })
+}